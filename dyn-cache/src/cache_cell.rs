@@ -1,55 +1,202 @@
-use super::dep_node::{DepNode, Dependent};
+use super::{
+    dep_node::{DepNode, Dependent},
+    EntryInfo, Retention,
+};
 use std::{
     any::type_name,
     borrow::Borrow,
+    cell::Cell,
     fmt::{Debug, Formatter, Result as FmtResult},
+    mem::size_of,
 };
 
+/// When a cell was read or stored.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct Rooted {
+    /// The cache's root count.
+    pub count: u64,
+    /// The cache's generation.
+    pub generation: u64,
+}
+
+/// Bookkeeping for a freshly stored input/output pair.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Stored {
+    /// The cache's store count.
+    pub at: u64,
+    pub rooted_at: Rooted,
+    pub retention: Retention,
+    /// The number of bytes accounted to the input and output.
+    pub size: usize,
+}
+
 /// A CacheCell represents the storage used for a particular input/output pair
 /// on the heap.
-#[derive(Clone, Default, Hash, Eq, PartialEq)]
+#[derive(Clone, Eq, PartialEq)]
 pub(crate) struct CacheCell<Input, Output> {
     dep: DepNode,
     input: Input,
     output: Output,
+    /// The most recent generation at the end of which this cell was live.
+    last_live: u64,
+    /// The cache's store count when this cell's output was stored.
+    stored_at: u64,
+    /// How long this cell is kept after it was last live.
+    retention: Retention,
+    /// The number of bytes accounted to this cell's input and output.
+    size: usize,
+    /// When this cell was last read or stored.
+    last_rooted: Cell<Rooted>,
 }
 
 impl<Input, Output> CacheCell<Input, Output> {
-    pub fn new(input: Input, output: Output, dep: DepNode) -> Self {
-        Self { dep, input, output }
+    pub fn new(input: Input, output: Output, dep: DepNode, stored: Stored) -> Self {
+        Self {
+            dep,
+            input,
+            output,
+            last_live: 0,
+            stored_at: stored.at,
+            retention: stored.retention,
+            size: stored.size,
+            last_rooted: Cell::new(stored.rooted_at),
+        }
     }
 
     /// Return a reference to the output if the input is equal, marking it live
     /// in the process. If get fails, returns its own `Dependent` to be used as
     /// a dependency of any queries which are invoked to re-initialize this
     /// cell.
-    pub fn get<Arg>(&self, input: &Arg, dependent: Dependent) -> Result<&Output, Dependent>
+    pub fn get<Arg>(
+        &self,
+        input: &Arg,
+        dependent: Dependent,
+        now: Rooted,
+    ) -> Result<&Output, Dependent>
     where
         Arg: PartialEq<Input> + ?Sized,
         Input: Borrow<Arg>,
     {
-        self.dep.root(dependent);
+        self.root(dependent, now);
         if input == &self.input { Ok(&self.output) } else { Err(self.dep.as_dependent()) }
     }
 
-    /// Store a new input/output and mark the storage live.
-    pub fn store(&mut self, input: Input, output: Output, dependent: Dependent) {
+    /// Returns true if `input` equals this cell's input.
+    pub fn has_input<Arg>(&self, input: &Arg) -> bool
+    where
+        Arg: PartialEq<Input> + ?Sized,
+        Input: Borrow<Arg>,
+    {
+        input == &self.input
+    }
+
+    /// Return a reference to the output without comparing inputs, marking it
+    /// live in the process.
+    pub fn read(&self, dependent: Dependent, now: Rooted) -> &Output {
+        self.root(dependent, now);
+        &self.output
+    }
+
+    fn root(&self, dependent: Dependent, now: Rooted) {
         self.dep.root(dependent);
+        self.last_rooted.set(now);
+    }
+
+    /// Store a new input/output and mark the storage live.
+    pub fn store(&mut self, input: Input, output: Output, dependent: Dependent, stored: Stored) {
+        self.root(dependent, stored.rooted_at);
         self.input = input;
         self.output = output;
+        self.stored_at = stored.at;
+        self.retention = stored.retention;
+        self.size = stored.size;
+    }
+
+    /// Returns this cell's input, output, and size for a snapshot.
+    #[cfg(feature = "persist")]
+    pub fn saved(&self) -> (&Input, &Output, usize) {
+        (&self.input, &self.output, self.size)
+    }
+
+    /// Returns the number of bytes accounted to this cell.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns the cache's root count when this cell was last read or stored.
+    pub fn last_rooted(&self) -> u64 {
+        self.last_rooted.get().count
+    }
+
+    /// Track this cell's liveness with `dep` instead of its current node.
+    pub fn replace_dep(&mut self, dep: DepNode) {
+        self.dep = dep;
+    }
+
+    /// Returns true if this cell's output was stored at or after `checkpoint`.
+    pub fn stored_since(&self, checkpoint: u64) -> bool {
+        self.stored_at >= checkpoint
     }
 
     pub fn is_live(&self) -> bool {
-        self.dep.is_known_live()
+        self.dep.is_live()
+    }
+
+    pub fn is_retained(&self) -> bool {
+        self.dep.is_retained()
     }
 
-    pub fn update_liveness(&mut self) {
-        self.dep.update_liveness();
+    /// Mark this cell as retained if it wasn't live in `generation` and its
+    /// retention policy still keeps it.
+    pub fn retain(&mut self, generation: u64) {
+        if self.retention.keeps(generation.saturating_sub(self.last_live)) {
+            self.dep.retain();
+        }
+    }
+
+    /// Returns this cell's age if it's kept by [`Retention::Lru`] and wasn't
+    /// live in `generation`.
+    pub fn unused_lru(&self, generation: u64) -> Option<(u64, u64)> {
+        if self.retention == Retention::Lru && self.last_live < generation {
+            Some((self.last_live, self.stored_at))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the cell's node in the dependency graph.
+    pub fn dep(&self) -> &DepNode {
+        &self.dep
     }
 
     pub fn mark_dead(&mut self) {
         self.dep.mark_dead();
     }
+
+    /// Record that this cell was live at the end of `generation`.
+    pub fn survive(&mut self, generation: u64) {
+        self.last_live = generation;
+    }
+
+    /// Describe this cell for inspection, given the current `generation`.
+    pub fn info<'a, Scope: 'static>(&self, scope: &'a Scope, generation: u64) -> EntryInfo<'a>
+    where
+        Input: 'static,
+        Output: 'static,
+    {
+        let is_live = self.is_live();
+        EntryInfo {
+            scope,
+            scope_type: type_name::<Scope>(),
+            input_type: type_name::<Input>(),
+            output_type: type_name::<Output>(),
+            is_live,
+            generation: if is_live { generation } else { self.last_live },
+            rooted_generation: self.last_rooted.get().generation,
+            shallow_size: size_of::<Input>() + size_of::<Output>(),
+            size: self.size,
+        }
+    }
 }
 
 impl<Input, Output> Debug for CacheCell<Input, Output>
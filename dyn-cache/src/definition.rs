@@ -59,11 +59,27 @@ paste::item! {
         $shared:ident,
         $refct:ident,
         $lock:ident :: $acquire:ident
+        $(, $(#[$field_meta:meta])* $field:ident: $field_ty:ty)*
     ) => {
-use crate::{dep_node::Dependent, *};
+use crate::{
+    cache_cell::{Rooted, Stored},
+    dep_node::{propagate_liveness, Dependent},
+    *,
+};
 use hash_hasher::HashBuildHasher;
-use hashbrown::HashMap;
-use std::{any::TypeId, borrow::Borrow, cmp::{Eq, Ordering}, hash::{Hash, Hasher}};
+use hashbrown::{hash_map::RawEntryMut, HashMap};
+use std::{
+    any::TypeId,
+    borrow::Borrow,
+    cell::Cell,
+    cmp::{Eq, Ordering},
+    hash::{Hash, Hasher},
+    mem::size_of,
+};
+#[cfg(feature = "persist")]
+use serde::{de::DeserializeOwned, Serialize};
+#[cfg(feature = "persist")]
+use std::{collections::BTreeMap, fmt::{Debug, Formatter, Result as FmtResult}};
 
 doc_comment! {"
 Holds arbitrary query results which are namespaced by arbitrary scope types. Usually used
@@ -80,7 +96,9 @@ The query scope is the value which indexes the storage for a particular query ty
 bound `Scope: 'static + Eq + Hash" $(" + " stringify!($bound))? "`.
 
 Each `Scope` corresponds to at most a single `Input: 'static" $(" + " stringify!($bound))? "`
-and a single `Output: 'static" $(" + " stringify!($bound))? "` value at any given time.
+and a single `Output: 'static" $(" + " stringify!($bound))? "` value at any given time, unless
+it's used with [`" stringify!($cache) "::get_many`] and [`" stringify!($cache) "::store_many`]
+which keep a bounded number of the most recently used inputs and their outputs.
 
 # Reading stored values
 
@@ -95,15 +113,75 @@ referenced since the prior call.
 
 After each GC, all values still in the cache are marked garbage. They are marked live again when
 inserted with [`" stringify!($cache) "::store`] or read with
-[`" stringify!($cache) "::get`].
+[`" stringify!($cache) "::get`]. Unused values can be kept for longer with a [`Retention`] policy.
+
+# Memory
+
+Each value is accounted the shallow size of its input and output, or the [`CacheSize`] of its
+output if it was stored with [`CacheEntry::weigh`]. See [`" stringify!($cache) "::bytes`] and
+[`" stringify!($cache) "::namespace_sizes`].
+
+If a byte budget is set with [`" stringify!($cache) "::set_byte_budget`], values are evicted in
+the order they were last read or stored until the cache is within the budget:
+
+* [`" stringify!($cache) "::store`] evicts values which haven't been used since the last GC
+* [`" stringify!($cache) "::gc`] evicts any values, including those which are still live
+
+Both report what was evicted with [`Evictions`]. Because the `cache*` methods on
+[`" stringify!($shared) "`] store values without returning their evictions, `gc` also reports
+the values evicted by stores since the previous GC.
+
+# Snapshots
+
+With the `persist` feature, the values of query types registered with
+[`" stringify!($cache) "::persist`] can be serialized with [`" stringify!($cache) "::snapshot`]
+and loaded into a later cache with [`" stringify!($cache) "::restore`].
 "=>
 #[derive(Debug, Default)]
 pub struct $cache {
     /// We use a [`hash_hasher::HashBuildHasher`] here because we know that `TypeId`s
     /// are globally unique and pre-hashed courtesy of rustc.
     inner: HashMap<TypeId, Box<dyn Storage $(+ $bound)?>, HashBuildHasher>,
+    /// The number of times this cache has been GC'd.
+    generation: u64,
+    /// The number of values stored in this cache.
+    stores: u64,
+    /// The retention policy for values stored without one, if not
+    /// [`Retention::Revision`].
+    default_retention: Option<Retention>,
+    /// The maximum number of unused values kept by [`Retention::Lru`], if any.
+    lru_capacity: Option<usize>,
+    /// The number of times values in this cache have been read or stored, used to order them by
+    /// recency for the byte budget.
+    roots: Cell<u64>,
+    /// The number of bytes accounted to all stored values.
+    bytes: usize,
+    /// The maximum number of bytes to keep, if any.
+    byte_budget: Option<usize>,
+    /// The values evicted by stores since the last GC.
+    store_evictions: Evictions,
+    /// Query types which are included in snapshots, by name.
+    #[cfg(feature = "persist")]
+    persisted: BTreeMap<String, Persist>,
+    $($(#[$field_meta])* $field: $field_ty,)*
 }}
 
+/// Saves and restores the values of a query type registered with `persist`.
+#[cfg(feature = "persist")]
+#[derive(Clone, Copy)]
+struct Persist {
+    save: fn(&$cache) -> Result<Option<serde_json::Value>, serde_json::Error>,
+    /// Deserializes the values, returning a function which adds them to a cache.
+    restore: fn(serde_json::Value) -> Result<Box<dyn FnOnce(&mut $cache)>, serde_json::Error>,
+}
+
+#[cfg(feature = "persist")]
+impl Debug for Persist {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("Persist").finish()
+    }
+}
+
 impl $cache {
 doc_comment! {"
 Return a reference to a query's stored output if a result is stored *and* `arg` equals the
@@ -128,10 +206,12 @@ a [`CacheEntry`] to pass to [`" stringify!($cache) "::store`].
         let dependent = Dependent::incoming();
         let query = Query::new(self.inner.hasher());
 
+        let now = self.root();
         if let Some(ns) = self.get_namespace(&query) {
-            ns.get(key, arg, dependent).map_err(|key_miss| CacheMiss { query, key_miss })
+            ns.get(key, arg, dependent, now).map_err(|key_miss| CacheMiss { query, key_miss })
         } else {
-            Err(CacheMiss { query, key_miss: KeyMiss::just_key(key, arg.to_owned(), dependent) })
+            let key_miss = KeyMiss::just_key(key, Lookup::One, arg.to_owned(), dependent);
+            Err(CacheMiss { query, key_miss })
         }
     }}
 
@@ -139,11 +219,18 @@ doc_comment! {"
 Stores a fresh [`CacheEntry`] whose input/output will not be GC'd at the next call.
 Call [`" stringify!($cache) "::get`] to get a [`CacheMiss`] and [`CacheMiss::init`] to get a
 [`CacheEntry`].
+
+Returns the values evicted to stay within the cache's byte budget, if any.
+
+# Panics
+
+If the entry was initialized from a miss returned by [`" stringify!($cache) "::get_many`].
     "=>
     pub fn store<Key, Scope, Input, Output>(
         &mut self,
         entry: CacheEntry<'_, Key, Scope, Input, Output>,
-    ) where
+    ) -> Evictions
+    where
         Key: Eq + Hash + ToOwned<Owned = Scope> + ?Sized,
         Scope: 'static + Borrow<Key> + Eq + Hash $(+ $bound)?,
         Input: 'static $(+ $bound)?,
@@ -152,10 +239,111 @@ Call [`" stringify!($cache) "::get`] to get a [`CacheMiss`] and [`CacheMiss::ini
         let CacheEntry {
             miss: CacheMiss { query, key_miss },
             output,
+            retention,
+            size,
         } = entry;
-        self.get_namespace_mut(&query).store(key_miss, output);
+        let stored = self.stored::<Input, Output>(retention, size);
+        let namespace = self.get_namespace_mut(&query);
+        let before = namespace.bytes();
+        namespace.store(key_miss, output, stored);
+        let after = namespace.bytes();
+        self.bytes = self.bytes + after - before;
+        self.record_store_evictions()
+    }}
+
+doc_comment! {"
+Like [`" stringify!($cache) "::get`], looking for `arg` among all of the inputs stored for `key`
+by [`" stringify!($cache) "::store_many`]. A returned output becomes the most recently used for
+`key`. Scopes used with these methods are stored separately from those used with `get` and
+`store`.
+
+If no reference is found, a [`CacheMiss`] is returned which must be initialized and passed to
+`store_many`.
+"=>
+    pub fn get_many<'k, Key, Scope, Arg, Input, Output>(
+        &mut self,
+        key: &'k Key,
+        arg: &Arg,
+    ) -> Result<&Output, CacheMiss<'k, Key, Scope, Input, Output>>
+    where
+        Key: Eq + Hash + ToOwned<Owned = Scope> + ?Sized,
+        Scope: 'static + Borrow<Key> + Eq + Hash,
+        Arg: PartialEq<Input> + ToOwned<Owned=Input> + ?Sized,
+        Input: 'static + Borrow<Arg>,
+        Output: 'static,
+    {
+        let dependent = Dependent::incoming();
+        let query = Query::new(self.inner.hasher());
+
+        let now = self.root();
+        if let Some(ns) = self.find_namespace_mut(&query) {
+            ns.get_many(key, arg, dependent, now).map_err(|key_miss| CacheMiss { query, key_miss })
+        } else {
+            let key_miss = KeyMiss::just_key(key, Lookup::Many, arg.to_owned(), dependent);
+            Err(CacheMiss { query, key_miss })
+        }
     }}
 
+doc_comment! {"
+Stores a fresh [`CacheEntry`] as the most recently used input for its key, dropping the least
+recently used inputs beyond `capacity`. Each input's value is marked live and collected
+independently of the others.
+
+Returns the values evicted to stay within the cache's byte budget, if any.
+
+# Panics
+
+If the entry wasn't initialized from a miss returned by [`" stringify!($cache) "::get_many`].
+"=>
+    pub fn store_many<Key, Scope, Input, Output>(
+        &mut self,
+        entry: CacheEntry<'_, Key, Scope, Input, Output>,
+        capacity: usize,
+    ) -> Evictions
+    where
+        Key: Eq + Hash + ToOwned<Owned = Scope> + ?Sized,
+        Scope: 'static + Borrow<Key> + Eq + Hash $(+ $bound)?,
+        Input: 'static $(+ $bound)?,
+        Output: 'static $(+ $bound)?,
+    {
+        let CacheEntry {
+            miss: CacheMiss { query, key_miss },
+            output,
+            retention,
+            size,
+        } = entry;
+        let stored = self.stored::<Input, Output>(retention, size);
+        let namespace = self.get_namespace_mut(&query);
+        let before = namespace.bytes();
+        namespace.store_many(key_miss, output, stored, capacity);
+        let after = namespace.bytes();
+        self.bytes = self.bytes + after - before;
+        self.record_store_evictions()
+    }}
+
+    /// Returns the bookkeeping for a value about to be stored, counting the store.
+    fn stored<Input, Output>(
+        &mut self,
+        retention: Option<Retention>,
+        size: Option<usize>,
+    ) -> Stored {
+        let stored = Stored {
+            at: self.stores,
+            rooted_at: self.root(),
+            retention: retention.or(self.default_retention).unwrap_or(Retention::Revision),
+            size: size_of::<Input>() + size.unwrap_or_else(size_of::<Output>),
+        };
+        self.stores += 1;
+        stored
+    }
+
+    /// Returns the current root count and generation, counting a read or store.
+    fn root(&self) -> Rooted {
+        let count = self.roots.get();
+        self.roots.set(count + 1);
+        Rooted { count, generation: self.generation }
+    }
+
     fn get_namespace<Scope, Input, Output>(
         &self,
         query: &Query<Scope, Input, Output>,
@@ -172,6 +360,23 @@ Call [`" stringify!($cache) "::get`] to get a [`CacheMiss`] and [`CacheMiss::ini
         Some(gc.as_any().downcast_ref().unwrap())
     }
 
+    fn find_namespace_mut<Scope, Input, Output>(
+        &mut self,
+        query: &Query<Scope, Input, Output>,
+    ) -> Option<&mut Namespace<Scope, Input, Output>>
+    where
+        Scope: 'static,
+        Input: 'static,
+        Output: 'static,
+    {
+        let gc: &mut dyn Storage =
+            match self.inner.raw_entry_mut().from_hash(query.hash(), |t| t == &query.ty()) {
+                RawEntryMut::Occupied(occ) => &mut **occ.into_mut(),
+                RawEntryMut::Vacant(_) => return None,
+            };
+        Some(gc.as_any_mut().downcast_mut().unwrap())
+    }
+
     fn get_namespace_mut<Scope, Input, Output>(
         &mut self,
         query: &Query<Scope, Input, Output>,
@@ -191,10 +396,260 @@ Call [`" stringify!($cache) "::get`] to get a [`CacheMiss`] and [`CacheMiss::ini
         gc.as_any_mut().downcast_mut().unwrap()
     }
 
-    /// Drop any values which have not been marked alive since the last call to this method.
-    pub fn gc(&mut self) {
-        self.inner.values_mut().for_each(|ns| ns.mark());
-        self.inner.values_mut().for_each(|namespace| namespace.sweep());
+    /// Drop the values over the byte budget which haven't been used since the last GC, counting
+    /// them towards the next GC's [`Evictions`].
+    fn record_store_evictions(&mut self) -> Evictions {
+        let evictions = self.enforce_budget(true);
+        self.store_evictions.values += evictions.values;
+        self.store_evictions.bytes += evictions.bytes;
+        evictions
+    }
+
+    /// Drop any values which have not been marked alive since the last call to this method,
+    /// unless they're kept by their [`Retention`] policy. If the remaining values exceed the
+    /// byte budget, the least recently used are evicted. Returns the [`Evictions`] made by this
+    /// call and by any stores since the last call.
+    pub fn gc(&mut self) -> Evictions {
+        let generation = self.generation;
+        self.inner.values_mut().for_each(|ns| ns.retain(generation));
+        // values can depend on values in other namespaces, so propagate across all of them
+        let mut nodes = Vec::new();
+        self.inner.values().for_each(|ns| ns.dep_nodes(&mut |node| nodes.push(node.clone())));
+        propagate_liveness(nodes);
+        self.inner.values_mut().for_each(|namespace| namespace.sweep(generation));
+        self.evict_lru(generation);
+        self.bytes = self.inner.values().map(|namespace| namespace.bytes()).sum();
+        self.generation += 1;
+        let mut evictions = self.enforce_budget(false);
+        let by_stores = std::mem::take(&mut self.store_evictions);
+        evictions.values += by_stores.values;
+        evictions.bytes += by_stores.bytes;
+        evictions
+    }
+
+    /// Drop the values which were least recently read or stored until the cache is within its
+    /// byte budget. If `unused_only`, values which are live are kept.
+    fn enforce_budget(&mut self, unused_only: bool) -> Evictions {
+        let mut evictions = Evictions::default();
+        let excess = match self.byte_budget {
+            Some(budget) if self.bytes > budget => self.bytes - budget,
+            _ => {
+                evictions.remaining_bytes = self.bytes;
+                return evictions;
+            }
+        };
+
+        let mut candidates = Vec::new();
+        self.inner.values().for_each(|ns| ns.rooted(unused_only, &mut |c| candidates.push(c)));
+        candidates.sort_unstable();
+        let mut newest_evicted = None;
+        let mut freed = 0;
+        for (rooted, size) in candidates {
+            if freed >= excess {
+                break;
+            }
+            freed += size;
+            newest_evicted = Some(rooted);
+        }
+
+        if let Some(newest) = newest_evicted {
+            for namespace in self.inner.values_mut() {
+                let (values, bytes) = namespace.evict_rooted(unused_only, newest);
+                evictions.values += values;
+                evictions.bytes += bytes;
+            }
+        }
+        self.bytes -= evictions.bytes;
+        evictions.remaining_bytes = self.bytes;
+        evictions
+    }
+
+    /// Drop the least recently used values kept by [`Retention::Lru`] until no more than the
+    /// LRU capacity remain.
+    fn evict_lru(&mut self, generation: u64) {
+        let capacity = match self.lru_capacity {
+            Some(capacity) => capacity,
+            None => return,
+        };
+        let mut unused = Vec::new();
+        self.inner.values().for_each(|ns| ns.unused_lru(generation, &mut |age| unused.push(age)));
+        if unused.len() <= capacity {
+            return;
+        }
+        unused.sort_unstable();
+        let newest_evicted = unused[unused.len() - capacity - 1];
+        self.inner.values_mut().for_each(|ns| ns.evict_lru(generation, newest_evicted));
+    }
+
+    /// Sets the [`Retention`] policy for values stored without one. Only affects values stored
+    /// after this call.
+    pub fn set_default_retention(&mut self, retention: Retention) {
+        self.default_retention = Some(retention);
+    }
+
+    /// Sets the maximum number of unused values to keep with [`Retention::Lru`]. Least recently
+    /// used values beyond the capacity are dropped at the next GC.
+    pub fn set_lru_capacity(&mut self, capacity: usize) {
+        self.lru_capacity = Some(capacity);
+    }
+
+    /// Sets the maximum number of bytes to keep in the cache. Values are evicted to fit the
+    /// budget at the next store or GC.
+    pub fn set_byte_budget(&mut self, bytes: usize) {
+        self.byte_budget = Some(bytes);
+    }
+
+    /// Returns the number of bytes accounted to all of the values stored in the cache.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Calls `visit` with the number and size of the values stored for each query type.
+    pub fn namespace_sizes(&self, mut visit: impl FnMut(NamespaceSize)) {
+        self.inner.values().for_each(|namespace| visit(namespace.size()));
+    }
+
+    /// Returns a [`Checkpoint`] which can be passed to `rollback` to discard values
+    /// stored after this call.
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint(self.stores)
+    }
+
+    /// Drops any values stored since `checkpoint` was returned by `checkpoint()`,
+    /// including values whose inputs were updated. Values which were only read
+    /// since the checkpoint are kept.
+    ///
+    /// `checkpoint` must have been returned by this cache.
+    pub fn rollback(&mut self, checkpoint: Checkpoint) {
+        self.inner.values_mut().for_each(|namespace| namespace.rollback(checkpoint.0));
+        self.bytes = self.inner.values().map(|namespace| namespace.bytes()).sum();
+    }
+
+    /// Returns the number of times this cache has been GC'd.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Calls `visit` with a description of each value stored in the cache.
+    pub fn inspect(&self, mut visit: impl FnMut(EntryInfo<'_>)) {
+        let generation = self.generation;
+        self.inner.values().for_each(|namespace| namespace.inspect(generation, &mut visit));
+    }
+
+doc_comment! {"
+Include the values stored for a query type in snapshots under `name`. A cache which registers the
+same query type with the same name can restore them. Names should stay the same across builds,
+unlike the `TypeId`s used to store each query type.
+
+# Example
+
+```
+use dyn_cache::sync::SendCache;
+
+let mut cache = SendCache::default();
+cache.persist::<char, u32, String>(\"labels\");
+let miss = cache.get(&'a', &1u32).unwrap_err();
+let (entry, ()) = miss.init(|n: &u32| (n.to_string(), ()));
+cache.store(entry);
+let snapshot = cache.snapshot().unwrap();
+
+let mut restarted = SendCache::default();
+restarted.persist::<char, u32, String>(\"labels\");
+restarted.restore(&snapshot).unwrap();
+assert_eq!(restarted.get::<_, _, _, _, String>(&'a', &1u32).ok().unwrap(), \"1\");
+```
+"=>
+    #[cfg(feature = "persist")]
+    pub fn persist<Scope, Input, Output>(&mut self, name: impl Into<String>)
+    where
+        Scope: 'static + Eq + Hash + Serialize + DeserializeOwned $(+ $bound)?,
+        Input: 'static + Serialize + DeserializeOwned $(+ $bound)?,
+        Output: 'static + Serialize + DeserializeOwned $(+ $bound)?,
+    {
+        let persist = Persist {
+            save: Self::save_namespace::<Scope, Input, Output>,
+            restore: Self::restore_namespace::<Scope, Input, Output>,
+        };
+        self.persisted.insert(name.into(), persist);
+    }}
+
+doc_comment! {"
+Serializes the values stored for each query type registered with
+[`" stringify!($cache) "::persist`], whether or not they're live.
+"=>
+    #[cfg(feature = "persist")]
+    pub fn snapshot(&self) -> Result<Vec<u8>, PersistError> {
+        let mut namespaces = BTreeMap::new();
+        for (name, persist) in &self.persisted {
+            let saved = (persist.save)(self).map_err(|e| PersistError::query(name, e))?;
+            if let Some(saved) = saved {
+                namespaces.insert(name.as_str(), saved);
+            }
+        }
+        serde_json::to_vec(&namespaces).map_err(PersistError::snapshot)
+    }}
+
+doc_comment! {"
+Loads the values in a snapshot returned by [`" stringify!($cache) "::snapshot`] for each query
+type registered with [`" stringify!($cache) "::persist`]. Values for query types which aren't
+registered are ignored, as are values for scopes which already have a value in this cache.
+
+Restored values start out dead, as if they had been marked by a GC. They're dropped at the next
+GC unless they're read in the meantime.
+
+Every registered query type's values are read before any are restored, so a snapshot which
+returns an error leaves the cache unchanged.
+"=>
+    #[cfg(feature = "persist")]
+    pub fn restore(&mut self, snapshot: &[u8]) -> Result<(), PersistError> {
+        let namespaces: BTreeMap<String, serde_json::Value> =
+            serde_json::from_slice(snapshot).map_err(PersistError::snapshot)?;
+        let mut restores = Vec::new();
+        for (name, saved) in namespaces {
+            if let Some(persist) = self.persisted.get(&name) {
+                restores.push((persist.restore)(saved).map_err(|e| PersistError::query(&name, e))?);
+            }
+        }
+        restores.into_iter().for_each(|restore| restore(self));
+        self.bytes = self.inner.values().map(|namespace| namespace.bytes()).sum();
+        Ok(())
+    }}
+
+    #[cfg(feature = "persist")]
+    fn save_namespace<Scope, Input, Output>(
+        &self,
+    ) -> Result<Option<serde_json::Value>, serde_json::Error>
+    where
+        Scope: 'static + Eq + Hash + Serialize,
+        Input: 'static + Serialize,
+        Output: 'static + Serialize,
+    {
+        let query = Query::<Scope, Input, Output>::new(self.inner.hasher());
+        let namespace = self.get_namespace(&query);
+        namespace.map(|namespace| serde_json::to_value(namespace.saved())).transpose()
+    }
+
+    #[cfg(feature = "persist")]
+    fn restore_namespace<Scope, Input, Output>(
+        saved: serde_json::Value,
+    ) -> Result<Box<dyn FnOnce(&mut Self)>, serde_json::Error>
+    where
+        Scope: 'static + Eq + Hash + DeserializeOwned $(+ $bound)?,
+        Input: 'static + DeserializeOwned $(+ $bound)?,
+        Output: 'static + DeserializeOwned $(+ $bound)?,
+    {
+        let saved = serde_json::from_value(saved)?;
+        Ok(Box::new(move |cache: &mut Self| {
+            let stored = Stored {
+                at: cache.stores,
+                rooted_at: cache.root(),
+                retention: Retention::Revision,
+                size: 0,
+            };
+            cache.stores += 1;
+            let query = Query::<Scope, Input, Output>::new(cache.inner.hasher());
+            cache.get_namespace_mut(&query).restore(saved, stored);
+        }))
     }
 }
 
@@ -283,6 +738,73 @@ See [`" stringify!($shared) "::cache`] for an ergonomic wrapper that requires `O
         init: impl FnOnce(&Input) -> Output,
         with: impl FnOnce(&Output) -> Ret,
     ) -> Ret
+    where
+        Key: Eq + Hash + ToOwned<Owned = Scope> + ?Sized,
+        Scope: 'static + Borrow<Key> + Eq + Hash $(+ $bound)?,
+        Arg: PartialEq<Input> + ToOwned<Owned=Input> + ?Sized,
+        Input: 'static + Borrow<Arg> $(+ $bound)?,
+        Output: 'static $(+ $bound)?,
+        Ret: 'static $(+ $bound)?,
+    {
+        self.cache_with_inner(key, arg, |entry| entry, init, with)
+    }}
+
+doc_comment!{r"
+Like [`" stringify!($shared) "::cache_with`], keeping the stored value according to `retention`
+instead of the cache's default policy. The policy applies from when the value is stored, and
+doesn't change for a stored value when only read.
+"=>
+    pub fn cache_with_retention<Key, Scope, Arg, Input, Output, Ret>(
+        &self,
+        key: &Key,
+        arg: &Arg,
+        retention: Retention,
+        init: impl FnOnce(&Input) -> Output,
+        with: impl FnOnce(&Output) -> Ret,
+    ) -> Ret
+    where
+        Key: Eq + Hash + ToOwned<Owned = Scope> + ?Sized,
+        Scope: 'static + Borrow<Key> + Eq + Hash $(+ $bound)?,
+        Arg: PartialEq<Input> + ToOwned<Owned=Input> + ?Sized,
+        Input: 'static + Borrow<Arg> $(+ $bound)?,
+        Output: 'static $(+ $bound)?,
+        Ret: 'static $(+ $bound)?,
+    {
+        self.cache_with_inner(key, arg, |entry| entry.retain(retention), init, with)
+    }}
+
+doc_comment!{r"
+Like [`" stringify!($shared) "::cache_with`], accounting for the stored value with its
+[`CacheSize`] so that the memory it owns counts towards the cache's byte budget.
+"=>
+    pub fn cache_sized_with<Key, Scope, Arg, Input, Output, Ret>(
+        &self,
+        key: &Key,
+        arg: &Arg,
+        init: impl FnOnce(&Input) -> Output,
+        with: impl FnOnce(&Output) -> Ret,
+    ) -> Ret
+    where
+        Key: Eq + Hash + ToOwned<Owned = Scope> + ?Sized,
+        Scope: 'static + Borrow<Key> + Eq + Hash $(+ $bound)?,
+        Arg: PartialEq<Input> + ToOwned<Owned=Input> + ?Sized,
+        Input: 'static + Borrow<Arg> $(+ $bound)?,
+        Output: 'static + CacheSize $(+ $bound)?,
+        Ret: 'static $(+ $bound)?,
+    {
+        self.cache_with_inner(key, arg, CacheEntry::weigh, init, with)
+    }}
+
+    fn cache_with_inner<'k, Key, Scope, Arg, Input, Output, Ret>(
+        &self,
+        key: &'k Key,
+        arg: &Arg,
+        prepare: impl FnOnce(
+            CacheEntry<'k, Key, Scope, Input, Output>,
+        ) -> CacheEntry<'k, Key, Scope, Input, Output>,
+        init: impl FnOnce(&Input) -> Output,
+        with: impl FnOnce(&Output) -> Ret,
+    ) -> Ret
     where
         Key: Eq + Hash + ToOwned<Owned = Scope> + ?Sized,
         Scope: 'static + Borrow<Key> + Eq + Hash $(+ $bound)?,
@@ -302,9 +824,9 @@ See [`" stringify!($shared) "::cache`] for an ergonomic wrapper that requires `O
             (store, ret)
         });
 
-        self.inner.$acquire().store(to_store);
+        self.inner.$acquire().store(prepare(to_store));
         to_return
-    }}
+    }
 
 doc_comment!{r"
 Caches the result of `init(arg)` once per `key`, re-running it when `arg` changes. Clones
@@ -329,6 +851,70 @@ See [`" stringify!($shared) "::cache_with`] for a lower-level version which does
         self.cache_with(key, arg, init, Clone::clone)
     }}
 
+doc_comment!{r"
+Caches the result of `init(arg)` for each of the `capacity` most recently used `arg`s per `key`,
+running it when `arg` differs from all of them. Always runs `with` on the stored `Output` before
+returning the result.
+
+Each stored input/output is collected independently like a value stored by
+[`" stringify!($shared) "::cache_with`]. Keys used with this method don't share storage with keys
+used with the single-input methods.
+"=>
+    pub fn cache_many_with<Key, Scope, Arg, Input, Output, Ret>(
+        &self,
+        key: &Key,
+        arg: &Arg,
+        capacity: usize,
+        init: impl FnOnce(&Input) -> Output,
+        with: impl FnOnce(&Output) -> Ret,
+    ) -> Ret
+    where
+        Key: Eq + Hash + ToOwned<Owned = Scope> + ?Sized,
+        Scope: 'static + Borrow<Key> + Eq + Hash $(+ $bound)?,
+        Arg: PartialEq<Input> + ToOwned<Owned=Input> + ?Sized,
+        Input: 'static + Borrow<Arg> $(+ $bound)?,
+        Output: 'static $(+ $bound)?,
+        Ret: 'static $(+ $bound)?,
+    {
+        let miss = match { self.inner.$acquire().get_many(key, arg) } {
+            Ok(stored) => return with(stored),
+            Err(m) => m,
+        };
+
+        let (to_store, to_return) = miss.init(|arg| {
+            let store = init(arg);
+            let ret = with(&store);
+            (store, ret)
+        });
+
+        self.inner.$acquire().store_many(to_store, capacity);
+        to_return
+    }}
+
+doc_comment!{r"
+Caches the result of `init(arg)` for each of the `capacity` most recently used `arg`s per `key`.
+Clones the cached output before returning the result.
+
+See [`" stringify!($shared) "::cache_many_with`] for a lower-level version which does not require
+`Output: Clone`.
+"=>
+    pub fn cache_many<Key, Scope, Arg, Input, Output>(
+        &self,
+        key: &Key,
+        arg: &Arg,
+        capacity: usize,
+        init: impl FnOnce(&Input) -> Output,
+    ) -> Output
+    where
+        Key: Eq + Hash + ToOwned<Owned = Scope> + ?Sized,
+        Scope: 'static + Borrow<Key> + Eq + Hash $(+ $bound)?,
+        Arg: PartialEq<Input> + ToOwned<Owned=Input> + ?Sized,
+        Input: 'static + Borrow<Arg> $(+ $bound)?,
+        Output: 'static + Clone $(+ $bound)?,
+    {
+        self.cache_many_with(key, arg, capacity, init, Clone::clone)
+    }}
+
 doc_comment!{r"
 Caches the result of `init(arg)` once per `key`, re-running it when `arg` changes.
 
@@ -355,8 +941,100 @@ for similar functionality that returns a copy of `Output` or
 doc_comment!{"
 Forwards to [`" stringify!($cache) "::gc`].
 "=>
-    pub fn gc(&self) {
-        self.inner.$acquire().gc();
+    pub fn gc(&self) -> Evictions {
+        self.inner.$acquire().gc()
+    }}
+
+doc_comment!{"
+Forwards to [`" stringify!($cache) "::set_default_retention`].
+"=>
+    pub fn set_default_retention(&self, retention: Retention) {
+        self.inner.$acquire().set_default_retention(retention);
+    }}
+
+doc_comment!{"
+Forwards to [`" stringify!($cache) "::set_lru_capacity`].
+"=>
+    pub fn set_lru_capacity(&self, capacity: usize) {
+        self.inner.$acquire().set_lru_capacity(capacity);
+    }}
+
+doc_comment!{"
+Forwards to [`" stringify!($cache) "::set_byte_budget`].
+"=>
+    pub fn set_byte_budget(&self, bytes: usize) {
+        self.inner.$acquire().set_byte_budget(bytes);
+    }}
+
+doc_comment!{"
+Forwards to [`" stringify!($cache) "::bytes`].
+"=>
+    pub fn bytes(&self) -> usize {
+        self.inner.$acquire().bytes()
+    }}
+
+doc_comment!{"
+Forwards to [`" stringify!($cache) "::namespace_sizes`]. The cache is locked while `visit` runs.
+"=>
+    pub fn namespace_sizes(&self, visit: impl FnMut(NamespaceSize)) {
+        self.inner.$acquire().namespace_sizes(visit);
+    }}
+
+doc_comment!{"
+Forwards to [`" stringify!($cache) "::persist`].
+"=>
+    #[cfg(feature = "persist")]
+    pub fn persist<Scope, Input, Output>(&self, name: impl Into<String>)
+    where
+        Scope: 'static + Eq + Hash + Serialize + DeserializeOwned $(+ $bound)?,
+        Input: 'static + Serialize + DeserializeOwned $(+ $bound)?,
+        Output: 'static + Serialize + DeserializeOwned $(+ $bound)?,
+    {
+        self.inner.$acquire().persist::<Scope, Input, Output>(name);
+    }}
+
+doc_comment!{"
+Forwards to [`" stringify!($cache) "::snapshot`].
+"=>
+    #[cfg(feature = "persist")]
+    pub fn snapshot(&self) -> Result<Vec<u8>, PersistError> {
+        self.inner.$acquire().snapshot()
+    }}
+
+doc_comment!{"
+Forwards to [`" stringify!($cache) "::restore`].
+"=>
+    #[cfg(feature = "persist")]
+    pub fn restore(&self, snapshot: &[u8]) -> Result<(), PersistError> {
+        self.inner.$acquire().restore(snapshot)
+    }}
+
+doc_comment!{"
+Forwards to [`" stringify!($cache) "::checkpoint`].
+"=>
+    pub fn checkpoint(&self) -> Checkpoint {
+        self.inner.$acquire().checkpoint()
+    }}
+
+doc_comment!{"
+Forwards to [`" stringify!($cache) "::rollback`].
+"=>
+    pub fn rollback(&self, checkpoint: Checkpoint) {
+        self.inner.$acquire().rollback(checkpoint);
+    }}
+
+doc_comment!{"
+Forwards to [`" stringify!($cache) "::generation`].
+"=>
+    pub fn generation(&self) -> u64 {
+        self.inner.$acquire().generation()
+    }}
+
+doc_comment!{"
+Forwards to [`" stringify!($cache) "::inspect`]. The cache is locked while `visit` runs.
+"=>
+    pub fn inspect(&self, visit: impl FnMut(EntryInfo<'_>)) {
+        self.inner.$acquire().inspect(visit);
     }}
 
     fn addr(&self) -> usize {
@@ -413,6 +1091,406 @@ mod $test_mod {
         assert_eq!(call_count.get(), 1);
     }
 
+    #[test]
+    fn rollback_drops_values_stored_since_checkpoint() {
+        let storage = $shared::default();
+        storage.cache(&'a', &1u8, |&n| n);
+        storage.cache(&'b', &1u8, |&n| n);
+
+        let checkpoint = storage.checkpoint();
+        storage.cache(&'a', &1u8, |&n| n); // read
+        storage.cache(&'b', &2u8, |&n| n); // updated
+        storage.cache(&'c', &1u8, |&n| n); // created
+        storage.rollback(checkpoint);
+
+        let mut scopes = vec![];
+        storage.inspect(|e| scopes.push(*e.scope.downcast_ref::<char>().unwrap()));
+        assert_eq!(scopes, vec!['a']);
+    }
+
+    #[test]
+    fn inspect_tracks_generations() {
+        let storage = $shared::default();
+        let entries = || {
+            let mut entries = vec![];
+            storage.inspect(|e| entries.push((*e.scope.downcast_ref::<char>().unwrap(), e.is_live, e.generation)));
+            entries.sort();
+            entries
+        };
+
+        storage.cache(&'a', &1u8, |&n| n);
+        storage.cache(&'b', &1u8, |&n| n);
+        assert_eq!(entries(), vec![('a', true, 0), ('b', true, 0)]);
+
+        storage.gc();
+        assert_eq!(storage.generation(), 1);
+        assert_eq!(entries(), vec![('a', false, 0), ('b', false, 0)]);
+
+        storage.cache(&'a', &1u8, |&n| n);
+        assert_eq!(entries(), vec![('a', true, 1), ('b', false, 0)]);
+
+        storage.gc();
+        assert_eq!(storage.generation(), 2);
+        assert_eq!(entries(), vec![('a', false, 1)], "'b' was collected");
+    }
+
+    #[test]
+    fn inspect_tracks_rooted_generations() {
+        let storage = $shared::default();
+        let entries = || {
+            let mut entries = vec![];
+            storage.inspect(|e| {
+                let scope = *e.scope.downcast_ref::<char>().unwrap();
+                entries.push((scope, e.generation, e.rooted_generation));
+            });
+            entries.sort();
+            entries
+        };
+        let root = || storage.cache(&'a', &1u8, |&n| storage.cache(&'b', &n, |&n| n));
+
+        root();
+        storage.gc();
+        root();
+        storage.gc();
+        assert_eq!(entries(), vec![('a', 1, 1), ('b', 1, 0)], "'b' is only kept live by 'a'");
+    }
+
+    #[test]
+    fn retention_policies_keep_unused_values() {
+        let storage = $shared::default();
+        let scopes = || {
+            let mut scopes = vec![];
+            storage.inspect(|e| scopes.push(*e.scope.downcast_ref::<char>().unwrap()));
+            scopes.sort();
+            scopes
+        };
+        let revision = |used: &[char]| {
+            for &scope in used {
+                storage.cache_with_retention(&scope, &(), Retention::Lru, |&()| (), |_| ());
+            }
+            storage.cache_with_retention(&'p', &(), Retention::Pinned, |&()| (), |_| ());
+            storage.cache_with_retention(&'r', &(), Retention::Revisions(1), |&()| (), |_| ());
+            storage.cache(&'x', &(), |&()| ());
+        };
+
+        storage.set_lru_capacity(2);
+        revision(&['a', 'b', 'c']);
+        storage.gc();
+        revision(&['b']);
+        storage.gc();
+        assert_eq!(scopes(), vec!['a', 'b', 'c', 'p', 'r', 'x'], "unused lru values within capacity");
+
+        revision(&['d']);
+        storage.gc();
+        assert_eq!(scopes(), vec!['b', 'c', 'd', 'p', 'r', 'x'], "least recently used evicted");
+
+        storage.gc();
+        assert_eq!(scopes(), vec!['b', 'd', 'p', 'r'], "kept for one unused revision");
+        storage.gc();
+        assert_eq!(scopes(), vec!['b', 'd', 'p']);
+    }
+
+    #[test]
+    fn retained_values_keep_their_dependencies() {
+        let storage = $shared::default();
+        let count = std::cell::Cell::new(0);
+        let increment = |&(): &()| count.set(count.get() + 1);
+        storage.cache_with_retention(
+            &'o',
+            &(),
+            Retention::Revisions(2),
+            |&()| storage.cache(&'i', &(), increment),
+            |_| (),
+        );
+        storage.gc();
+        storage.gc();
+        storage.cache(&'i', &(), increment);
+        assert_eq!(count.get(), 1, "inner value kept while the outer value was retained");
+
+        storage.gc();
+        storage.gc();
+        storage.gc();
+        let mut num_entries = 0;
+        storage.inspect(|_| num_entries += 1);
+        assert_eq!(num_entries, 0);
+    }
+
+    #[test]
+    fn cache_many_keeps_recent_inputs() {
+        let storage = $shared::default();
+        let call_count = std::cell::Cell::new(0);
+        let double = |&n: &u32| {
+            call_count.set(call_count.get() + 1);
+            n * 2
+        };
+
+        for _ in 0..3 {
+            assert_eq!(storage.cache_many(&'a', &1, 2, double), 2);
+            assert_eq!(storage.cache_many(&'a', &2, 2, double), 4);
+        }
+        assert_eq!(call_count.get(), 2, "alternating inputs don't thrash");
+
+        storage.cache_many(&'a', &3, 2, double);
+        storage.cache_many(&'a', &2, 2, double);
+        storage.cache_many(&'a', &1, 2, double);
+        assert_eq!(call_count.get(), 4, "least recently used input was dropped");
+
+        storage.gc();
+        storage.cache_many(&'a', &2, 2, double);
+        storage.gc();
+        let mut inputs = 0;
+        storage.inspect(|_| inputs += 1);
+        assert_eq!(inputs, 1, "unused inputs are collected individually");
+        storage.cache_many(&'a', &2, 2, double);
+        assert_eq!(call_count.get(), 4);
+
+        assert_eq!(storage.cache(&'a', &2, double), 4);
+        assert_eq!(call_count.get(), 5, "single-input storage is separate");
+    }
+
+    #[test]
+    #[should_panic(expected = "store_many needs a miss from get_many")]
+    fn store_many_rejects_misses_from_get() {
+        let mut storage = $cache::default();
+        let (entry, ()) = storage.get(&'a', &1u32).unwrap_err().init(|&n| (n, ()));
+        storage.store(entry);
+
+        // the existing cell for 'a' is in the storage for single inputs
+        let (entry, ()) = storage.get(&'a', &2u32).unwrap_err().init(|&n| (n, ()));
+        storage.store_many(entry, 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "store needs a miss from get, not get_many")]
+    fn store_rejects_misses_from_get_many() {
+        let mut storage = $cache::default();
+        let (entry, ()) = storage.get_many(&'a', &1u32).unwrap_err().init(|&n| (n, ()));
+        storage.store(entry);
+    }
+
+    #[test]
+    fn cache_many_tracks_dependencies() {
+        let storage = $shared::default();
+        let call_count = std::cell::Cell::new(0);
+        let inner = |&n: &u32| {
+            call_count.set(call_count.get() + 1);
+            n
+        };
+        let outer = |n: u32| storage.cache_many(&'o', &n, 2, |&n| storage.cache(&n, &(), |&()| inner(&n)));
+
+        outer(1);
+        outer(2);
+        storage.gc();
+        outer(1);
+        storage.gc();
+        storage.cache(&1u32, &(), |&()| inner(&1));
+        assert_eq!(call_count.get(), 2, "dependency of a used input was kept");
+        storage.cache(&2u32, &(), |&()| inner(&2));
+        assert_eq!(call_count.get(), 3, "dependency of an unused input was collected");
+    }
+
+    #[test]
+    fn self_referencing_queries() {
+        let storage = $shared::default();
+        let call_count = std::cell::Cell::new(0);
+        fn sum_to(storage: &$shared, calls: &std::cell::Cell<u32>, n: u32) -> u32 {
+            storage.cache(&'s', &n, |&n| {
+                calls.set(calls.get() + 1);
+                if n == 0 { 0 } else { n + sum_to(storage, calls, n - 1) }
+            })
+        }
+
+        assert_eq!(sum_to(&storage, &call_count, 3), 6, "reentrant misses on one scope");
+        assert_eq!(sum_to(&storage, &call_count, 3), 6);
+        assert_eq!(call_count.get(), 4, "outermost input stays cached");
+
+        // initializing reads another input of the same query and scope, depending on itself
+        let bump_calls = std::cell::Cell::new(0);
+        let bump = || {
+            storage.cache(&'b', &2u32, |_| {
+                bump_calls.set(bump_calls.get() + 1);
+                storage.cache(&'b', &1u32, |&n| n) + 1
+            })
+        };
+        let scopes = || {
+            let mut scopes = vec![];
+            storage.inspect(|e| scopes.push(*e.scope.downcast_ref::<char>().unwrap()));
+            scopes
+        };
+        storage.gc();
+        assert_eq!(bump(), 2);
+        for _ in 0..3 {
+            storage.gc();
+            assert_eq!(bump(), 2);
+        }
+        storage.gc();
+        assert_eq!(bump_calls.get(), 1, "rooted self-references stay alive");
+        assert_eq!(scopes(), vec!['b']);
+
+        storage.gc();
+        assert_eq!(scopes(), vec![], "unrooted self-references are collected");
+    }
+
+    #[test]
+    fn mutually_dependent_queries() {
+        let storage = $shared::default();
+        let call_count = std::cell::Cell::new(0);
+        let count = |&n: &u32| {
+            call_count.set(call_count.get() + 1);
+            n
+        };
+        let a = || storage.cache(&'a', &1u32, |&n| storage.cache(&'b', &n, count));
+        let b = || storage.cache(&'b', &2u32, count);
+        let c = || storage.cache(&'c', &(), |&()| storage.cache(&'b', &2u32, |&n| a() + n));
+
+        // 'a' depends on 'b', then 'c' re-initializes 'b' reading 'a'
+        a();
+        c();
+        assert_eq!(call_count.get(), 1);
+        let calls_after_setup = call_count.get();
+
+        for root in &[&a as &dyn Fn() -> u32, &b, &c] {
+            storage.gc();
+            root();
+            storage.gc();
+            a();
+            b();
+            assert_eq!(call_count.get(), calls_after_setup, "each member keeps the cycle alive");
+        }
+
+        storage.gc();
+        storage.gc();
+        let mut num_entries = 0;
+        storage.inspect(|_| num_entries += 1);
+        assert_eq!(num_entries, 0, "an unused cycle is collected");
+    }
+
+    #[test]
+    fn byte_budget_evicts_least_recently_rooted() {
+        let storage = $shared::default();
+        let value = |scope: char| storage.cache(&scope, &0u32, |_| 0u32);
+        let scopes = || {
+            let mut scopes = vec![];
+            storage.inspect(|e| scopes.push(*e.scope.downcast_ref::<char>().unwrap()));
+            scopes.sort_unstable();
+            scopes
+        };
+        storage.set_byte_budget(24);
+
+        for &scope in &['a', 'b', 'c', 'd'] {
+            value(scope);
+        }
+        assert_eq!(storage.bytes(), 32, "live values aren't evicted when stored");
+        assert_eq!(storage.gc(), Evictions { values: 1, bytes: 8, remaining_bytes: 24 });
+        assert_eq!(scopes(), vec!['b', 'c', 'd'], "least recently used value evicted");
+
+        value('d');
+        value('c');
+        value('e');
+        assert_eq!(scopes(), vec!['c', 'd', 'e'], "storing evicts unused values");
+        assert_eq!(storage.bytes(), 24);
+        assert_eq!(
+            storage.gc(),
+            Evictions { values: 1, bytes: 8, remaining_bytes: 24 },
+            "gc reports values evicted by stores since the last gc",
+        );
+        for &scope in &['c', 'd', 'e'] {
+            value(scope);
+        }
+        assert_eq!(storage.gc(), Evictions { values: 0, bytes: 0, remaining_bytes: 24 });
+    }
+
+    #[test]
+    fn weighed_outputs_count_heap_allocations() {
+        let storage = $shared::default();
+        let name = |n: usize| {
+            storage.cache_sized_with(&'n', &n, |&n| String::with_capacity(n), |s| s.capacity())
+        };
+        let name_size = |n| std::mem::size_of::<usize>() + std::mem::size_of::<String>() + n;
+
+        assert_eq!(name(100), 100);
+        storage.cache(&'c', &1u8, |&n| n);
+        assert_eq!(storage.bytes(), name_size(100) + 2);
+
+        let mut sizes = vec![];
+        storage.namespace_sizes(|size| sizes.push((size.scope_type, size.values, size.bytes)));
+        sizes.sort_unstable();
+        assert_eq!(sizes, vec![("char", 1, 2), ("char", 1, name_size(100))]);
+
+        storage.gc();
+        assert_eq!(name(10), 10);
+        assert_eq!(storage.bytes(), name_size(10) + 2, "replaced values are no longer counted");
+        storage.gc();
+        assert_eq!(storage.bytes(), name_size(10), "collected values are no longer counted");
+
+        storage.set_byte_budget(name_size(10) - 1);
+        name(10);
+        let evictions = storage.gc();
+        assert_eq!((evictions.values, evictions.remaining_bytes), (1, 0));
+    }
+
+    #[cfg(feature = "persist")]
+    #[test]
+    fn snapshots_restore_dead_values() {
+        let storage = $shared::default();
+        storage.persist::<char, u32, String>("labels");
+        storage.cache(&'a', &1u32, |n| n.to_string());
+        storage.cache_many(&'m', &2u32, 2, |n| n.to_string());
+        storage.cache_many(&'m', &3u32, 2, |n| n.to_string());
+        storage.cache(&'x', &1u8, |&n| n);
+        let snapshot = storage.snapshot().unwrap();
+
+        let restored = $shared::default();
+        restored.persist::<char, u32, String>("labels");
+        restored.restore(&snapshot).unwrap();
+        let mut entries = vec![];
+        restored.inspect(|e| entries.push((*e.scope.downcast_ref::<char>().unwrap(), e.is_live)));
+        entries.sort_unstable();
+        assert_eq!(entries, vec![('a', false), ('m', false), ('m', false)], "registered only");
+        assert_eq!(restored.bytes(), storage.bytes() - 2);
+
+        let call_count = std::cell::Cell::new(0);
+        let label = |n: &u32| {
+            call_count.set(call_count.get() + 1);
+            n.to_string()
+        };
+        assert_eq!(restored.cache(&'a', &1u32, label), "1");
+        assert_eq!(restored.cache_many(&'m', &3u32, 2, label), "3");
+        assert_eq!(call_count.get(), 0, "restored values are used");
+
+        restored.gc();
+        let mut num_entries = 0;
+        restored.inspect(|_| num_entries += 1);
+        assert_eq!(num_entries, 2, "restored values are swept unless they're read");
+
+        let unregistered = $shared::default();
+        unregistered.restore(&snapshot).unwrap();
+        assert_eq!(unregistered.bytes(), 0);
+        let error = unregistered.restore(b"not a snapshot").unwrap_err();
+        assert_eq!(error.name(), None);
+    }
+
+    #[cfg(feature = "persist")]
+    #[test]
+    fn failed_restores_leave_the_cache_unchanged() {
+        let storage = $shared::default();
+        storage.persist::<char, u32, String>("a_labels");
+        storage.persist::<char, u8, u8>("b_bytes");
+        storage.cache(&'a', &1u32, |n| n.to_string());
+        storage.cache(&'b', &1u8, |&n| n);
+        let snapshot = storage.snapshot().unwrap();
+
+        let restored = $shared::default();
+        restored.persist::<char, u32, String>("a_labels");
+        // the saved bytes can't be read as strings
+        restored.persist::<char, u8, String>("b_bytes");
+        let error = restored.restore(&snapshot).unwrap_err();
+        assert_eq!(error.name(), Some("b_bytes"));
+        let mut num_entries = 0;
+        restored.inspect(|_| num_entries += 1);
+        assert_eq!((num_entries, restored.bytes()), (0, 0), "a_labels was read but not restored");
+    }
+
     #[test]
     fn distinct_scopes_distinct_storage() {
         let storage = $shared::default();
@@ -3,6 +3,7 @@ use illicit::AsContext;
 use parking_lot::Mutex;
 use std::{
     cmp::Ordering,
+    collections::HashMap,
     hash::{Hash, Hasher},
     sync::{Arc, Weak},
 };
@@ -19,6 +20,14 @@ impl DepNode {
         this
     }
 
+    /// Returns a node which will be swept at the next gc unless it's rooted.
+    #[cfg(feature = "persist")]
+    pub fn dead() -> Self {
+        let mut this = Self::default();
+        this.mark_dead();
+        this
+    }
+
     pub fn root(&self, dependent: Dependent) {
         self.inner.lock().root(dependent);
     }
@@ -27,19 +36,37 @@ impl DepNode {
         Dependent { inner: Arc::downgrade(&self.inner) }
     }
 
-    pub fn is_known_live(&self) -> bool {
-        // TODO(#174) find a better way to handle cycles
-        if let Some(l) = self.inner.try_lock() {
-            matches!(l.liveness, Liveness::Live)
-        } else {
-            false
-        }
+    pub fn is_live(&self) -> bool {
+        self.liveness() == Liveness::Live
     }
 
-    pub fn update_liveness(&mut self) {
-        // TODO(#174) find a better way to handle cycles
-        if let Some(mut this) = self.inner.try_lock() {
-            this.update_liveness();
+    /// Returns true if this node is kept by its own retention policy or that of
+    /// a transitive dependent, without being live.
+    pub fn is_retained(&self) -> bool {
+        self.liveness() == Liveness::Retained
+    }
+
+    fn liveness(&self) -> Liveness {
+        self.inner.lock().liveness
+    }
+
+    /// Returns this node's dependents, dropping any which have been freed.
+    fn dependents(&self) -> Vec<DepNode> {
+        let mut inner = self.inner.lock();
+        inner.dependents.sort_unstable();
+        inner.dependents.dedup();
+        inner.dependents.retain(|d| d.inner.strong_count() > 0);
+        inner.dependents.iter().filter_map(Dependent::upgrade).collect()
+    }
+
+    /// Raise this node's liveness to `liveness`, returning true if it rose.
+    fn raise(&self, liveness: Liveness) -> bool {
+        let mut inner = self.inner.lock();
+        if liveness > inner.liveness {
+            inner.liveness = liveness;
+            true
+        } else {
+            false
         }
     }
 
@@ -47,6 +74,15 @@ impl DepNode {
         self.inner.lock().mark_dead();
     }
 
+    /// Keep this node and its dependencies through the next sweep if it hasn't
+    /// been marked live.
+    pub fn retain(&mut self) {
+        let mut inner = self.inner.lock();
+        if matches!(inner.liveness, Liveness::Dead) {
+            inner.liveness = Liveness::Retained;
+        }
+    }
+
     /// Return the memory address of this `DepNode`.
     fn addr(&self) -> usize {
         Arc::as_ptr(&self.inner) as *const _ as _
@@ -55,6 +91,41 @@ impl DepNode {
 
 impl_common_traits_for_type_with_addr!(DepNode);
 
+/// Raise the liveness of each of `nodes` to that of its strongest transitive
+/// dependent, giving every node in a cycle the same liveness. Returns the
+/// number of times a node's liveness was passed on to its dependencies.
+///
+/// Nodes only know their dependents, so those edges are reversed first. After
+/// that a node is only revisited when its liveness rises, which happens at most
+/// twice per gc, so the work is linear in the size of the graph rather than
+/// growing with its depth. Only one node is locked at a time so that cycles in
+/// the graph, including nodes which depend on themselves, can't deadlock.
+pub(crate) fn propagate_liveness(nodes: impl IntoIterator<Item = DepNode>) -> usize {
+    // keyed by address, each dependent and the nodes which depend on it
+    let mut dependencies: HashMap<usize, (DepNode, Vec<DepNode>)> = HashMap::new();
+    for node in nodes {
+        for dependent in node.dependents() {
+            let entry = dependencies.entry(dependent.addr());
+            entry.or_insert_with(|| (dependent, Vec::new())).1.push(node.clone());
+        }
+    }
+
+    let mut pending: Vec<usize> = dependencies.keys().copied().collect();
+    let mut visits = 0;
+    while let Some(addr) = pending.pop() {
+        visits += 1;
+        let (node, node_dependencies) = &dependencies[&addr];
+        let liveness = node.liveness();
+        for dependency in node_dependencies {
+            let addr = dependency.addr();
+            if dependency.raise(liveness) && dependencies.contains_key(&addr) {
+                pending.push(addr);
+            }
+        }
+    }
+    visits
+}
+
 #[derive(Debug)]
 struct InnerDepNode {
     liveness: Liveness,
@@ -74,38 +145,6 @@ impl InnerDepNode {
         self.liveness = Liveness::Live;
     }
 
-    /// Check incoming dependents for roots, marking ourselves live if a root
-    /// exists. Drops stale dependents.
-    fn update_liveness(&mut self) {
-        self.dependents.sort_unstable();
-        self.dependents.dedup();
-
-        if matches!(self.liveness, Liveness::Live) {
-            // we've already been here this gc, nothing new to see here
-            return;
-        }
-
-        let mut has_root = false;
-        self.dependents.retain(|dependent| {
-            let mut keep = false;
-
-            if let Some(mut dependent) = dependent.upgrade() {
-                dependent.update_liveness();
-                if dependent.is_known_live() {
-                    has_root = true;
-                }
-                keep = true;
-            }
-
-            keep
-        });
-
-        // if we found a transitive root then mark ourselves live
-        if has_root {
-            self.liveness = Liveness::Live;
-        }
-    }
-
     fn mark_dead(&mut self) {
         self.liveness = Liveness::Dead;
     }
@@ -141,3 +180,25 @@ impl Dependent {
 }
 
 impl_common_traits_for_type_with_addr!(Dependent);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn liveness_propagates_down_deep_chains_in_linear_time() {
+        let root = DepNode::new(Dependent::default());
+        let mut chain = vec![root];
+        for _ in 0..1_000 {
+            let dependent = chain.last().unwrap().as_dependent();
+            chain.push(DepNode::new(dependent));
+        }
+        for node in &mut chain[1..] {
+            node.mark_dead();
+        }
+
+        let visits = propagate_liveness(chain.iter().rev().cloned());
+        assert!(chain.iter().all(DepNode::is_live), "the root's liveness reached the end");
+        assert!(visits <= 3 * chain.len(), "{} visits for {} nodes", visits, chain.len());
+    }
+}
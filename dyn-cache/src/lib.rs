@@ -109,7 +109,10 @@
 //! | [`local::SharedLocalCache`] | RefCell       |
 //!
 //! These variants are used by calling [`sync::SharedSendCache::cache_with`] or
-//! [`local::SharedLocalCache::cache`].
+//! [`local::SharedLocalCache::cache`]. Threads which miss on the same query at
+//! once each run its initialization unless they call
+//! [`sync::SharedSendCache::cache_with_single_flight`], which waits for the
+//! first thread's result instead.
 //!
 //! The shared cache types above are implemented by wrapping these "inner"
 //! types:
@@ -163,6 +166,24 @@
 //! same type of scope can be used in multiple queries without collision if
 //! the types of inputs, outputs, or both differ.
 //!
+//! Queries which alternate between a few inputs can keep a bounded number of
+//! `(Input, Output)` pairs per scope, most recently used first:
+//!
+//! ```
+//! let storage = dyn_cache::local::SharedLocalCache::default();
+//! let count = std::cell::Cell::new(0);
+//! let square = |&n: &u32| {
+//!     count.set(count.get() + 1);
+//!     n * n
+//! };
+//!
+//! for _ in 0..10 {
+//!     assert_eq!(storage.cache_many(&'a', &2, 2, square), 4);
+//!     assert_eq!(storage.cache_many(&'a', &3, 2, square), 9);
+//! }
+//! assert_eq!(count.get(), 2, "both inputs stay cached");
+//! ```
+//!
 //! ## Inputs
 //!
 //! The input to a query determines when it is re-run. If a given query is
@@ -264,6 +285,46 @@
 //! assert_eq!(count.get(), 5);
 //! ```
 //!
+//! ## Inspection
+//!
+//! Each call to `gc()` ends a "generation" of the cache. The contents of a
+//! cache can be listed along with the most recent generation in which each
+//! value was used:
+//!
+//! ```
+//! let storage = dyn_cache::local::SharedLocalCache::default();
+//! storage.cache(&'a', &1, |&n| n + 1);
+//! storage.gc();
+//! storage.cache(&'b', &1, |&n| n + 2);
+//!
+//! let mut entries = vec![];
+//! storage.inspect(|entry| {
+//!     let scope: char = *entry.scope.downcast_ref().unwrap();
+//!     entries.push((scope, entry.is_live, entry.generation));
+//! });
+//! entries.sort();
+//! assert_eq!(entries, vec![('a', false, 0), ('b', true, 1)]);
+//! ```
+//!
+//! ## Memory
+//!
+//! Caches count the bytes held by their values, weighing outputs with
+//! [`CacheSize`] when asked to. With a byte budget the least recently used
+//! values are evicted, even if they're still live:
+//!
+//! ```
+//! let storage = dyn_cache::local::SharedLocalCache::default();
+//! storage.set_byte_budget(1500);
+//!
+//! storage.cache_sized_with(&'a', &1, |&n| vec![0u8; n * 1000], |_| ());
+//! storage.cache_sized_with(&'b', &1, |&n| vec![0u8; n * 1000], |_| ());
+//! assert!(storage.bytes() > 2000, "live values are only evicted by gc");
+//!
+//! let evicted = storage.gc();
+//! assert_eq!(evicted.values, 1, "'a' was used least recently");
+//! assert!(storage.bytes() <= 1500);
+//! ```
+//!
 //! ## Nesting
 //!
 //! When a cache read *fails*, we expect that the value will be populated
@@ -323,12 +384,19 @@
 //! assert_eq!(a_inc(3), 5);
 //! assert_eq!(count.get(), 0);
 //! ```
+//!
+//! Queries may depend on each other in cycles, for example when a query reads
+//! its own previous value while being re-initialized, or when a query calls
+//! itself with a different input. Each value is as live as its strongest
+//! dependent, so values in a cycle share liveness: the whole cycle is kept if
+//! any of its members or their dependents are rooted, and is collected
+//! together otherwise.
 
 use downcast_rs::{impl_downcast, Downcast};
 use hash_hasher::HashBuildHasher;
 use hashbrown::hash_map::DefaultHashBuilder;
 use std::{
-    any::TypeId,
+    any::{Any, TypeId},
     fmt::{Debug, Formatter, Result as FmtResult},
     hash::{BuildHasher, Hash, Hasher},
     marker::PhantomData,
@@ -340,8 +408,14 @@ mod definition;
 mod cache_cell;
 mod dep_node;
 mod namespace;
+#[cfg(feature = "persist")]
+mod persist;
+mod size;
 
-use namespace::{KeyMiss, Namespace};
+use namespace::{KeyMiss, Lookup, Namespace};
+#[cfg(feature = "persist")]
+pub use persist::PersistError;
+pub use size::{CacheSize, Evictions, NamespaceSize};
 
 /// The result of a failed attempt to retrieve a value from the cache.
 /// Initialize a full [`CacheEntry`] for storage with [`CacheMiss::init`].
@@ -387,7 +461,7 @@ impl<'k, Key: ?Sized, Scope, Input, Output, H> CacheMiss<'k, Key, Scope, Input,
         query: impl FnOnce(&Input) -> (Output, R),
     ) -> (CacheEntry<'k, Key, Scope, Input, Output, H>, R) {
         let (output, to_return) = self.key_miss.init(query);
-        (CacheEntry { output, miss: self }, to_return)
+        (CacheEntry { output, miss: self, retention: None, size: None }, to_return)
     }
 }
 
@@ -412,8 +486,100 @@ where
 pub struct CacheEntry<'k, Key: ?Sized, Scope, Input, Output, H = DefaultHashBuilder> {
     miss: CacheMiss<'k, Key, Scope, Input, Output, H>,
     output: Output,
+    retention: Option<Retention>,
+    size: Option<usize>,
+}
+
+impl<'k, Key: ?Sized, Scope, Input, Output, H> CacheEntry<'k, Key, Scope, Input, Output, H> {
+    /// Keep the stored value according to `retention` rather than the cache's
+    /// default policy.
+    pub fn retain(mut self, retention: Retention) -> Self {
+        self.retention = Some(retention);
+        self
+    }
+
+    /// Account for the stored output with its [`CacheSize`], including any
+    /// heap allocations it owns, rather than only its shallow size.
+    pub fn weigh(mut self) -> Self
+    where
+        Output: CacheSize,
+    {
+        self.size = Some(self.output.cache_size());
+        self
+    }
+}
+
+/// How long a cache keeps a value after the last generation in which it was
+/// used. A value's dependencies are kept for as long as it is.
+///
+/// Each cache has a default policy which is used for values stored without one,
+/// set with [`local::LocalCache::set_default_retention`] or
+/// [`sync::SendCache::set_default_retention`]. Otherwise
+/// [`Retention::Revision`] is used.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Retention {
+    /// Drop the value at the first GC in which it wasn't used.
+    Revision,
+    /// Keep the value through this many GCs in which it wasn't used, dropping
+    /// it at the next.
+    Revisions(u64),
+    /// Keep the value while it's unused, dropping the least recently used of
+    /// these values once there are more of them than the cache's LRU capacity.
+    /// The capacity is unlimited unless set with
+    /// [`local::LocalCache::set_lru_capacity`] or
+    /// [`sync::SendCache::set_lru_capacity`].
+    Lru,
+    /// Keep the value until its input changes or it's rolled back.
+    Pinned,
 }
 
+impl Retention {
+    /// Returns true if a value which has been unused for `unused_for` GCs
+    /// should be kept.
+    fn keeps(self, unused_for: u64) -> bool {
+        match self {
+            Retention::Revision => false,
+            Retention::Revisions(n) => unused_for <= n,
+            Retention::Lru | Retention::Pinned => true,
+        }
+    }
+}
+
+/// Describes a single value stored in a cache. Passed to the function provided
+/// to [`local::LocalCache::inspect`] or [`sync::SendCache::inspect`].
+#[derive(Debug)]
+pub struct EntryInfo<'a> {
+    /// The scope under which the value is stored.
+    pub scope: &'a dyn Any,
+    /// The type name of the value's scope.
+    pub scope_type: &'static str,
+    /// The type name of the value's input.
+    pub input_type: &'static str,
+    /// The type name of the value's output.
+    pub output_type: &'static str,
+    /// Whether the value has been used since the last GC.
+    pub is_live: bool,
+    /// The most recent generation in which the value was live. A cache's
+    /// generation is the number of times it has been GC'd.
+    pub generation: u64,
+    /// The most recent generation in which the value was read or stored. May
+    /// be older than `generation` if the value was kept live by another
+    /// value which depends on it.
+    pub rooted_generation: u64,
+    /// The size in bytes of the value's input and output, not including any
+    /// heap allocations they own.
+    pub shallow_size: usize,
+    /// The number of bytes accounted to the value. Includes the heap
+    /// allocations of outputs stored with [`CacheEntry::weigh`].
+    pub size: usize,
+}
+
+/// A point in a cache's history which can be returned to by discarding the
+/// values stored since. Returned by [`local::LocalCache::checkpoint`] and
+/// [`sync::SendCache::checkpoint`].
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Checkpoint(u64);
+
 /// A cache for types which are not thread-safe (`?Send`).
 pub mod local {
     use std::{cell::RefCell, rc::Rc};
@@ -426,27 +592,75 @@ pub mod sync {
     use parking_lot::Mutex;
     use std::sync::Arc;
 
-    define_cache!(sync, SendCache: Send, Arc, Mutex::lock);
+    define_cache!(
+        sync,
+        SendCache: Send,
+        Arc,
+        Mutex::lock,
+        /// Initializations in progress, see
+        /// [`SharedSendCache::cache_with_single_flight`].
+        flights: flight::Flights
+    );
+
+    pub(crate) mod flight;
 }
 
 /// A type which can contain values of varying liveness.
 trait Storage: Downcast + Debug {
-    /// Traverse stored values, identifying roots.
-    fn mark(&mut self);
+    /// Identify unused values which are kept by their retention policy at the
+    /// end of `generation`.
+    fn retain(&mut self, generation: u64);
 
-    /// Remove dead entries.
-    fn sweep(&mut self);
+    /// Visit the node which tracks the liveness of each stored value.
+    fn dep_nodes(&self, visit: &mut dyn FnMut(&dep_node::DepNode));
+
+    /// Remove dead entries at the end of `generation`.
+    fn sweep(&mut self, generation: u64);
+
+    /// Visit the age of each value kept by [`Retention::Lru`] which wasn't used
+    /// in `generation`, ordered by when it was last used and then stored.
+    fn unused_lru(&self, generation: u64, visit: &mut dyn FnMut((u64, u64)));
+
+    /// Remove values kept by [`Retention::Lru`] which weren't used in
+    /// `generation` and whose age is at most `newest`.
+    fn evict_lru(&mut self, generation: u64, newest: (u64, u64));
+
+    /// Remove entries stored at or after `checkpoint`.
+    fn rollback(&mut self, checkpoint: u64);
+
+    /// Describe each stored value to `visit`, given the current `generation`.
+    fn inspect(&self, generation: u64, visit: &mut dyn FnMut(EntryInfo<'_>));
+
+    /// Returns the number of bytes accounted to the stored values.
+    fn bytes(&self) -> usize;
+
+    /// Describe the number and size of the stored values.
+    fn size(&self) -> NamespaceSize;
+
+    /// Visit when each value which may be evicted for the byte budget was last
+    /// rooted, along with its size. Only values which haven't been used since
+    /// the last GC are visited if `unused_only` is true.
+    fn rooted(&self, unused_only: bool, visit: &mut dyn FnMut((u64, usize)));
+
+    /// Remove values visited by `rooted` which were last rooted at or before
+    /// `newest`, returning the number of values and bytes removed.
+    fn evict_rooted(&mut self, unused_only: bool, newest: u64) -> (usize, usize);
 }
 
 impl_downcast!(Storage);
 
-/// Describes the outcome of garbage collection for a cached value.
-#[derive(Clone, Copy, Debug, PartialEq)]
+/// Describes the outcome of garbage collection for a cached value. Ordered
+/// from weakest to strongest, so that a value is as live as its strongest
+/// dependent.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
 enum Liveness {
-    /// The value is still live.
-    Live,
     /// The value should be dropped.
     Dead,
+    /// The value hasn't been used but should be kept, along with its
+    /// dependencies, because of a [`Retention`] policy.
+    Retained,
+    /// The value is still live.
+    Live,
 }
 
 /// The type of a dynamic cache query, used to shard storage in a fashion
@@ -1,8 +1,12 @@
+#[cfg(feature = "persist")]
+use super::persist::Saved;
 use super::{
-    cache_cell::CacheCell,
+    cache_cell::{CacheCell, Rooted, Stored},
     dep_node::{DepNode, Dependent},
-    Storage,
+    EntryInfo, NamespaceSize, Storage,
 };
+#[cfg(feature = "persist")]
+use hashbrown::hash_map::Entry;
 use hashbrown::{
     hash_map::{DefaultHashBuilder, RawEntryMut},
     HashMap,
@@ -17,24 +21,41 @@ use std::{
 };
 
 /// The result of failing to find a `key` in a cache with matching input. Passed
-/// back to [`Namespace::store`] to initialize a value in the cache.
+/// back to [`Namespace::store`] or [`Namespace::store_many`], whichever matches
+/// its [`Lookup`], to initialize a value in the cache.
 #[derive(Clone, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct KeyMiss<'k, K: ?Sized, I, H> {
     inner: Result<Hashed<&'k K, H>, &'k K>,
+    lookup: Lookup,
     dependent: Dependent,
     node: Option<DepNode>,
     input: I,
 }
 
+/// The storage in which a [`KeyMiss`] looked for its key.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) enum Lookup {
+    /// Scopes with a single input, see [`Namespace::get`].
+    One,
+    /// Scopes with several inputs, see [`Namespace::get_many`].
+    Many,
+}
+
 impl<'k, K: ?Sized, I, H> KeyMiss<'k, K, I, H> {
-    fn hashed(h: Hashed<&'k K, H>, input: I, node: Option<DepNode>, dependent: Dependent) -> Self {
-        Self { inner: Ok(h), node, dependent, input }
+    fn hashed(
+        h: Hashed<&'k K, H>,
+        lookup: Lookup,
+        input: I,
+        node: Option<DepNode>,
+        dependent: Dependent,
+    ) -> Self {
+        Self { inner: Ok(h), lookup, node, dependent, input }
     }
 
-    pub(crate) fn just_key(k: &'k K, input: I, dependent: Dependent) -> Self {
+    pub(crate) fn just_key(k: &'k K, lookup: Lookup, input: I, dependent: Dependent) -> Self {
         let node = DepNode::new(dependent);
         let dependent = node.as_dependent();
-        Self { inner: Err(k), dependent, node: Some(node), input }
+        Self { inner: Err(k), lookup, dependent, node: Some(node), input }
     }
 
     pub(crate) fn init<R>(&self, op: impl FnOnce(&I) -> R) -> R {
@@ -50,6 +71,7 @@ where
     fn fmt(&self, f: &mut Formatter) -> FmtResult {
         f.debug_struct("KeyMiss")
             .field("inner", &self.inner)
+            .field("lookup", &self.lookup)
             .field("dependent", &self.dependent)
             .field("node", &self.node)
             .field("input", &self.input)
@@ -83,14 +105,24 @@ where
 #[derive(Clone)]
 pub(crate) struct Namespace<Scope, Input, Output, H = DefaultHashBuilder> {
     inner: HashMap<Scope, CacheCell<Input, Output>, H>,
+    /// Scopes which hold several inputs, most recently used first. Shares
+    /// `inner`'s hasher.
+    many: HashMap<Scope, Vec<CacheCell<Input, Output>>, H>,
+    /// The number of bytes accounted to all of the cells.
+    bytes: usize,
 }
 
 impl<Scope, Input, Output, H> Default for Namespace<Scope, Input, Output, H>
 where
-    H: Default,
+    H: Clone + Default,
 {
     fn default() -> Self {
-        Self { inner: Default::default() }
+        let hasher = H::default();
+        Self {
+            inner: HashMap::with_hasher(hasher.clone()),
+            many: HashMap::with_hasher(hasher),
+            bytes: 0,
+        }
     }
 }
 
@@ -137,6 +169,7 @@ where
         key: &'k Key,
         arg: &Arg,
         dependent: Dependent,
+        now: Rooted,
     ) -> Result<&Output, KeyMiss<'k, Key, Input, H>>
     where
         Key: Eq + Hash + ?Sized,
@@ -146,25 +179,102 @@ where
     {
         let hashed = self.hashed(key);
         if let Some((_, cell)) = self.entry(&hashed) {
-            cell.get(arg, dependent).map_err(|d| KeyMiss::hashed(hashed, arg.to_owned(), None, d))
+            cell.get(arg, dependent, now)
+                .map_err(|d| KeyMiss::hashed(hashed, Lookup::One, arg.to_owned(), None, d))
         } else {
             let node = DepNode::new(dependent);
             let new_dep = node.as_dependent();
-            Err(KeyMiss::hashed(hashed, arg.to_owned(), Some(node), new_dep))
+            Err(KeyMiss::hashed(hashed, Lookup::One, arg.to_owned(), Some(node), new_dep))
+        }
+    }
+
+    /// Like [`Namespace::get`], looking for `arg` among all of the inputs
+    /// stored for `key` by [`Namespace::store_many`]. Hits become the most
+    /// recently used input for `key`.
+    pub fn get_many<'k, Key, Arg>(
+        &mut self,
+        key: &'k Key,
+        arg: &Arg,
+        dependent: Dependent,
+        now: Rooted,
+    ) -> Result<&Output, KeyMiss<'k, Key, Input, H>>
+    where
+        Key: Eq + Hash + ?Sized,
+        Scope: Borrow<Key>,
+        Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+        Input: Borrow<Arg>,
+    {
+        let hashed = self.hashed(key);
+        let entry = self.many.raw_entry_mut().from_hash(hashed.hash, |q| q.borrow().eq(hashed.key));
+        if let RawEntryMut::Occupied(occ) = entry {
+            let cells = occ.into_mut();
+            if let Some(i) = cells.iter().position(|c| c.has_input(arg)) {
+                cells[..=i].rotate_right(1);
+                return Ok(cells[0].read(dependent, now));
+            }
         }
+
+        let node = DepNode::new(dependent);
+        let new_dep = node.as_dependent();
+        Err(KeyMiss::hashed(hashed, Lookup::Many, arg.to_owned(), Some(node), new_dep))
     }
 
-    pub fn store<Key>(&mut self, miss: KeyMiss<'_, Key, Input, H>, output: Output)
+    /// Store `output` as the most recently used input for the missed key,
+    /// dropping the least recently used inputs beyond `capacity`.
+    ///
+    /// # Panics
+    ///
+    /// If the miss wasn't returned by [`Namespace::get_many`].
+    pub fn store_many<Key>(
+        &mut self,
+        miss: KeyMiss<'_, Key, Input, H>,
+        output: Output,
+        stored: Stored,
+        capacity: usize,
+    ) where
+        Key: Eq + Hash + ToOwned<Owned = Scope> + ?Sized,
+        Scope: Borrow<Key>,
+    {
+        assert_eq!(miss.lookup, Lookup::Many, "store_many needs a miss from get_many");
+        let hashed = miss.inner.unwrap_or_else(|k| self.hashed(k));
+        let node = miss.node.expect("misses from get_many always create a fresh node");
+        let cell = CacheCell::new(miss.input, output, node, stored);
+
+        let entry = self.many.raw_entry_mut().from_hash(hashed.hash, |q| q.borrow().eq(hashed.key));
+        let cells = match entry {
+            RawEntryMut::Occupied(occ) => occ.into_mut(),
+            RawEntryMut::Vacant(vac) => vac.insert(hashed.key.to_owned(), Vec::new()).1,
+        };
+        cells.insert(0, cell);
+        let keep = capacity.max(1).min(cells.len());
+        let dropped: usize = cells.drain(keep..).map(|c| c.size()).sum();
+        self.bytes = self.bytes + stored.size - dropped;
+    }
+
+    /// Store `output` for the missed key.
+    ///
+    /// # Panics
+    ///
+    /// If the miss wasn't returned by [`Namespace::get`].
+    pub fn store<Key>(&mut self, miss: KeyMiss<'_, Key, Input, H>, output: Output, stored: Stored)
     where
         Key: Eq + Hash + ToOwned<Owned = Scope> + ?Sized,
         Scope: Borrow<Key>,
     {
+        assert_eq!(miss.lookup, Lookup::One, "store needs a miss from get, not get_many");
         let dependent = miss.dependent;
         let hashed = miss.inner.unwrap_or_else(|k| self.hashed(k));
-        match self.entry_mut(&hashed) {
+        let replaced = match self.entry_mut(&hashed) {
             RawEntryMut::Occupied(occ) => {
-                assert!(miss.node.is_none(), "mustn't create nodes that aren't used");
-                occ.into_mut().store(miss.input, output, dependent);
+                let cell = occ.into_mut();
+                if let Some(node) = miss.node {
+                    // a reentrant query stored this scope while we were initializing it, and
+                    // the values we read during init depend on our node rather than the cell's
+                    cell.replace_dep(node);
+                }
+                let replaced = cell.size();
+                cell.store(miss.input, output, dependent, stored);
+                replaced
             }
             RawEntryMut::Vacant(vac) => {
                 vac.insert(
@@ -173,10 +283,101 @@ where
                         miss.input,
                         output,
                         miss.node.expect("if no cell present, we must have created a fresh node"),
+                        stored,
                     ),
                 );
+                0
+            }
+        };
+        self.bytes = self.bytes + stored.size - replaced;
+    }
+}
+
+#[cfg(feature = "persist")]
+impl<Scope, Input, Output, H> Namespace<Scope, Input, Output, H>
+where
+    Scope: Eq + Hash,
+    H: BuildHasher,
+{
+    /// Borrow every cell's contents for a snapshot.
+    pub fn saved(&self) -> Saved<&Scope, &Input, &Output> {
+        let single = self.inner.iter().map(|(scope, cell)| {
+            let (input, output, size) = cell.saved();
+            (scope, input, output, size)
+        });
+        let many = self
+            .many
+            .iter()
+            .map(|(scope, cells)| (scope, cells.iter().map(CacheCell::saved).collect()));
+        Saved { single: single.collect(), many: many.collect() }
+    }
+
+    /// Insert dead cells for the saved values of any scopes which don't
+    /// already have cells, sized as they were saved.
+    pub fn restore(&mut self, saved: Saved<Scope, Input, Output>, stored: Stored) {
+        let mut bytes = 0;
+        let mut restored = |input, output, size| {
+            bytes += size;
+            CacheCell::new(input, output, DepNode::dead(), Stored { size, ..stored })
+        };
+
+        for (scope, input, output, size) in saved.single {
+            if let Entry::Vacant(vacant) = self.inner.entry(scope) {
+                vacant.insert(restored(input, output, size));
+            }
+        }
+        for (scope, cells) in saved.many {
+            if let Entry::Vacant(vacant) = self.many.entry(scope) {
+                let cells = cells.into_iter().map(|(i, o, size)| restored(i, o, size));
+                vacant.insert(cells.collect());
             }
         }
+        self.bytes += bytes;
+    }
+}
+
+impl<Scope, Input, Output, H> Namespace<Scope, Input, Output, H> {
+    /// Iterate over every cell along with its scope.
+    fn cells(&self) -> impl Iterator<Item = (&Scope, &CacheCell<Input, Output>)> {
+        let many =
+            self.many.iter().flat_map(|(scope, cells)| cells.iter().map(move |c| (scope, c)));
+        self.inner.iter().chain(many)
+    }
+
+    fn cells_mut(&mut self) -> impl Iterator<Item = &mut CacheCell<Input, Output>> {
+        self.inner.values_mut().chain(self.many.values_mut().flatten())
+    }
+
+    /// Keep only the cells for which `keep` returns true, dropping any scopes
+    /// which no longer hold a cell. Returns the number of cells and bytes
+    /// dropped.
+    fn retain_cells(
+        &mut self,
+        mut keep: impl FnMut(&mut CacheCell<Input, Output>) -> bool,
+    ) -> (usize, usize) {
+        let (mut values, mut bytes) = (0, 0);
+        let mut keep = |c: &mut CacheCell<Input, Output>| {
+            let kept = keep(c);
+            if !kept {
+                values += 1;
+                bytes += c.size();
+            }
+            kept
+        };
+        self.inner.retain(|_, c| keep(c));
+        self.many.retain(|_, cells| {
+            let mut i = 0;
+            while i < cells.len() {
+                if keep(&mut cells[i]) {
+                    i += 1;
+                } else {
+                    cells.remove(i);
+                }
+            }
+            !cells.is_empty()
+        });
+        self.bytes -= bytes;
+        (values, bytes)
     }
 }
 
@@ -187,17 +388,65 @@ where
     Output: 'static,
     H: 'static,
 {
-    fn mark(&mut self) {
-        self.inner.values_mut().for_each(CacheCell::update_liveness);
+    fn retain(&mut self, generation: u64) {
+        self.cells_mut().for_each(|c| c.retain(generation));
+    }
+
+    fn dep_nodes(&self, visit: &mut dyn FnMut(&DepNode)) {
+        self.cells().for_each(|(_, c)| visit(c.dep()));
     }
 
-    fn sweep(&mut self) {
-        self.inner.retain(|_, c| {
-            let keep = c.is_live();
+    fn sweep(&mut self, generation: u64) {
+        self.retain_cells(|c| {
+            let live = c.is_live();
+            if live {
+                c.survive(generation);
+            }
+            let keep = live || c.is_retained();
             c.mark_dead();
             keep
         });
     }
+
+    fn unused_lru(&self, generation: u64, visit: &mut dyn FnMut((u64, u64))) {
+        self.cells().filter_map(|(_, c)| c.unused_lru(generation)).for_each(visit);
+    }
+
+    fn evict_lru(&mut self, generation: u64, newest: (u64, u64)) {
+        self.retain_cells(|c| !matches!(c.unused_lru(generation), Some(age) if age <= newest));
+    }
+
+    fn rollback(&mut self, checkpoint: u64) {
+        self.retain_cells(|c| !c.stored_since(checkpoint));
+    }
+
+    fn inspect(&self, generation: u64, visit: &mut dyn FnMut(EntryInfo<'_>)) {
+        self.cells().for_each(|(scope, cell)| visit(cell.info(scope, generation)));
+    }
+
+    fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    fn size(&self) -> NamespaceSize {
+        NamespaceSize {
+            scope_type: type_name::<Scope>(),
+            input_type: type_name::<Input>(),
+            output_type: type_name::<Output>(),
+            values: self.cells().count(),
+            bytes: self.bytes,
+        }
+    }
+
+    fn rooted(&self, unused_only: bool, visit: &mut dyn FnMut((u64, usize))) {
+        self.cells()
+            .filter(|(_, c)| !(unused_only && c.is_live()))
+            .for_each(|(_, c)| visit((c.last_rooted(), c.size())));
+    }
+
+    fn evict_rooted(&mut self, unused_only: bool, newest: u64) -> (usize, usize) {
+        self.retain_cells(|c| (unused_only && c.is_live()) || c.last_rooted() > newest)
+    }
 }
 
 impl<Scope, Input, Output, H> Debug for Namespace<Scope, Input, Output, H> {
@@ -0,0 +1,59 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error,
+    fmt::{Display, Formatter, Result as FmtResult},
+};
+
+/// The error returned when a cache can't write or read a snapshot. A snapshot
+/// which fails to restore leaves the cache unchanged.
+#[derive(Debug)]
+pub struct PersistError {
+    name: Option<String>,
+    source: serde_json::Error,
+}
+
+impl PersistError {
+    /// An error with the snapshot as a whole, rather than a single query type.
+    pub(crate) fn snapshot(source: serde_json::Error) -> Self {
+        Self { name: None, source }
+    }
+
+    /// An error with the values of the query type registered as `name`.
+    pub(crate) fn query(name: &str, source: serde_json::Error) -> Self {
+        Self { name: Some(name.to_owned()), source }
+    }
+
+    /// Returns the name of the query type whose values couldn't be written or
+    /// read, or `None` if the error was with the snapshot as a whole.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+}
+
+impl Display for PersistError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match &self.name {
+            Some(name) => write!(f, "couldn't persist the values of `{}`: {}", name, self.source),
+            None => write!(f, "couldn't persist the snapshot: {}", self.source),
+        }
+    }
+}
+
+impl Error for PersistError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// The values stored for a query type, as written to a snapshot. Each value
+/// is saved with the number of bytes accounted to it.
+#[derive(Deserialize, Serialize)]
+pub(crate) struct Saved<Scope, Input, Output> {
+    /// Values stored by scopes with a single input.
+    pub single: Vec<(Scope, Input, Output, usize)>,
+    /// Values stored by scopes with several inputs, most recently used first.
+    pub many: Vec<(Scope, SavedCells<Input, Output>)>,
+}
+
+/// The inputs and outputs saved for a single scope, with their sizes.
+pub(crate) type SavedCells<Input, Output> = Vec<(Input, Output, usize)>;
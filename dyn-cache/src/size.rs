@@ -0,0 +1,132 @@
+use std::{mem::size_of, rc::Rc, sync::Arc};
+
+/// Estimates the memory held by a cached output, including any heap
+/// allocations it owns, so that a cache can keep its values within a byte
+/// budget. Outputs are weighed when stored with [`crate::CacheEntry::weigh`],
+/// otherwise only their shallow size is counted.
+///
+/// ```
+/// use dyn_cache::CacheSize;
+///
+/// let name = String::with_capacity(16);
+/// assert_eq!(name.cache_size(), std::mem::size_of::<String>() + 16);
+///
+/// let scores: Vec<u32> = vec![1, 2, 3];
+/// assert_eq!(scores.cache_size(), std::mem::size_of::<Vec<u32>>() + 3 * 4);
+/// ```
+pub trait CacheSize {
+    /// Returns the number of bytes held by this value, including its own size.
+    fn cache_size(&self) -> usize;
+}
+
+macro_rules! shallow_cache_size {
+    ($($ty:ty),+) => {
+        $(
+            impl CacheSize for $ty {
+                fn cache_size(&self) -> usize {
+                    size_of::<Self>()
+                }
+            }
+        )+
+    };
+}
+
+shallow_cache_size!(
+    (),
+    bool,
+    char,
+    u8,
+    u16,
+    u32,
+    u64,
+    u128,
+    usize,
+    i8,
+    i16,
+    i32,
+    i64,
+    i128,
+    isize,
+    f32,
+    f64
+);
+
+impl CacheSize for String {
+    fn cache_size(&self) -> usize {
+        size_of::<Self>() + self.capacity()
+    }
+}
+
+impl<T: CacheSize> CacheSize for Vec<T> {
+    fn cache_size(&self) -> usize {
+        let spare = (self.capacity() - self.len()) * size_of::<T>();
+        size_of::<Self>() + spare + self.iter().map(CacheSize::cache_size).sum::<usize>()
+    }
+}
+
+impl<T: CacheSize> CacheSize for Box<T> {
+    fn cache_size(&self) -> usize {
+        size_of::<Self>() + (**self).cache_size()
+    }
+}
+
+impl<T: CacheSize> CacheSize for Option<T> {
+    fn cache_size(&self) -> usize {
+        let owned = self.as_ref().map_or(0, |v| v.cache_size() - size_of::<T>());
+        size_of::<Self>() + owned
+    }
+}
+
+/// Shared pointers count the whole of their contents towards each cache value
+/// which holds them.
+impl<T: CacheSize> CacheSize for Rc<T> {
+    fn cache_size(&self) -> usize {
+        size_of::<Self>() + (**self).cache_size()
+    }
+}
+
+/// Shared pointers count the whole of their contents towards each cache value
+/// which holds them.
+impl<T: CacheSize> CacheSize for Arc<T> {
+    fn cache_size(&self) -> usize {
+        size_of::<Self>() + (**self).cache_size()
+    }
+}
+
+impl<A: CacheSize, B: CacheSize> CacheSize for (A, B) {
+    fn cache_size(&self) -> usize {
+        let owned = (self.0.cache_size() - size_of::<A>()) + (self.1.cache_size() - size_of::<B>());
+        size_of::<Self>() + owned
+    }
+}
+
+/// The memory used by the values of a single query type in a cache. Passed to
+/// the function provided to [`crate::local::LocalCache::namespace_sizes`] or
+/// [`crate::sync::SendCache::namespace_sizes`].
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct NamespaceSize {
+    /// The type name of the query's scope.
+    pub scope_type: &'static str,
+    /// The type name of the query's input.
+    pub input_type: &'static str,
+    /// The type name of the query's output.
+    pub output_type: &'static str,
+    /// The number of values stored for the query type.
+    pub values: usize,
+    /// The number of bytes accounted to the stored values.
+    pub bytes: usize,
+}
+
+/// Values dropped from a cache to keep it within its byte budget, returned by
+/// `store` and `gc` on [`crate::local::LocalCache`] and
+/// [`crate::sync::SendCache`]. `gc` includes the values evicted by stores since
+/// the previous GC.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Evictions {
+    /// The number of values dropped.
+    pub values: usize,
+    /// The number of bytes accounted to the dropped values.
+    pub bytes: usize,
+    /// The number of bytes accounted to the values remaining in the cache.
+    pub remaining_bytes: usize,
+}
@@ -0,0 +1,483 @@
+use super::{SendCache, SharedSendCache};
+use crate::CacheMiss;
+use hash_hasher::HashBuildHasher;
+use hashbrown::{hash_map::DefaultHashBuilder, HashMap};
+use parking_lot::Condvar;
+use std::{
+    any::{Any, TypeId},
+    borrow::Borrow,
+    future::Future,
+    hash::{BuildHasher, Hash, Hasher},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll, Waker},
+    thread::{self, ThreadId},
+};
+
+/// Initializations in progress in a [`SendCache`], bucketed by the hash of
+/// their query type and scope.
+#[derive(Debug, Default)]
+pub(crate) struct Flights {
+    hasher: DefaultHashBuilder,
+    /// We use a [`hash_hasher::HashBuildHasher`] here because the keys are
+    /// already hashes.
+    flights: HashMap<u64, Vec<Flight>, HashBuildHasher>,
+}
+
+impl Flights {
+    fn hash<Scope, Input, Output, Key>(&self, key: &Key) -> u64
+    where
+        Scope: 'static,
+        Input: 'static,
+        Output: 'static,
+        Key: Hash + ?Sized,
+    {
+        let mut hasher = self.hasher.build_hasher();
+        TypeId::of::<(Scope, Input, Output)>().hash(&mut hasher);
+        key.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Returns the initialization in progress for `arg` under `key`, if any.
+    fn find<Key, Scope, Arg, Input, Output>(&mut self, key: &Key, arg: &Arg) -> Option<&mut Flight>
+    where
+        Key: Eq + Hash + ?Sized,
+        Scope: 'static + Borrow<Key>,
+        Arg: PartialEq<Input> + ?Sized,
+        Input: 'static,
+        Output: 'static,
+    {
+        let hash = self.hash::<Scope, Input, Output, Key>(key);
+        let bucket = self.flights.get_mut(&hash)?;
+        bucket.iter_mut().find(|f| f.is_for::<Key, Scope, Arg, Input, Output>(key, arg))
+    }
+
+    fn begin<Scope, Input, Output>(&mut self, scope: Scope, input: Input)
+    where
+        Scope: 'static + Hash + Send,
+        Input: 'static + Send,
+        Output: 'static,
+    {
+        let hash = self.hash::<Scope, Input, Output, Scope>(&scope);
+        let flight = Flight {
+            query: TypeId::of::<(Scope, Input, Output)>(),
+            scope_and_input: Box::new((scope, input)),
+            leader: thread::current().id(),
+            done: Default::default(),
+            wakers: Vec::new(),
+        };
+        self.flights.entry(hash).or_default().push(flight);
+    }
+
+    /// Remove the initialization in progress for `arg` under `key`, if any.
+    fn end<Key, Scope, Arg, Input, Output>(&mut self, key: &Key, arg: &Arg) -> Option<Flight>
+    where
+        Key: Eq + Hash + ?Sized,
+        Scope: 'static + Borrow<Key>,
+        Arg: PartialEq<Input> + ?Sized,
+        Input: 'static,
+        Output: 'static,
+    {
+        let hash = self.hash::<Scope, Input, Output, Key>(key);
+        let bucket = self.flights.get_mut(&hash)?;
+        let index =
+            bucket.iter().position(|f| f.is_for::<Key, Scope, Arg, Input, Output>(key, arg))?;
+        let flight = bucket.swap_remove(index);
+        if bucket.is_empty() {
+            self.flights.remove(&hash);
+        }
+        Some(flight)
+    }
+}
+
+/// An initialization in progress for a scope and input, which other callers
+/// of [`SharedSendCache::cache_with_single_flight`] wait for.
+#[derive(Debug)]
+struct Flight {
+    /// The query type being initialized.
+    query: TypeId,
+    /// The `(Scope, Input)` being initialized.
+    scope_and_input: Box<dyn Any + Send>,
+    /// The thread running the initialization.
+    leader: ThreadId,
+    /// Notified when the initialization completes or panics.
+    done: Arc<Condvar>,
+    /// Woken when the initialization completes or panics.
+    wakers: Vec<Waker>,
+}
+
+impl Flight {
+    fn is_for<Key, Scope, Arg, Input, Output>(&self, key: &Key, arg: &Arg) -> bool
+    where
+        Key: Eq + ?Sized,
+        Scope: 'static + Borrow<Key>,
+        Arg: PartialEq<Input> + ?Sized,
+        Input: 'static,
+        Output: 'static,
+    {
+        let scope_and_input = self.scope_and_input.downcast_ref::<(Scope, Input)>();
+        self.query == TypeId::of::<(Scope, Input, Output)>()
+            && matches!(scope_and_input, Some((s, i)) if s.borrow() == key && arg == i)
+    }
+
+    /// Notify every waiting caller.
+    fn land(self) {
+        self.done.notify_all();
+        self.wakers.into_iter().for_each(Waker::wake);
+    }
+}
+
+/// The outcome of a caller's attempt to read a value with single flight.
+enum Joined<Miss, Ret> {
+    /// The value was stored, and this was returned from `with`.
+    Hit(Ret),
+    /// The caller has started a flight and must initialize the value.
+    Lead(Miss),
+    /// Another caller is initializing the value.
+    Wait(Arc<Condvar>),
+}
+
+impl SendCache {
+    /// Read the value for `key` and `arg`, start a flight to initialize it, or
+    /// return the flight which is already initializing it. If `waker` is
+    /// provided, it's woken when an existing flight lands.
+    fn join_flight<'k, Key, Scope, Arg, Input, Output, Ret>(
+        &mut self,
+        key: &'k Key,
+        arg: &Arg,
+        with: &mut Option<impl FnOnce(&Output) -> Ret>,
+        waker: Option<&Waker>,
+    ) -> Joined<CacheMiss<'k, Key, Scope, Input, Output>, Ret>
+    where
+        Key: Eq + Hash + ToOwned<Owned = Scope> + ?Sized,
+        Scope: 'static + Borrow<Key> + Eq + Hash + Send,
+        Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+        Input: 'static + Borrow<Arg> + Send,
+        Output: 'static + Send,
+    {
+        if let Some(flight) = self.flights.find::<Key, Scope, Arg, Input, Output>(key, arg) {
+            assert_ne!(
+                flight.leader,
+                thread::current().id(),
+                "a single flight initialization must not wait for itself",
+            );
+            if let Some(waker) = waker {
+                if !flight.wakers.iter().any(|w| w.will_wake(waker)) {
+                    flight.wakers.push(waker.clone());
+                }
+            }
+            return Joined::Wait(flight.done.clone());
+        }
+
+        match self.get(key, arg) {
+            Ok(stored) => Joined::Hit(with.take().expect("with is only called once")(stored)),
+            Err(miss) => {
+                self.flights.begin::<Scope, Input, Output>(key.to_owned(), arg.to_owned());
+                Joined::Lead(miss)
+            }
+        }
+    }
+
+    /// Notify any callers waiting for the flight for `key` and `arg`.
+    fn land_flight<Key, Scope, Arg, Input, Output>(&mut self, key: &Key, arg: &Arg)
+    where
+        Key: Eq + Hash + ?Sized,
+        Scope: 'static + Borrow<Key>,
+        Arg: PartialEq<Input> + ?Sized,
+        Input: 'static,
+        Output: 'static,
+    {
+        if let Some(flight) = self.flights.end::<Key, Scope, Arg, Input, Output>(key, arg) {
+            flight.land();
+        }
+    }
+}
+
+impl SharedSendCache {
+    /// Like [`SharedSendCache::cache_with`], but only one caller at a time
+    /// runs `init` for a given `key` and `arg`. Callers which miss while
+    /// another thread is initializing the same scope and input block until
+    /// it finishes and then read its value. If the initializing thread
+    /// panics, one of the waiting callers runs `init` instead.
+    ///
+    /// An `init` which waits for its own scope and input panics rather than
+    /// deadlocking, but initializations on different threads which wait for
+    /// each other will deadlock.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use dyn_cache::sync::SharedSendCache;
+    /// use std::{
+    ///     sync::atomic::{AtomicUsize, Ordering},
+    ///     thread,
+    ///     time::Duration,
+    /// };
+    ///
+    /// let storage = SharedSendCache::default();
+    /// static CALLS: AtomicUsize = AtomicUsize::new(0);
+    /// let expensive = |&n: &u32| {
+    ///     CALLS.fetch_add(1, Ordering::SeqCst);
+    ///     thread::sleep(Duration::from_millis(50));
+    ///     n * 2
+    /// };
+    ///
+    /// let workers = (0..4)
+    ///     .map(|_| {
+    ///         let storage = storage.clone();
+    ///         thread::spawn(move || storage.cache_with_single_flight(&'a', &21, expensive, |n| *n))
+    ///     })
+    ///     .collect::<Vec<_>>();
+    ///
+    /// for worker in workers {
+    ///     assert_eq!(worker.join().unwrap(), 42);
+    /// }
+    /// assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+    /// ```
+    pub fn cache_with_single_flight<Key, Scope, Arg, Input, Output, Ret>(
+        &self,
+        key: &Key,
+        arg: &Arg,
+        init: impl FnOnce(&Input) -> Output,
+        with: impl FnOnce(&Output) -> Ret,
+    ) -> Ret
+    where
+        Key: Eq + Hash + ToOwned<Owned = Scope> + ?Sized,
+        Scope: 'static + Borrow<Key> + Eq + Hash + Send,
+        Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+        Input: 'static + Borrow<Arg> + Send,
+        Output: 'static + Send,
+    {
+        let mut with = Some(with);
+        let mut cache = self.inner.lock();
+        let miss = loop {
+            match cache.join_flight(key, arg, &mut with, None) {
+                Joined::Hit(ret) => return ret,
+                Joined::Lead(miss) => break miss,
+                Joined::Wait(done) => done.wait(&mut cache),
+            }
+        };
+        drop(cache);
+        self.lead_flight(key, arg, miss, init, with.expect("with is only called once"))
+    }
+
+    /// Like [`SharedSendCache::cache_with_single_flight`], returning a future
+    /// which waits for another caller's initialization without blocking its
+    /// thread. `init` still runs synchronously when the future is polled.
+    pub async fn cache_with_single_flight_async<Key, Scope, Arg, Input, Output, Ret>(
+        &self,
+        key: &Key,
+        arg: &Arg,
+        init: impl FnOnce(&Input) -> Output,
+        with: impl FnOnce(&Output) -> Ret,
+    ) -> Ret
+    where
+        Key: Eq + Hash + ToOwned<Owned = Scope> + ?Sized,
+        Scope: 'static + Borrow<Key> + Eq + Hash + Send,
+        Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+        Input: 'static + Borrow<Arg> + Send,
+        Output: 'static + Send,
+    {
+        let mut with = Some(with);
+        let joined = PollFn(|cx: &mut Context<'_>| {
+            match self.inner.lock().join_flight(key, arg, &mut with, Some(cx.waker())) {
+                Joined::Hit(ret) => Poll::Ready(Ok(ret)),
+                Joined::Lead(miss) => Poll::Ready(Err(miss)),
+                Joined::Wait(_) => Poll::Pending,
+            }
+        });
+        match joined.await {
+            Ok(ret) => ret,
+            Err(miss) => {
+                self.lead_flight(key, arg, miss, init, with.expect("with is only called once"))
+            }
+        }
+    }
+
+    /// Initialize and store the value for a flight this caller started,
+    /// landing the flight afterwards or if `init` panics.
+    fn lead_flight<Key, Scope, Arg, Input, Output, Ret>(
+        &self,
+        key: &Key,
+        arg: &Arg,
+        miss: CacheMiss<'_, Key, Scope, Input, Output>,
+        init: impl FnOnce(&Input) -> Output,
+        with: impl FnOnce(&Output) -> Ret,
+    ) -> Ret
+    where
+        Key: Eq + Hash + ToOwned<Owned = Scope> + ?Sized,
+        Scope: 'static + Borrow<Key> + Eq + Hash + Send,
+        Arg: PartialEq<Input> + ?Sized,
+        Input: 'static + Send,
+        Output: 'static + Send,
+    {
+        let _landing = OnDrop(|| {
+            self.inner.lock().land_flight::<Key, Scope, Arg, Input, Output>(key, arg);
+        });
+        let (to_store, to_return) = miss.init(|arg| {
+            let store = init(arg);
+            let ret = with(&store);
+            (store, ret)
+        });
+        self.inner.lock().store(to_store);
+        to_return
+    }
+}
+
+/// Runs a function when dropped, including while unwinding.
+struct OnDrop<F: FnMut()>(F);
+
+impl<F: FnMut()> Drop for OnDrop<F> {
+    fn drop(&mut self) {
+        (self.0)();
+    }
+}
+
+/// A future which calls a function each time it's polled.
+struct PollFn<F>(F);
+
+impl<F, T> Future for PollFn<F>
+where
+    F: FnMut(&mut Context<'_>) -> Poll<T> + Unpin,
+{
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        (self.get_mut().0)(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        panic::{catch_unwind, AssertUnwindSafe},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            mpsc, Barrier,
+        },
+        time::Duration,
+    };
+
+    /// Returns an `init` which counts its calls and takes a while, panicking
+    /// on the calls listed in `panics`.
+    fn slow_double(
+        calls: &Arc<AtomicUsize>,
+        panics: &'static [usize],
+    ) -> impl FnOnce(&u32) -> u32 + Clone {
+        let calls = calls.clone();
+        move |&n| {
+            let call = calls.fetch_add(1, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(50));
+            assert!(!panics.contains(&call), "init panicked");
+            n * 2
+        }
+    }
+
+    #[test]
+    fn concurrent_misses_share_one_initialization() {
+        let storage = SharedSendCache::default();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let start = Arc::new(Barrier::new(8));
+        let workers = (0..8)
+            .map(|i| {
+                let (storage, start, init) =
+                    (storage.clone(), start.clone(), slow_double(&calls, &[]));
+                thread::spawn(move || {
+                    start.wait();
+                    let input = if i < 6 { 1u32 } else { 2 };
+                    // single storage keeps one input per scope, give each its own
+                    storage.cache_with_single_flight(&input, &input, init, |n| *n)
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let mut results = workers.into_iter().map(|w| w.join().unwrap()).collect::<Vec<_>>();
+        results.sort_unstable();
+        assert_eq!(results, vec![2, 2, 2, 2, 2, 2, 4, 4]);
+        assert_eq!(calls.load(Ordering::SeqCst), 2, "one initialization per input");
+    }
+
+    #[test]
+    fn waiters_take_over_from_a_panicking_leader() {
+        let storage = SharedSendCache::default();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let init = slow_double(&calls, &[0]);
+        let (started, leading) = mpsc::channel();
+
+        let leader = {
+            let (storage, init) = (storage.clone(), init.clone());
+            let init = move |n: &u32| {
+                started.send(()).unwrap();
+                init(n)
+            };
+            thread::spawn(move || storage.cache_with_single_flight(&'a', &1u32, init, |n| *n))
+        };
+        leading.recv().unwrap();
+        assert_eq!(storage.cache_with_single_flight(&'a', &1u32, init, |n| *n), 2);
+        assert!(leader.join().is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn async_waiters_are_woken() {
+        let storage = SharedSendCache::default();
+        let (started, leading) = mpsc::channel();
+        let (release, released) = mpsc::channel();
+
+        let leader = {
+            let storage = storage.clone();
+            let init = move |&n: &u32| {
+                started.send(()).unwrap();
+                released.recv().unwrap();
+                n * 2
+            };
+            thread::spawn(move || storage.cache_with_single_flight(&'a', &1u32, init, |n| *n))
+        };
+        leading.recv().unwrap();
+
+        let init = |_: &u32| -> u32 { unreachable!("the leader initializes") };
+        let mut waiter =
+            Box::pin(storage.cache_with_single_flight_async(&'a', &1u32, init, |n| *n));
+        let waited = futures::executor::block_on(async {
+            for _ in 0..3 {
+                assert!(futures::poll!(waiter.as_mut()).is_pending(), "waits for the leader");
+            }
+            let wakers = {
+                let cache = storage.inner.lock();
+                let flight = cache.flights.flights.values().flatten().next().unwrap();
+                flight.wakers.len()
+            };
+            assert_eq!(wakers, 1, "repeated polls keep one waker");
+            release.send(()).unwrap();
+            waiter.await
+        });
+        assert_eq!(waited, 2);
+        assert_eq!(leader.join().unwrap(), 2);
+    }
+
+    #[test]
+    fn returns_can_borrow_from_the_caller() {
+        let storage = SharedSendCache::default();
+        let labels = [String::from("zero"), String::from("one")];
+        let label = |n: &u32| -> &str { &labels[*n as usize] };
+        assert_eq!(storage.cache_with_single_flight(&'a', &1u32, |&n| n, label), "one");
+        let waiter = storage.cache_with_single_flight_async(&'a', &1u32, |&n| n, label);
+        assert_eq!(futures::executor::block_on(waiter), "one");
+    }
+
+    #[test]
+    fn waiting_for_own_flight_panics() {
+        let storage = SharedSendCache::default();
+        let reentered = catch_unwind(AssertUnwindSafe(|| {
+            storage.cache_with_single_flight(
+                &'a',
+                &1u32,
+                |_| storage.cache_with_single_flight(&'a', &1u32, |&n| n, |n| *n),
+                |n| *n,
+            )
+        }));
+        assert!(reentered.is_err());
+        assert_eq!(storage.cache_with_single_flight(&'a', &1u32, |&n| n + 1, |n| *n), 2);
+    }
+}
@@ -33,13 +33,23 @@
 //! [`runtime::Revision`]s at the same callsite and are dropped from the cache
 //! at the end of the first revision where they were not used.
 //!
+//! ## Memoized Subtrees
+//!
+//! Each revision runs the whole root function, relying on the cache to make
+//! repeated work cheap. Subtrees wrapped in [`memo`] go further, skipping their
+//! body entirely unless a state variable read within it has changed.
+//!
 //! ## State
 //!
 //! State variables are stored in the cache and can be mutated in between
 //! revisions. They are declared with the [`cache_state`] and [`state`]
 //! functions which return a [`Commit`] for reading the current value and a
 //! [`Key`] for updating it. Updates to state variables wake the runtime,
-//! initiating a new revision.
+//! initiating a new revision. Values computed from state variables can be
+//! [`derived`] from their keys and are only recomputed after new commits.
+//! State which changes in response to typed actions can be declared with
+//! [`reducer`], and state which several runtimes read and write can be
+//! declared in a [`runtime::SharedStore`] with [`shared_state`].
 //!
 //! ## Loading Futures
 //!
@@ -50,6 +60,44 @@
 //! each revision. If a revision occurs without referencing the pending future,
 //! the task is cancelled.
 //!
+//! ## Effects
+//!
+//! Side effects which should happen after a revision has been computed can be
+//! scheduled with [`effect`] and [`layout_effect`]. They're re-run when their
+//! argument changes and can return a cleanup function which is called before
+//! they run again or when they're no longer called in a revision.
+//!
+//! ## Loading
+//!
+//! Futures can be loaded into a revision with [`load`] and its variants, or
+//! with [`load_result`] for futures which can fail, time out, or need retrying.
+//! Streams can be consumed with [`load_stream`] and [`load_stream_fold`].
+//!
+//! ## Suspense
+//!
+//! Rather than matching on the result of every [`load`], a subtree can be
+//! wrapped in a [`suspense`] boundary which renders fallback content while any
+//! of the subtree's loads are pending.
+//!
+//! ## Errors
+//!
+//! A panic during a revision normally unwinds out of the runtime. Wrapping a
+//! subtree in [`catch_panic`] renders fallback content instead, discarding the
+//! subtree's partial work.
+//!
+//! ## Persistence
+//!
+//! With the `persist` feature, state variables created with
+//! [`persisted_state`] are given stable names under which a runtime can
+//! snapshot their values, allowing a later runtime to start where it left off.
+//!
+//! ## Threads
+//!
+//! The functions in this module expect to be run by a [`runtime::Runtime`],
+//! which doesn't require any of its values to be thread-safe. The [`sync`]
+//! module offers the same functions for a [`runtime::SendRuntime`], which can
+//! be moved between threads and spawns its loaders onto a thread pool.
+//!
 //! [moxie-dom]: https://docs.rs/moxie-dom
 //! [topo]: https://docs.rs/topo/
 
@@ -57,18 +105,24 @@
 #![deny(clippy::all, missing_docs)]
 
 pub mod runtime;
+pub mod sync;
 pub mod testing;
 
-use crate::runtime::{Context, Var};
+use crate::runtime::{Batch, Context, Phase, Priority, Var};
+use futures::stream::Stream;
+use illicit::AsContext;
 use parking_lot::Mutex;
 use std::{
+    any::Any,
     borrow::Borrow,
     fmt::{Debug, Display, Formatter, Result as FmtResult},
     future::Future,
     hash::{Hash, Hasher},
     ops::Deref,
+    rc::Rc,
     sync::Arc,
     task::Poll,
+    time::Duration,
 };
 use topo::CallId;
 
@@ -134,7 +188,63 @@ where
     Output: 'static,
     Ret: 'static,
 {
-    rt.cache.cache_with(&CallId::current(), arg, init, with)
+    rt.cache_with(&CallId::current(), arg, init, with)
+}
+
+/// Like [`cache_with`], keeping the cached value according to `retention`
+/// rather than the runtime's default policy, which drops it at the end of the
+/// first revision in which it isn't used. Values which a retained value used
+/// while it was initialized, like its state variables, are kept along with it.
+///
+/// # Example
+///
+/// ```
+/// use moxie::{
+///     cache_with_retention,
+///     runtime::{Retention, RunLoop},
+/// };
+/// use std::{cell::Cell, rc::Rc};
+///
+/// let (show_tab, num_renders) = (Rc::new(Cell::new(true)), Rc::new(Cell::new(0)));
+/// let (show, renders) = (show_tab.clone(), num_renders.clone());
+/// let mut rt = RunLoop::new(move || {
+///     if show.get() {
+///         let render = |&(): &()| renders.set(renders.get() + 1);
+///         cache_with_retention(&(), Retention::Revisions(2), render, |_| ());
+///     }
+/// });
+///
+/// rt.run_once();
+/// show_tab.set(false);
+/// rt.run_once();
+/// rt.run_once();
+/// show_tab.set(true);
+/// rt.run_once();
+/// assert_eq!(num_renders.get(), 1, "kept while hidden for two revisions");
+///
+/// show_tab.set(false);
+/// for _ in 0..3 {
+///     rt.run_once();
+/// }
+/// show_tab.set(true);
+/// rt.run_once();
+/// assert_eq!(num_renders.get(), 2, "dropped after the third");
+/// ```
+#[topo::nested]
+#[illicit::from_env(rt: &Context)]
+pub fn cache_with_retention<Arg, Input, Output, Ret>(
+    arg: &Arg,
+    retention: runtime::Retention,
+    init: impl FnOnce(&Input) -> Output,
+    with: impl FnOnce(&Output) -> Ret,
+) -> Ret
+where
+    Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+    Input: Borrow<Arg> + 'static,
+    Output: 'static,
+    Ret: 'static,
+{
+    rt.cache_with_retention(&CallId::current(), arg, Some(retention), init, with)
 }
 
 /// Caches `init` once in the current [`topo::CallId`]. Runs `with` on every
@@ -175,7 +285,7 @@ where
     Output: 'static,
     Ret: 'static,
 {
-    rt.cache.cache_with(&CallId::current(), &(), |&()| init(), with)
+    rt.cache_with(&CallId::current(), &(), |&()| init(), with)
 }
 
 /// Memoizes `init` at this callsite, cloning a cached `Output` if it exists and
@@ -227,7 +337,7 @@ where
     Input: Borrow<Arg> + 'static,
     Output: Clone + 'static,
 {
-    rt.cache.cache(&CallId::current(), arg, init)
+    rt.cache_with(&CallId::current(), arg, init, Clone::clone)
 }
 
 /// Runs `init` once per [`topo::CallId`]. The provided value
@@ -261,7 +371,62 @@ pub fn once<Output>(init: impl FnOnce() -> Output) -> Output
 where
     Output: Clone + 'static,
 {
-    rt.cache.cache(&CallId::current(), &(), |()| init())
+    rt.cache_with(&CallId::current(), &(), |()| init(), Clone::clone)
+}
+
+/// Run `body` as a memoized subtree, returning a clone of its output. On later
+/// revisions `body` is only re-run if `capture` has changed or a state variable
+/// read within it has received a commit, otherwise its previous output is
+/// returned.
+///
+/// The cached values, state variables, and effects used by `body` are kept
+/// alive while its output is being reused, just as if it had run. Memoized
+/// subtrees can be nested, in which case a commit to a state variable read by
+/// an inner subtree re-runs each of the subtrees enclosing it, but none of
+/// their other memoized subtrees.
+///
+/// Only state variables rooted within `body` are tracked. Any other values it
+/// reads which can change between revisions, including [`Key`]s and
+/// [`illicit`] environment values from outside the subtree, should be passed
+/// in `capture`.
+///
+/// # Example
+///
+/// ```
+/// use moxie::{memo, runtime::RunLoop, state};
+/// use std::cell::Cell;
+///
+/// let runs = (Cell::new(0), Cell::new(0));
+/// let mut rt = RunLoop::new(|| {
+///     let first = memo(&(), || {
+///         runs.0.set(runs.0.get() + 1);
+///         state(|| 0u8)
+///     });
+///     let second = memo(&(), || {
+///         runs.1.set(runs.1.get() + 1);
+///         state(|| 0u8)
+///     });
+///     (first, second)
+/// });
+///
+/// let ((_, first), _) = rt.run_once();
+/// rt.run_once();
+/// assert_eq!(runs, (Cell::new(1), Cell::new(1)), "clean subtrees are reused");
+///
+/// first.set(1);
+/// let ((first, _), (second, _)) = rt.run_once();
+/// assert_eq!((*first, *second), (1, 0));
+/// assert_eq!(runs, (Cell::new(2), Cell::new(1)), "only the dirty subtree re-ran");
+/// ```
+#[topo::nested]
+#[illicit::from_env(rt: &Context)]
+pub fn memo<Arg, Input, Output>(capture: &Arg, body: impl FnOnce() -> Output) -> Output
+where
+    Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+    Input: Borrow<Arg> + 'static,
+    Output: Clone + 'static,
+{
+    rt.memo(&CallId::current(), capture, body)
 }
 
 /// Root a state variable at this callsite, returning a [`Key`] to the state
@@ -383,6 +548,263 @@ where
     rt.cache_state(&CallId::current(), arg, init)
 }
 
+/// Root a state variable at this callsite which is saved in the runtime's
+/// [`runtime::Runtime::snapshot`] under `name`, returning a [`Key`] to the
+/// state variable.
+///
+/// If the runtime was given a value for `name` with
+/// [`runtime::Runtime::hydrate`], the state variable starts from that value
+/// instead of calling `init`. Names should be unique within a runtime, and
+/// stable across builds of the program which need to read each other's
+/// snapshots. Re-initializes the state variable if `name` changes.
+///
+/// Requires the `persist` feature.
+///
+/// # Example
+///
+/// ```
+/// use moxie::{persisted_state, runtime::Runtime};
+///
+/// let mut rt = Runtime::new();
+/// let (draft, draft_key) = rt.run_once(|| persisted_state("draft", String::new));
+/// assert_eq!(*draft, "");
+/// draft_key.set(String::from("hello"));
+///
+/// let snapshot = rt.snapshot().unwrap();
+/// assert_eq!(serde_json::to_string(&snapshot).unwrap(), r#"{"draft":"hello"}"#);
+/// ```
+#[cfg(feature = "persist")]
+#[topo::nested]
+#[illicit::from_env(rt: &Context)]
+pub fn persisted_state<Output>(
+    name: &str,
+    init: impl FnOnce() -> Output,
+) -> (Commit<Output>, Key<Output>)
+where
+    Output: serde::Serialize + serde::de::DeserializeOwned + 'static,
+{
+    rt.persisted_state(&CallId::current(), name, init)
+}
+
+/// Root the state variable named `name` in `store`, returning a [`Key`] to it.
+/// The state variable is created with `init` if the store doesn't have one
+/// for `name` yet.
+///
+/// Each runtime which roots a state variable from `store` is woken by commits
+/// to any of the store's state variables, whichever runtime made them, until
+/// it goes a revision without rooting any of them. The first runtime to run a
+/// revision after a commit applies it, and every runtime sees the new value
+/// from its next revision.
+///
+/// # Panics
+///
+/// If the store's state variable for `name` holds a type other than `State`.
+///
+/// # Example
+///
+/// ```
+/// use moxie::{
+///     runtime::{Runtime, SharedStore},
+///     shared_state,
+///     testing::BoolWaker,
+/// };
+///
+/// let store = SharedStore::new();
+/// let (mut first, mut second) = (Runtime::new(), Runtime::new());
+/// let second_woken = BoolWaker::new();
+/// second.set_state_change_waker(futures::task::waker(second_woken.clone()));
+///
+/// let root = move || shared_state(&store, "theme", || "light");
+/// let (_, theme) = first.run_once(root.clone());
+/// assert_eq!(*second.run_once(root.clone()).0, "light");
+///
+/// theme.set("dark");
+/// assert!(second_woken.is_woken(), "commits wake every runtime using the store");
+/// assert_eq!(*second.run_once(root).0, "dark");
+/// ```
+#[topo::nested]
+#[illicit::from_env(rt: &Context)]
+pub fn shared_state<State>(
+    store: &runtime::SharedStore,
+    name: &str,
+    init: impl FnOnce() -> State,
+) -> (Commit<State>, Key<State>)
+where
+    State: Send + Sync + 'static,
+{
+    rt.shared_state(&CallId::current(), store, name, init)
+}
+
+/// Derive a value from the latest commits to one or more state variables,
+/// recomputing it only when one of them has received a new commit.
+///
+/// `sources` is either a single [`Key`] or a tuple of them, see [`Sources`].
+/// Each revision, the current commit to every source is compared by identity to
+/// the commits the cached value was derived from. If any have changed, `derive`
+/// is called with the new commits. Like [`cache`], the derived value is
+/// dropped at the end of a revision in which this call was not made.
+///
+/// Pending commits aren't applied here, so a derived value always agrees with
+/// the [`state`] calls made earlier in the revision. Commits which haven't been
+/// applied yet are picked up in a later revision.
+///
+/// # Example
+///
+/// ```
+/// use moxie::{derived, runtime::RunLoop, state};
+/// use std::cell::Cell;
+///
+/// let num_derived = Cell::new(0);
+/// let mut rt = RunLoop::new(|| {
+///     let (_, first) = state(|| 1u64);
+///     let (_, second) = state(|| 2u64);
+///     let sum = derived(&(first.clone(), second.clone()), |(first, second)| {
+///         num_derived.set(num_derived.get() + 1);
+///         **first + **second
+///     });
+///     (*sum, first, second)
+/// });
+///
+/// let (sum, first, second) = rt.run_once();
+/// assert_eq!(sum, 3);
+/// assert_eq!(rt.run_once().0, 3);
+/// assert_eq!(num_derived.get(), 1, "sources haven't changed");
+///
+/// first.set(1); // no-op, doesn't create a new commit
+/// assert_eq!(rt.run_once().0, 3);
+/// assert_eq!(num_derived.get(), 1);
+///
+/// second.set(5);
+/// assert_eq!(rt.run_once().0, 6);
+/// assert_eq!(num_derived.get(), 2, "recomputed once after a new commit");
+/// ```
+#[topo::nested]
+#[illicit::from_env(rt: &Context)]
+pub fn derived<S, Output>(sources: &S, derive: impl FnOnce(&S::Commits) -> Output) -> Commit<Output>
+where
+    S: Sources + 'static,
+    Output: 'static,
+{
+    let id = CallId::current();
+    let observed = Observed::<S>(sources.latest());
+    rt.cache.cache(&id, &observed, |observed| Commit { id, inner: Arc::new(derive(&observed.0)) })
+}
+
+/// Root a state variable at this callsite which is updated by dispatching
+/// actions to `reduce`, returning the latest commit and a [`Dispatch`] for
+/// sending actions.
+///
+/// Each action is reduced against the latest value when it's dispatched. The
+/// runtime is woken by the first action dispatched after a revision, and any
+/// further actions dispatched before the next revision don't wake it again.
+/// Actions dispatched during a [`batch`] are staged with the batch's other
+/// commits.
+///
+/// Actions dispatched from within `reduce` or middleware are reduced after the
+/// current action, in the order they were dispatched.
+///
+/// `reduce` is only captured when the state variable is initialized, so it
+/// should not capture values which change between revisions.
+///
+/// # Example
+///
+/// ```
+/// use futures::task::waker;
+/// use moxie::{reducer, runtime::RunLoop, testing::BoolWaker};
+///
+/// enum Action {
+///     Add(u32),
+///     Reset,
+/// }
+///
+/// let mut rt = RunLoop::new(|| {
+///     reducer(
+///         || 0u32,
+///         |total, action| match action {
+///             Action::Add(n) => total + n,
+///             Action::Reset => 0,
+///         },
+///     )
+/// });
+/// let track_wakes = BoolWaker::new();
+/// rt.set_state_change_waker(waker(track_wakes.clone()));
+///
+/// let (total, dispatch) = rt.run_once();
+/// assert_eq!(*total, 0);
+///
+/// dispatch.dispatch(Action::Add(2));
+/// dispatch.dispatch(Action::Reset);
+/// dispatch.dispatch(Action::Add(5));
+/// assert!(track_wakes.is_woken());
+///
+/// let (total, _) = rt.run_once();
+/// assert_eq!(*total, 5);
+/// ```
+#[topo::nested]
+#[illicit::from_env(rt: &Context)]
+pub fn reducer<State, Action>(
+    init: impl FnOnce() -> State,
+    reduce: impl Fn(&State, Action) -> State + 'static,
+) -> (Commit<State>, Dispatch<Action>)
+where
+    State: 'static,
+    Action: 'static,
+{
+    rt.reducer(&CallId::current(), init, reduce, ())
+}
+
+/// Calls [`reducer`], passing each dispatched action and the state it produces
+/// through `middleware`. Like `reduce`, `middleware` is only captured when the
+/// state variable is initialized.
+///
+/// # Example
+///
+/// ```
+/// use moxie::{reducer_with_middleware, runtime::RunLoop, Middleware};
+/// use std::{cell::RefCell, rc::Rc};
+///
+/// #[derive(Clone, Debug)]
+/// struct Add(i32);
+///
+/// #[derive(Default)]
+/// struct Log(Rc<RefCell<Vec<String>>>);
+///
+/// impl Middleware<i32, Add> for Log {
+///     fn before(&self, state: &i32, action: Add) -> Option<Add> {
+///         self.0.borrow_mut().push(format!("{} + {:?}", state, action));
+///         if action.0 < 0 {
+///             None
+///         } else {
+///             Some(action)
+///         }
+///     }
+/// }
+///
+/// let log = Rc::new(RefCell::new(vec![]));
+/// let log2 = log.clone();
+/// let mut rt =
+///     RunLoop::new(move || reducer_with_middleware(|| 1, |n, Add(m)| n + m, Log(log2.clone())));
+///
+/// let (_, dispatch) = rt.run_once();
+/// dispatch.dispatch(Add(2));
+/// dispatch.dispatch(Add(-10));
+/// assert_eq!(*rt.run_once().0, 3, "negative actions are dropped");
+/// assert_eq!(*log.borrow(), vec!["1 + Add(2)", "3 + Add(-10)"]);
+/// ```
+#[topo::nested]
+#[illicit::from_env(rt: &Context)]
+pub fn reducer_with_middleware<State, Action>(
+    init: impl FnOnce() -> State,
+    reduce: impl Fn(&State, Action) -> State + 'static,
+    middleware: impl Middleware<State, Action> + 'static,
+) -> (Commit<State>, Dispatch<Action>)
+where
+    State: 'static,
+    Action: 'static,
+{
+    rt.reducer(&CallId::current(), init, reduce, middleware)
+}
+
 /// Load a value from the future returned by `init` whenever `capture` changes,
 /// returning the result of calling `with` with the loaded value. Cancels the
 /// running future after any revision during which this call was not made.
@@ -461,7 +883,51 @@ where
     Output: 'static,
     Ret: 'static,
 {
-    rt.load_with(&CallId::current(), arg, init, with)
+    rt.load_with(&CallId::current(), Priority::Normal, arg, init, with)
+}
+
+/// Calls [`load_with`], spawning the loading future with the executor for
+/// `priority`. See [`runtime::Runtime::set_priority_executor`].
+///
+/// # Example
+///
+/// ```
+/// use futures::{executor::LocalPool, future::ready};
+/// use moxie::{
+///     load_with_priority,
+///     runtime::{Priority, RunLoop},
+/// };
+/// use std::task::Poll;
+///
+/// let mut rt = RunLoop::new(|| load_with_priority(Priority::Idle, &(), |()| ready(1), |n| *n));
+///
+/// let mut urgent = LocalPool::new();
+/// let mut idle = LocalPool::new();
+/// rt.set_task_executor(urgent.spawner());
+/// rt.set_priority_executor(Priority::Idle, idle.spawner());
+///
+/// assert_eq!(rt.run_once(), Poll::Pending);
+/// urgent.run_until_stalled();
+/// assert_eq!(rt.run_once(), Poll::Pending, "idle work has its own executor");
+/// idle.run_until_stalled();
+/// assert_eq!(rt.run_once(), Poll::Ready(1));
+/// ```
+#[topo::nested]
+#[illicit::from_env(rt: &Context)]
+pub fn load_with_priority<Arg, Input, Fut, Output, Ret>(
+    priority: Priority,
+    arg: &Arg,
+    init: impl FnOnce(&Input) -> Fut,
+    with: impl FnOnce(&Output) -> Ret,
+) -> Poll<Ret>
+where
+    Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+    Input: Borrow<Arg> + 'static,
+    Fut: Future<Output = Output> + 'static,
+    Output: 'static,
+    Ret: 'static,
+{
+    rt.load_with(&CallId::current(), priority, arg, init, with)
 }
 
 /// Calls [`load_with`] but never re-initializes the loading future.
@@ -501,7 +967,24 @@ where
     Output: 'static,
     Ret: 'static,
 {
-    rt.load_with(&CallId::current(), &(), |()| init(), with)
+    rt.load_with(&CallId::current(), Priority::Normal, &(), |()| init(), with)
+}
+
+/// Calls [`load_once_with`], spawning the loading future with the executor for
+/// `priority`. See [`runtime::Runtime::set_priority_executor`].
+#[topo::nested]
+#[illicit::from_env(rt: &Context)]
+pub fn load_once_with_priority<Fut, Output, Ret>(
+    priority: Priority,
+    init: impl FnOnce() -> Fut,
+    with: impl FnOnce(&Output) -> Ret,
+) -> Poll<Ret>
+where
+    Fut: Future<Output = Output> + 'static,
+    Output: 'static,
+    Ret: 'static,
+{
+    rt.load_with(&CallId::current(), priority, &(), |()| init(), with)
 }
 
 /// Calls [`load_with`], never re-initializes the loading future, and clones the
@@ -538,7 +1021,7 @@ where
     Fut: Future<Output = Output> + 'static,
     Output: Clone + 'static,
 {
-    rt.load_with(&CallId::current(), &(), |()| init(), Clone::clone)
+    rt.load_with(&CallId::current(), Priority::Normal, &(), |()| init(), Clone::clone)
 }
 
 /// Load a value from a future, cloning it on subsequent revisions after it is
@@ -614,58 +1097,688 @@ where
     Fut: Future<Output = Output> + 'static,
     Output: Clone + 'static,
 {
-    rt.load_with(&CallId::current(), capture, init, Clone::clone)
+    rt.load_with(&CallId::current(), Priority::Normal, capture, init, Clone::clone)
 }
 
-/// A read-only pointer to the value of a state variable *at a particular
-/// revision*.
+/// Consume a stream, returning the latest item it has produced or
+/// `Poll::Pending` if it hasn't produced any yet. Re-initializes the stream if
+/// `capture` changes from previous revisions, and cancels it after any revision
+/// during which this was not called.
 ///
-/// Reads through a commit are not guaranteed to be the latest value visible to
-/// the runtime. Commits should be shared and used within the context of a
-/// single [`crate::runtime::Revision`], being re-loaded from the state variable
-/// each time.
+/// Each item is committed to a state variable, waking the runtime.
 ///
-/// See [`state`] and [`cache_state`] for examples.
-#[derive(Eq, Hash, PartialEq)]
-pub struct Commit<State> {
-    id: CallId,
-    inner: Arc<State>,
-}
-
-impl<State> Clone for Commit<State> {
-    fn clone(&self) -> Self {
-        Self { id: self.id, inner: Arc::clone(&self.inner) }
-    }
-}
-
-impl<State> Debug for Commit<State>
+/// # Example
+///
+/// ```
+/// use futures::{channel::mpsc, executor::LocalPool};
+/// use moxie::{load_stream, runtime::RunLoop};
+/// use std::task::Poll;
+///
+/// let (send, recv) = mpsc::unbounded();
+/// let recv = std::cell::RefCell::new(Some(recv));
+/// let mut rt = RunLoop::new(|| load_stream(&(), |()| recv.borrow_mut().take().unwrap()));
+///
+/// let mut exec = LocalPool::new();
+/// rt.set_task_executor(exec.spawner());
+///
+/// assert_eq!(rt.run_once(), Poll::Pending);
+/// send.unbounded_send(1).unwrap();
+/// send.unbounded_send(2).unwrap();
+/// exec.run_until_stalled();
+/// assert_eq!(rt.run_once(), Poll::Ready(2));
+/// ```
+#[topo::nested]
+#[illicit::from_env(rt: &Context)]
+pub fn load_stream<Arg, Input, S>(capture: &Arg, init: impl FnOnce(&Input) -> S) -> Poll<S::Item>
 where
-    State: Debug,
+    Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+    Input: Borrow<Arg> + 'static,
+    S: Stream + 'static,
+    S::Item: Clone + 'static,
 {
-    fn fmt(&self, f: &mut Formatter) -> FmtResult {
-        self.inner.fmt(f)
-    }
-}
-
-impl<State> Deref for Commit<State> {
-    type Target = State;
-
-    fn deref(&self) -> &Self::Target {
-        self.inner.deref()
-    }
+    let (id, fold) = (CallId::current(), |_: &_, item| Poll::Ready(item));
+    rt.load_stream(&id, Priority::Normal, capture, init, || Poll::Pending, fold)
 }
 
-impl<State> Display for Commit<State>
+/// Calls [`load_stream`], spawning the stream's task with the executor for
+/// `priority`. See [`runtime::Runtime::set_priority_executor`].
+#[topo::nested]
+#[illicit::from_env(rt: &Context)]
+pub fn load_stream_with_priority<Arg, Input, S>(
+    priority: Priority,
+    capture: &Arg,
+    init: impl FnOnce(&Input) -> S,
+) -> Poll<S::Item>
 where
-    State: Display,
+    Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+    Input: Borrow<Arg> + 'static,
+    S: Stream + 'static,
+    S::Item: Clone + 'static,
 {
-    fn fmt(&self, f: &mut Formatter) -> FmtResult {
-        f.write_fmt(format_args!("{}", self.inner))
-    }
+    let (id, fold) = (CallId::current(), |_: &_, item| Poll::Ready(item));
+    rt.load_stream(&id, priority, capture, init, || Poll::Pending, fold)
 }
 
-/// A `Key` offers access to a state variable. The key allows reads of the state
-/// variable through a snapshot taken when the `Key` was created. Writes are
+/// Consume a stream, returning the result of folding all of the items it has
+/// produced into the value returned by `seed`. Re-initializes the stream and
+/// the folded value if `capture` changes from previous revisions, and cancels
+/// the stream after any revision during which this was not called.
+///
+/// Each folded value is committed to a state variable, waking the runtime.
+/// The load is pending for [`suspense`] boundaries until the stream produces
+/// its first item.
+///
+/// # Example
+///
+/// ```
+/// use futures::{executor::LocalPool, stream};
+/// use moxie::{load_stream_fold, runtime::RunLoop};
+///
+/// let mut rt = RunLoop::new(|| {
+///     load_stream_fold(
+///         &3,
+///         |&n| stream::iter(1..=n),
+///         Vec::new,
+///         |prev, item| {
+///             let mut next = prev.clone();
+///             next.push(item);
+///             next
+///         },
+///     )
+/// });
+///
+/// let mut exec = LocalPool::new();
+/// rt.set_task_executor(exec.spawner());
+///
+/// assert_eq!(rt.run_once(), Vec::<i32>::new());
+/// exec.run_until_stalled();
+/// assert_eq!(rt.run_once(), vec![1, 2, 3]);
+/// ```
+#[topo::nested]
+#[illicit::from_env(rt: &Context)]
+pub fn load_stream_fold<Arg, Input, S, Acc>(
+    capture: &Arg,
+    init: impl FnOnce(&Input) -> S,
+    seed: impl FnOnce() -> Acc,
+    fold: impl FnMut(&Acc, S::Item) -> Acc + 'static,
+) -> Acc
+where
+    Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+    Input: Borrow<Arg> + 'static,
+    S: Stream + 'static,
+    Acc: Clone + 'static,
+{
+    rt.load_stream(&CallId::current(), Priority::Normal, capture, init, seed, fold)
+}
+
+/// Calls [`load_stream_fold`], spawning the stream's task with the executor for
+/// `priority`. See [`runtime::Runtime::set_priority_executor`].
+#[topo::nested]
+#[illicit::from_env(rt: &Context)]
+pub fn load_stream_fold_with_priority<Arg, Input, S, Acc>(
+    priority: Priority,
+    capture: &Arg,
+    init: impl FnOnce(&Input) -> S,
+    seed: impl FnOnce() -> Acc,
+    fold: impl FnMut(&Acc, S::Item) -> Acc + 'static,
+) -> Acc
+where
+    Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+    Input: Borrow<Arg> + 'static,
+    S: Stream + 'static,
+    Acc: Clone + 'static,
+{
+    rt.load_stream(&CallId::current(), priority, capture, init, seed, fold)
+}
+
+/// Load a `Result` from a future, like [`load`], reporting whether the future
+/// is still loading, has failed, or has timed out. Returns a [`Retry`] handle
+/// which restarts the future when used.
+///
+/// Equivalent to [`load_result_with_options`] with the default
+/// [`LoadOptions`].
+///
+/// # Example
+///
+/// ```
+/// use moxie::{load_result, testing::TestRuntime, LoadResult};
+/// use std::cell::Cell;
+///
+/// let attempts = Cell::new(0);
+/// let mut rt = TestRuntime::new();
+/// let root = || {
+///     load_result(&(), |()| {
+///         attempts.set(attempts.get() + 1);
+///         let attempt = attempts.get();
+///         async move {
+///             if attempt == 1 {
+///                 Err("failed")
+///             } else {
+///                 Ok(attempt)
+///             }
+///         }
+///     })
+/// };
+///
+/// assert_eq!(rt.run_once(root).0, LoadResult::Loading);
+/// rt.run_until_stalled();
+/// let (result, retry) = rt.run_once(root);
+/// assert_eq!(result, LoadResult::Failed("failed"));
+///
+/// retry.retry();
+/// assert_eq!(rt.run_once(root).0, LoadResult::Loading);
+/// rt.run_until_stalled();
+/// assert_eq!(rt.run_once(root).0, LoadResult::Ready(2));
+/// ```
+#[topo::nested]
+#[illicit::from_env(rt: &Context)]
+pub fn load_result<Arg, Input, Fut, Output, Error>(
+    capture: &Arg,
+    init: impl FnOnce(&Input) -> Fut,
+) -> (LoadResult<Output, Error>, Retry)
+where
+    Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+    Input: Borrow<Arg> + 'static,
+    Fut: Future<Output = Result<Output, Error>> + 'static,
+    Output: Clone + 'static,
+    Error: Clone + 'static,
+{
+    let (result, retry) =
+        rt.load_result(&CallId::current(), capture, &LoadOptions::default(), init);
+    ((*result).clone(), retry)
+}
+
+/// Load a `Result` from a future, re-initializing it when `capture` changes or
+/// when the returned [`Retry`] handle is used. The future is cancelled after
+/// any revision in which this isn't called, when it's restarted, or when
+/// `options` specifies a timeout which elapses first.
+///
+/// # Example
+///
+/// ```
+/// use futures::future::pending;
+/// use moxie::{load_result_with_options, testing::TestRuntime, LoadOptions, LoadResult};
+/// use std::time::Duration;
+///
+/// let mut rt = TestRuntime::new();
+/// let options = LoadOptions::new().timeout(Duration::from_secs(1));
+/// let root =
+///     || load_result_with_options(&(), options.clone(), |()| pending::<Result<(), ()>>()).0;
+///
+/// assert_eq!(rt.run_once(root), LoadResult::Loading);
+/// rt.advance(Duration::from_secs(1));
+/// assert_eq!(rt.run_once(root), LoadResult::TimedOut);
+/// ```
+#[topo::nested]
+#[illicit::from_env(rt: &Context)]
+pub fn load_result_with_options<Arg, Input, Fut, Output, Error>(
+    capture: &Arg,
+    options: LoadOptions,
+    init: impl FnOnce(&Input) -> Fut,
+) -> (LoadResult<Output, Error>, Retry)
+where
+    Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+    Input: Borrow<Arg> + 'static,
+    Fut: Future<Output = Result<Output, Error>> + 'static,
+    Output: Clone + 'static,
+    Error: Clone + 'static,
+{
+    let (result, retry) = rt.load_result(&CallId::current(), capture, &options, init);
+    ((*result).clone(), retry)
+}
+
+/// The state of a load started by [`load_result`].
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum LoadResult<Output, Error> {
+    /// The future hasn't completed yet.
+    Loading,
+    /// The future completed successfully.
+    Ready(Output),
+    /// The future completed with an error.
+    Failed(Error),
+    /// The future didn't complete before the timeout in its [`LoadOptions`].
+    TimedOut,
+    /// The load has a timeout but the runtime has no [`runtime::Timer`] to
+    /// measure it with, so the future wasn't started. Set one with
+    /// [`runtime::Runtime::set_timer`].
+    NoTimer,
+}
+
+impl<Output, Error> LoadResult<Output, Error> {
+    /// Returns true if the future hasn't completed yet.
+    pub fn is_loading(&self) -> bool {
+        matches!(self, LoadResult::Loading)
+    }
+
+    /// Returns true if the future completed successfully.
+    pub fn is_ready(&self) -> bool {
+        matches!(self, LoadResult::Ready(_))
+    }
+
+    /// Returns the loaded value, if any.
+    pub fn ready(self) -> Option<Output> {
+        match self {
+            LoadResult::Ready(output) => Some(output),
+            _ => None,
+        }
+    }
+}
+
+impl<Output, Error> From<Result<Output, Error>> for LoadResult<Output, Error> {
+    fn from(result: Result<Output, Error>) -> Self {
+        match result {
+            Ok(output) => LoadResult::Ready(output),
+            Err(error) => LoadResult::Failed(error),
+        }
+    }
+}
+
+/// Configures the loads made by [`load_result_with_options`].
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct LoadOptions {
+    timeout: Option<Duration>,
+    keep_previous: bool,
+    priority: Priority,
+}
+
+impl Default for LoadOptions {
+    fn default() -> Self {
+        Self { timeout: None, keep_previous: false, priority: Priority::Normal }
+    }
+}
+
+impl LoadOptions {
+    /// Returns the default options, with no timeout, without keeping previous
+    /// values, and with [`runtime::Priority::Normal`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Spawn the future with the executor for `priority`. See
+    /// [`runtime::Runtime::set_priority_executor`].
+    pub fn priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    /// Cancel the future and report [`LoadResult::TimedOut`] if it doesn't
+    /// complete within `timeout`, measured with the runtime's
+    /// [`runtime::Timer`].
+    ///
+    /// Loads with a timeout report [`LoadResult::NoTimer`] without starting
+    /// their future if the runtime doesn't have a timer, set with
+    /// [`runtime::Runtime::set_timer`].
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Continue reporting a previously loaded [`LoadResult::Ready`] value
+    /// while a new future is loading, rather than reporting
+    /// [`LoadResult::Loading`].
+    pub fn keep_previous(mut self) -> Self {
+        self.keep_previous = true;
+        self
+    }
+}
+
+/// Restarts the future of a [`load_result`] call on the next revision.
+#[derive(Clone, Debug)]
+pub struct Retry {
+    attempts: Key<u64>,
+}
+
+impl Retry {
+    /// Restarts the load's future on the next revision, cancelling it if it's
+    /// still running. Wakes the runtime.
+    pub fn retry(&self) {
+        self.attempts.update(|attempt| Some(attempt + 1));
+    }
+}
+
+/// Runs `op` after the current revision's root function has returned whenever
+/// `arg` changes, calling the cleanup function it returns before running it
+/// again and when this callsite is dropped from the cache.
+///
+/// Effects run at the end of [`runtime::Runtime::run_once`] after any
+/// [`layout_effect`]s and after the revision's cached values have been
+/// collected. Because they run after the revision, `op` and its cleanup must
+/// be `'static`.
+///
+/// # Example
+///
+/// ```
+/// use moxie::{effect, runtime::RunLoop};
+/// use std::{cell::RefCell, rc::Rc};
+///
+/// let log = Rc::new(RefCell::new(vec![]));
+/// let (epoch, mount) = (Rc::new(RefCell::new(0)), Rc::new(RefCell::new(true)));
+///
+/// let mut rt = RunLoop::new(|| {
+///     if *mount.borrow() {
+///         let log = log.clone();
+///         effect(&*epoch.borrow(), move |e| {
+///             let e = *e;
+///             log.borrow_mut().push(format!("start {}", e));
+///             move || log.borrow_mut().push(format!("stop {}", e))
+///         });
+///     }
+///     log.borrow().len()
+/// });
+///
+/// assert_eq!(rt.run_once(), 0, "effects run after the root function");
+/// assert_eq!(*log.borrow(), ["start 0"]);
+///
+/// rt.run_once();
+/// assert_eq!(*log.borrow(), ["start 0"], "only re-run when the argument changes");
+///
+/// *epoch.borrow_mut() = 1;
+/// rt.run_once();
+/// assert_eq!(*log.borrow(), ["start 0", "stop 0", "start 1"]);
+///
+/// *mount.borrow_mut() = false;
+/// rt.run_once();
+/// assert_eq!(*log.borrow(), ["start 0", "stop 0", "start 1", "stop 1"]);
+/// ```
+#[topo::nested]
+#[illicit::from_env(rt: &Context)]
+pub fn effect<Arg, Input, Cleanup>(arg: &Arg, op: impl FnOnce(&Input) -> Cleanup + 'static)
+where
+    Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+    Input: Borrow<Arg> + 'static,
+    Cleanup: FnOnce() + 'static,
+{
+    rt.effect(&CallId::current(), arg, Phase::Passive, op);
+}
+
+/// Like [`effect`], but runs `op` immediately after the root function returns
+/// and before the revision's cached values are collected.
+///
+/// Layout effects are useful for work which must happen before anything else
+/// observes the results of the revision, like measuring or adjusting a view
+/// that the root function has just updated.
+///
+/// # Example
+///
+/// ```
+/// use moxie::{effect, layout_effect, runtime::RunLoop};
+/// use std::{cell::RefCell, rc::Rc};
+///
+/// let log = Rc::new(RefCell::new(vec![]));
+/// let mut rt = RunLoop::new(|| {
+///     let passive = log.clone();
+///     effect(&(), move |()| {
+///         passive.borrow_mut().push("effect");
+///         || ()
+///     });
+///     let layout = log.clone();
+///     layout_effect(&(), move |()| {
+///         layout.borrow_mut().push("layout effect");
+///         || ()
+///     });
+/// });
+///
+/// rt.run_once();
+/// assert_eq!(*log.borrow(), ["layout effect", "effect"]);
+/// ```
+#[topo::nested]
+#[illicit::from_env(rt: &Context)]
+pub fn layout_effect<Arg, Input, Cleanup>(arg: &Arg, op: impl FnOnce(&Input) -> Cleanup + 'static)
+where
+    Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+    Input: Borrow<Arg> + 'static,
+    Cleanup: FnOnce() + 'static,
+{
+    rt.effect(&CallId::current(), arg, Phase::Layout, op);
+}
+
+/// Runs `children` as a suspense boundary, returning the result of `fallback`
+/// instead if any [`load`] or [`load_with`] (or their variants) called by
+/// `children` returned `Poll::Pending` during this revision.
+///
+/// `children` always runs so that its loads can make progress, but its result
+/// is discarded while it's pending. Loads within a nested boundary are only
+/// seen by the innermost one.
+///
+/// See [`suspense_after`] to wait before showing the fallback.
+///
+/// # Example
+///
+/// ```
+/// use futures::{channel::oneshot, executor::LocalPool};
+/// use moxie::{load_once, runtime::RunLoop, suspense};
+///
+/// let (send, recv) = oneshot::channel();
+/// let recv = std::cell::RefCell::new(Some(recv));
+/// let mut rt = RunLoop::new(|| {
+///     suspense(
+///         || {
+///             let recv = recv.borrow_mut().take();
+///             load_once(|| async move { recv.unwrap().await.unwrap() }).map(|n: u8| n.to_string())
+///         },
+///         || std::task::Poll::Ready("loading...".to_string()),
+///     )
+/// });
+///
+/// let mut exec = LocalPool::new();
+/// rt.set_task_executor(exec.spawner());
+///
+/// assert_eq!(rt.run_once(), std::task::Poll::Ready("loading...".to_string()));
+/// send.send(7).unwrap();
+/// exec.run_until_stalled();
+/// assert_eq!(rt.run_once(), std::task::Poll::Ready("7".to_string()));
+/// ```
+#[topo::nested]
+#[illicit::from_env(rt: &Context)]
+pub fn suspense<Ret>(children: impl FnOnce() -> Ret, fallback: impl FnOnce() -> Ret) -> Ret {
+    rt.suspense(&CallId::current(), Duration::default(), children, fallback)
+}
+
+/// Like [`suspense`], but continues returning the result of `children` until
+/// they have been pending for `delay`. The runtime is woken once `delay` has
+/// elapsed, measured with the runtime's [`runtime::Timer`].
+///
+/// Useful for avoiding a flash of fallback content when loads usually complete
+/// quickly.
+///
+/// If `children` are pending and the runtime doesn't have a timer, set with
+/// [`runtime::Runtime::set_timer`], the fallback is shown without any delay
+/// and a warning is logged.
+///
+/// # Example
+///
+/// ```
+/// use futures::future::pending;
+/// use moxie::{load_once, suspense_after, testing::TestRuntime};
+/// use std::{task::Poll, time::Duration};
+///
+/// let mut rt = TestRuntime::new();
+/// let root = || {
+///     suspense_after(
+///         Duration::from_millis(100),
+///         || load_once(pending::<()>).map(|()| "loaded"),
+///         || Poll::Ready("loading..."),
+///     )
+/// };
+///
+/// assert_eq!(rt.run_once(root), Poll::Pending);
+/// rt.advance(Duration::from_millis(100));
+/// assert_eq!(rt.run_once(root), Poll::Ready("loading..."));
+/// ```
+#[topo::nested]
+#[illicit::from_env(rt: &Context)]
+pub fn suspense_after<Ret>(
+    delay: Duration,
+    children: impl FnOnce() -> Ret,
+    fallback: impl FnOnce() -> Ret,
+) -> Ret {
+    rt.suspense(&CallId::current(), delay, children, fallback)
+}
+
+/// Runs `child`, returning the result of `fallback` instead if `child` panics.
+/// Acts as an error boundary: a panic within `child` doesn't unwind through the
+/// rest of the revision.
+///
+/// If `child` panics, any values it stored in the runtime's cache during this
+/// revision are discarded along with any effects it scheduled, so its partial
+/// work won't be observed by later revisions. Cached values it only read are
+/// kept, as is the rest of the runtime's cache.
+///
+/// The caught [`Panicked`] error is passed to `fallback` and is also offered to
+/// it via [`illicit`], so functions called by `fallback` can retrieve it with
+/// `illicit::get::<Panicked>()`.
+///
+/// Note that the panic hook is still called for the caught panic, so a message
+/// will be printed by default.
+///
+/// # Example
+///
+/// ```
+/// use moxie::{catch_panic, runtime::RunLoop, Panicked};
+///
+/// let mut rt = RunLoop::new(|| {
+///     catch_panic(
+///         || -> String { panic!("oh no") },
+///         |_| illicit::expect::<Panicked>().message().unwrap().to_owned(),
+///     )
+/// });
+///
+/// assert_eq!(rt.run_once(), "oh no");
+/// ```
+#[topo::nested]
+#[illicit::from_env(rt: &Context)]
+pub fn catch_panic<Ret>(
+    child: impl FnOnce() -> Ret,
+    fallback: impl FnOnce(&Panicked) -> Ret,
+) -> Ret {
+    match rt.catch_panic(child) {
+        Ok(ret) => ret,
+        Err(panicked) => panicked.offer(|| fallback(&illicit::expect::<Panicked>())),
+    }
+}
+
+/// A panic caught by [`catch_panic`].
+pub struct Panicked {
+    payload: Box<dyn Any + Send>,
+}
+
+impl Panicked {
+    pub(crate) fn new(payload: Box<dyn Any + Send>) -> Self {
+        Self { payload }
+    }
+
+    /// Returns the panic's message if it was a string, as is the case for
+    /// panics raised with `panic!`.
+    pub fn message(&self) -> Option<&str> {
+        if let Some(message) = self.payload.downcast_ref::<&'static str>() {
+            Some(message)
+        } else if let Some(message) = self.payload.downcast_ref::<String>() {
+            Some(message)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the value the panic was raised with.
+    pub fn payload(&self) -> &(dyn Any + Send) {
+        &*self.payload
+    }
+}
+
+impl Debug for Panicked {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("Panicked").field("message", &self.message()).finish()
+    }
+}
+
+/// Runs `op`, grouping all of the commits it makes to state variables into a
+/// single transaction.
+///
+/// Commits made with [`Key::update`] and [`Key::set`] during `op` are staged
+/// rather than enqueued. Reads of [`Key::update`]'s argument see the staged
+/// values, but revisions don't. When `op` returns, the staged commits are
+/// published together and each runtime's state change waker is invoked once.
+/// If `op` panics, the staged commits are discarded.
+///
+/// Calls to `batch` within `op` join the outer batch.
+///
+/// # Example
+///
+/// ```
+/// use futures::task::waker;
+/// use moxie::{batch, runtime::RunLoop, state, testing::BoolWaker};
+///
+/// let mut rt = RunLoop::new(|| (state(|| 0u64), state(|| 0u64)));
+///
+/// let track_wakes = BoolWaker::new();
+/// rt.set_state_change_waker(waker(track_wakes.clone()));
+///
+/// let ((_, first), (_, second)) = rt.run_once();
+///
+/// batch(|| {
+///     first.set(1);
+///     second.update(|prev| Some(prev + 1));
+///     first.update(|prev| Some(prev + 1));
+///     assert!(!track_wakes.is_woken(), "nothing published until the batch closes");
+/// });
+/// assert!(track_wakes.is_woken());
+///
+/// let ((first, _), (second, _)) = rt.run_once();
+/// assert_eq!((*first, *second), (2, 1));
+/// ```
+pub fn batch<R>(op: impl FnOnce() -> R) -> R {
+    Batch::run(op)
+}
+
+/// A read-only pointer to the value of a state variable *at a particular
+/// revision*.
+///
+/// Reads through a commit are not guaranteed to be the latest value visible to
+/// the runtime. Commits should be shared and used within the context of a
+/// single [`crate::runtime::Revision`], being re-loaded from the state variable
+/// each time.
+///
+/// See [`state`] and [`cache_state`] for examples.
+#[derive(Eq, Hash, PartialEq)]
+pub struct Commit<State> {
+    id: CallId,
+    inner: Arc<State>,
+}
+
+impl<State> Clone for Commit<State> {
+    fn clone(&self) -> Self {
+        Self { id: self.id, inner: Arc::clone(&self.inner) }
+    }
+}
+
+impl<State> Debug for Commit<State>
+where
+    State: Debug,
+{
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        self.inner.fmt(f)
+    }
+}
+
+impl<State> Deref for Commit<State> {
+    type Target = State;
+
+    fn deref(&self) -> &Self::Target {
+        self.inner.deref()
+    }
+}
+
+impl<State> Display for Commit<State>
+where
+    State: Display,
+{
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.write_fmt(format_args!("{}", self.inner))
+    }
+}
+
+/// A `Key` offers access to a state variable. The key allows reads of the state
+/// variable through a snapshot taken when the `Key` was created. Writes are
 /// supported with [`Key::update`] and [`Key::set`].
 ///
 /// They are created with the [`cache_state`] and [`state`] functions.
@@ -683,6 +1796,131 @@ impl<State> Key<State> {
         self.id
     }
 
+    /// Returns a stream of the commits enqueued to the state variable after
+    /// this call, for observing it from outside of the runtime. `buffer`
+    /// determines which commits are kept while the stream isn't being polled.
+    /// The stream ends once the state variable has been dropped.
+    ///
+    /// Commits made within a [`batch`] are yielded when the batch closes.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use futures::{executor::block_on, StreamExt};
+    /// use moxie::{runtime::RunLoop, state, WatchBuffer};
+    ///
+    /// let mut rt = RunLoop::new(|| state(|| 0u64));
+    /// let (_, key) = rt.run_once();
+    ///
+    /// let mut all = key.watch(WatchBuffer::Bounded(8));
+    /// let mut latest = key.watch(WatchBuffer::Latest);
+    /// key.set(1);
+    /// key.set(2);
+    ///
+    /// assert_eq!(*block_on(all.next()).unwrap(), 1);
+    /// assert_eq!(*block_on(all.next()).unwrap(), 2);
+    /// assert_eq!(*block_on(latest.next()).unwrap(), 2);
+    /// ```
+    pub fn watch(&self, buffer: WatchBuffer) -> Watch<State> {
+        let channel = runtime::Channel::new(buffer);
+        self.var.lock().watch(channel.clone());
+        Watch { channel }
+    }
+}
+
+/// Determines which commits a [`Watch`] keeps while it isn't being polled.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum WatchBuffer {
+    /// Keep up to this many commits, dropping any new commits while the buffer
+    /// is full. Buffers at least one commit.
+    Bounded(usize),
+    /// Keep only the most recent commit.
+    Latest,
+}
+
+/// A stream of the commits to a state variable, returned by [`Key::watch`].
+pub struct Watch<State> {
+    channel: Arc<runtime::Channel<State>>,
+}
+
+impl<State> Stream for Watch<State> {
+    type Item = Commit<State>;
+
+    fn poll_next(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        self.channel.poll_next(cx)
+    }
+}
+
+impl<State> Debug for Watch<State> {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("Watch").finish()
+    }
+}
+
+/// Sends actions to a state variable created with [`reducer`].
+pub struct Dispatch<Action> {
+    id: CallId,
+    send: Rc<dyn Fn(Action)>,
+}
+
+impl<Action> Dispatch<Action> {
+    /// Returns the `topo::CallId` at which the state variable is bound.
+    pub fn id(&self) -> CallId {
+        self.id
+    }
+
+    /// Reduces `action` against the state variable's latest value, committing
+    /// the result. See [`reducer`] for when the runtime is woken.
+    pub fn dispatch(&self, action: Action) {
+        (self.send)(action);
+    }
+}
+
+impl<Action> Clone for Dispatch<Action> {
+    fn clone(&self) -> Self {
+        Self { id: self.id, send: self.send.clone() }
+    }
+}
+
+impl<Action> Debug for Dispatch<Action> {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("Dispatch").field("id", &self.id).finish()
+    }
+}
+
+/// Observes the actions dispatched to a state variable created with
+/// [`reducer_with_middleware`].
+pub trait Middleware<State, Action> {
+    /// Called with the latest state and each action before it's reduced.
+    /// Returning `None` drops the action.
+    fn before(&self, _state: &State, action: Action) -> Option<Action> {
+        Some(action)
+    }
+
+    /// Called with the state produced by reducing each action, before it's
+    /// committed.
+    fn after(&self, _state: &State) {}
+}
+
+impl<State, Action> Middleware<State, Action> for () {}
+
+impl<State> Key<State>
+where
+    State: 'static,
+{
+    /// Set a new value for the state variable, immediately taking effect.
+    fn force(&self, new: State) {
+        self.var.lock().enqueue_commit(new);
+    }
+
+    // TODO(#197) delete this and remove the Deref impl
+    fn refresh(&mut self) {
+        self.commit_at_root = runtime::Var::root(self.var.clone()).0;
+    }
+
     /// Runs `updater` with a reference to the state variable's latest value,
     /// and enqueues a commit to the variable if `updater` returns `Some`.
     /// Returns the `Revision` at which the state variable was last rooted
@@ -696,6 +1934,9 @@ impl<State> Key<State> {
     /// outside of a `Revision`'s execution, otherwise unpredictable waker
     /// behavior may be obtained.
     ///
+    /// Within a call to [`batch`] the commit is staged instead, and the waker
+    /// is invoked once the batch closes.
+    ///
     /// [Runtime]: crate::runtime::Runtime
     /// [run_once]: crate::runtime::Runtime::run_once
     ///
@@ -733,24 +1974,19 @@ impl<State> Key<State> {
     pub fn update(&self, updater: impl FnOnce(&State) -> Option<State>) {
         let mut var = self.var.lock();
         if let Some(new) = updater(var.latest()) {
-            var.enqueue_commit(new);
+            if let Ok(batch) = illicit::get::<Batch>() {
+                var.stage_commit(new);
+                batch.stage(self.var.clone());
+            } else {
+                var.enqueue_commit(new);
+            }
         }
     }
-
-    /// Set a new value for the state variable, immediately taking effect.
-    fn force(&self, new: State) {
-        self.var.lock().enqueue_commit(new);
-    }
-
-    // TODO(#197) delete this and remove the Deref impl
-    fn refresh(&mut self) {
-        self.commit_at_root = runtime::Var::root(self.var.clone()).0;
-    }
 }
 
 impl<State> Key<State>
 where
-    State: PartialEq,
+    State: PartialEq + 'static,
 {
     /// Commits a new state value if it is unequal to the current value and the
     /// state variable is still live. Has the same properties as
@@ -812,11 +2048,86 @@ impl<State> Hash for Key<State> {
     }
 }
 
+/// One or more state variables from which a value can be [`derived`].
+///
+/// Implemented for [`Key`] and for tuples of up to four `Key`s.
+pub trait Sources {
+    /// The current commits to each state variable.
+    type Commits: Clone + 'static;
+
+    /// Returns the current commits to each state variable, without applying
+    /// any pending commits.
+    fn latest(&self) -> Self::Commits;
+
+    /// Returns true if `first` and `second` are the same commits, compared by
+    /// identity rather than by value.
+    fn same_commits(first: &Self::Commits, second: &Self::Commits) -> bool;
+}
+
+impl<State> Sources for Key<State>
+where
+    State: 'static,
+{
+    type Commits = Commit<State>;
+
+    fn latest(&self) -> Self::Commits {
+        self.var.lock().current_commit()
+    }
+
+    fn same_commits(first: &Self::Commits, second: &Self::Commits) -> bool {
+        Arc::ptr_eq(&first.inner, &second.inner)
+    }
+}
+
+macro_rules! impl_sources_for_tuple {
+    ($($source:ident : $idx:tt),+) => {
+        impl<$($source),+> Sources for ($($source,)+)
+        where
+            $($source: Sources),+
+        {
+            type Commits = ($($source::Commits,)+);
+
+            fn latest(&self) -> Self::Commits {
+                ($(self.$idx.latest(),)+)
+            }
+
+            fn same_commits(first: &Self::Commits, second: &Self::Commits) -> bool {
+                true $(&& $source::same_commits(&first.$idx, &second.$idx))+
+            }
+        }
+    };
+}
+
+impl_sources_for_tuple!(A: 0);
+impl_sources_for_tuple!(A: 0, B: 1);
+impl_sources_for_tuple!(A: 0, B: 1, C: 2);
+impl_sources_for_tuple!(A: 0, B: 1, C: 2, D: 3);
+
+/// The commits from which a [`derived`] value was computed, compared by
+/// identity.
+struct Observed<S: Sources>(S::Commits);
+
+impl<S: Sources> Clone for Observed<S> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<S: Sources> PartialEq for Observed<S> {
+    fn eq(&self, other: &Self) -> bool {
+        S::same_commits(&self.0, &other.0)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::runtime::{Revision, RunLoop};
-    use std::{cell::Cell, collections::HashSet, rc::Rc};
+    use std::{
+        cell::{Cell, RefCell},
+        collections::HashSet,
+        rc::Rc,
+    };
 
     fn with_test_logs(test: impl FnOnce()) {
         tracing::subscriber::with_default(
@@ -1024,4 +2335,499 @@ mod tests {
             "must be no task holding the channel and able to receive a message"
         );
     }
+
+    #[test]
+    fn revisions_during_batch_see_no_staged_commits() {
+        let mut rt = RunLoop::new(|| (state(|| 0u8), state(|| 0u8)));
+        let ((_, first), (_, second)) = rt.run_once();
+
+        batch(|| {
+            first.set(1);
+            let ((first_commit, _), _) = rt.run_once();
+            assert_eq!(*first_commit, 0, "staged commit isn't visible yet");
+            second.set(1);
+        });
+
+        let ((first, _), (second, _)) = rt.run_once();
+        assert_eq!((*first, *second), (1, 1), "both commits land in the same revision");
+    }
+
+    #[test]
+    fn nested_batches_publish_once() {
+        let mut rt = RunLoop::new(|| state(|| 0u8));
+        let wakes = crate::testing::BoolWaker::new();
+        rt.set_state_change_waker(futures::task::waker(wakes.clone()));
+
+        let (_, key) = rt.run_once();
+        batch(|| {
+            key.set(1);
+            batch(|| key.update(|prev| Some(prev + 1)));
+            assert!(!wakes.is_woken(), "inner batch joins the outer one");
+        });
+        assert!(wakes.is_woken());
+        assert_eq!(*rt.run_once().0, 2);
+    }
+
+    #[test]
+    fn panicking_batch_discards_commits() {
+        let mut rt = RunLoop::new(|| state(|| 0u8));
+        let wakes = crate::testing::BoolWaker::new();
+        rt.set_state_change_waker(futures::task::waker(wakes.clone()));
+
+        let (_, key) = rt.run_once();
+        let to_move = key.clone();
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            batch(|| {
+                to_move.set(1);
+                panic!("oh no");
+            })
+        }));
+        assert!(res.is_err());
+        assert!(!wakes.is_woken(), "nothing published");
+        assert_eq!(*rt.run_once().0, 0);
+
+        key.set(2);
+        assert!(wakes.is_woken(), "commits outside of batches still work");
+        assert_eq!(*rt.run_once().0, 2);
+    }
+
+    #[test]
+    fn derived_values_dont_apply_pending_commits() {
+        let mut rt = RunLoop::new(|| {
+            let (commit, key) = state(|| 0u32);
+            key.set(*commit + 1);
+            let doubled = derived(&key, |commit| **commit * 2);
+            (*commit, *doubled)
+        });
+
+        assert_eq!(rt.run_once(), (0, 0), "derived from the same commit as state");
+        assert_eq!(rt.run_once(), (1, 2), "the pending commit is applied next revision");
+    }
+
+    #[test]
+    fn derived_values_are_collected_with_their_callsite() {
+        let num_derived = Cell::new(0);
+        let mut rt = RunLoop::new(|| {
+            let (_, key) = state(|| 0u8);
+            if Revision::current().0 != 2 {
+                derived(&key, |_| num_derived.set(num_derived.get() + 1));
+            }
+        });
+
+        rt.run_once();
+        assert_eq!(num_derived.get(), 1);
+        rt.run_once();
+        assert_eq!(num_derived.get(), 1, "not called this revision");
+        rt.run_once();
+        assert_eq!(num_derived.get(), 2, "previous value was dropped, derived again");
+        rt.run_once();
+        assert_eq!(num_derived.get(), 2);
+    }
+
+    #[test]
+    fn effects_run_in_call_order_after_the_root() {
+        let log = Arc::new(Mutex::new(vec![]));
+        let mut rt = RunLoop::new(|| {
+            for name in &["first", "second", "third"] {
+                let log = log.clone();
+                topo::call(|| {
+                    effect(name, move |name: &&str| {
+                        log.lock().push(*name);
+                        || ()
+                    })
+                });
+            }
+            log.lock().push("root");
+        });
+
+        rt.run_once();
+        assert_eq!(*log.lock(), ["root", "first", "second", "third"]);
+    }
+
+    #[test]
+    fn caught_panics_discard_the_subtree() {
+        let log = Arc::new(Mutex::new(vec![]));
+        let should_panic = Cell::new(false);
+        let mut rt = crate::runtime::Runtime::new();
+        let root = || {
+            let outside = once(|| Arc::new(()));
+            let inside = catch_panic(
+                || {
+                    let inside = once(|| Arc::new(()));
+                    if should_panic.get() {
+                        state(|| ());
+                    }
+                    let log = log.clone();
+                    effect(&should_panic.get(), move |_| {
+                        log.lock().push("effect");
+                        || ()
+                    });
+                    assert!(!should_panic.get(), "asked to panic");
+                    Some(inside)
+                },
+                |panicked| {
+                    assert_eq!(panicked.message(), Some("asked to panic"));
+                    assert!(illicit::get::<Panicked>().is_ok(), "fallback can get the error");
+                    None
+                },
+            );
+            (outside, inside)
+        };
+
+        let (first_outside, first_inside) = rt.run_once(root);
+        let first_inside = first_inside.unwrap();
+        assert_eq!(*log.lock(), ["effect"]);
+
+        should_panic.set(true);
+        let (second_outside, second_inside) = rt.run_once(root);
+        assert!(Arc::ptr_eq(&first_outside, &second_outside), "outside the boundary is kept");
+        assert!(second_inside.is_none());
+        assert_eq!(rt.inspect().state.len(), 0, "state created before the panic is discarded");
+        assert_eq!(*log.lock(), ["effect"], "no effects from the panicking revision");
+
+        should_panic.set(false);
+        let (_, third_inside) = rt.run_once(root);
+        let third_inside = third_inside.unwrap();
+        assert!(Arc::ptr_eq(&first_inside, &third_inside), "values only read are kept");
+        assert_eq!(*log.lock(), ["effect", "effect"]);
+    }
+
+    #[test]
+    fn suspense_boundaries_see_their_own_pending_loads() {
+        use crate::testing::TestRuntime;
+        use std::time::Duration;
+
+        let mut rt = TestRuntime::new();
+        let clock = rt.clock();
+        let root = || {
+            suspense_after(
+                Duration::from_millis(10),
+                || {
+                    let inner =
+                        suspense(|| load_once(futures::future::pending::<()>), || Poll::Ready(()));
+                    let outer = load_once(|| clock.sleep(Duration::from_millis(20)));
+                    (inner, outer)
+                },
+                || (Poll::Pending, Poll::Pending),
+            )
+        };
+
+        assert_eq!(rt.run_once(root), (Poll::Ready(()), Poll::Pending), "delay hasn't elapsed");
+        rt.take_wakes();
+
+        rt.advance(Duration::from_millis(10));
+        assert_eq!(rt.take_wakes(), 1, "the boundary's timer woke the runtime");
+        assert_eq!(rt.run_once(root), (Poll::Pending, Poll::Pending), "fallback");
+
+        rt.advance(Duration::from_millis(10));
+        assert_eq!(rt.run_once(root), (Poll::Ready(()), Poll::Ready(())));
+    }
+
+    #[test]
+    fn delayed_suspense_without_a_timer_falls_back_immediately() {
+        let pool = futures::executor::LocalPool::new();
+        let mut rt = runtime::Runtime::new();
+        rt.set_task_executor(pool.spawner());
+        let shown = rt.run_once(|| {
+            suspense_after(
+                std::time::Duration::from_millis(10),
+                || load_once(futures::future::pending::<()>).map(|()| "loaded"),
+                || Poll::Ready("fallback"),
+            )
+        });
+        assert_eq!(shown, Poll::Ready("fallback"));
+    }
+
+    #[test]
+    fn load_result_timeouts_need_a_timer() {
+        let pool = futures::executor::LocalPool::new();
+        let mut rt = runtime::Runtime::new();
+        rt.set_task_executor(pool.spawner());
+        let started = Cell::new(false);
+        let options = LoadOptions::new().timeout(std::time::Duration::from_secs(1));
+        let (result, _) = rt.run_once(|| {
+            load_result_with_options(&(), options.clone(), |()| {
+                started.set(true);
+                futures::future::ready(Ok::<(), ()>(()))
+            })
+        });
+        assert_eq!(result, LoadResult::NoTimer);
+        assert!(!started.get(), "the future isn't started without a timer");
+    }
+
+    #[test]
+    fn load_result_timeouts_stop_with_their_loads() {
+        use crate::testing::TestRuntime;
+        use std::time::Duration;
+
+        let mut rt = TestRuntime::new();
+        let clock = rt.clock();
+        let options = LoadOptions::new().timeout(Duration::from_secs(1));
+        let completes = || {
+            let clock = clock.clone();
+            load_result_with_options(&(), options.clone(), move |()| {
+                let sleep = clock.sleep(Duration::from_millis(10));
+                async move {
+                    sleep.await;
+                    Ok::<_, ()>(())
+                }
+            })
+            .0
+        };
+
+        assert_eq!(rt.run_once(completes), LoadResult::Loading);
+        rt.run_until_stalled();
+        assert_eq!(clock.pending_sleeps(), 2, "the load and its timeout");
+        rt.advance(Duration::from_millis(10));
+        assert_eq!(rt.run_once(completes), LoadResult::Ready(()));
+        assert_eq!(clock.pending_sleeps(), 0, "the timeout stopped when the load completed");
+
+        let aborted = || {
+            load_result_with_options(&(), options.clone(), |()| {
+                futures::future::pending::<Result<(), ()>>()
+            })
+            .0
+        };
+        assert_eq!(rt.run_once(aborted), LoadResult::Loading);
+        rt.run_until_stalled();
+        assert_eq!(clock.pending_sleeps(), 1);
+        rt.run_once(|| ());
+        rt.run_until_stalled();
+        assert_eq!(clock.pending_sleeps(), 0, "the timeout stopped when the load was cancelled");
+    }
+
+    #[test]
+    fn load_results_keep_previous_values_while_reloading() {
+        use crate::testing::TestRuntime;
+        use std::time::Duration;
+
+        let mut rt = TestRuntime::new();
+        let clock = rt.clock();
+        let root = |n: u32, options: LoadOptions| {
+            let clock = clock.clone();
+            load_result_with_options(&n, options, move |&n| {
+                let sleep = clock.sleep(Duration::from_millis(10));
+                async move {
+                    sleep.await;
+                    Ok::<_, ()>(n)
+                }
+            })
+            .0
+        };
+
+        let keep = LoadOptions::new().keep_previous();
+        assert_eq!(rt.run_once(|| root(1, keep.clone())), LoadResult::Loading);
+        rt.advance(Duration::from_millis(10));
+        assert_eq!(rt.run_once(|| root(1, keep.clone())), LoadResult::Ready(1));
+
+        assert_eq!(rt.run_once(|| root(2, keep.clone())), LoadResult::Ready(1), "kept");
+        assert_eq!(rt.runtime().inspect().tasks.len(), 1);
+        assert_eq!(rt.run_once(|| root(3, keep.clone())), LoadResult::Ready(1), "kept");
+        rt.run_until_stalled();
+        assert_eq!(rt.runtime().inspect().tasks.len(), 1, "the load for 2 was cancelled");
+        rt.advance(Duration::from_millis(10));
+        assert_eq!(rt.run_once(|| root(3, keep.clone())), LoadResult::Ready(3));
+
+        let flash = LoadOptions::new();
+        assert_eq!(rt.run_once(|| root(4, flash.clone())), LoadResult::Loading);
+    }
+
+    #[test]
+    fn load_results_and_streams_are_spawned_with_their_priority() {
+        use futures::{executor::LocalPool, future::ready, stream};
+
+        let mut rt = RunLoop::new(|| {
+            let options = LoadOptions::new().priority(Priority::High);
+            let result = load_result_with_options(&(), options, |()| ready(Ok::<_, ()>(1))).0;
+            let item = load_stream_with_priority(Priority::High, &(), |()| stream::iter(vec![2]));
+            (result, item)
+        });
+        let (mut normal, mut high) = (LocalPool::new(), LocalPool::new());
+        rt.set_task_executor(normal.spawner());
+        rt.set_priority_executor(Priority::High, high.spawner());
+
+        assert_eq!(rt.run_once(), (LoadResult::Loading, Poll::Pending));
+        normal.run_until_stalled();
+        assert_eq!(rt.run_once(), (LoadResult::Loading, Poll::Pending), "not spawned as normal");
+        high.run_until_stalled();
+        assert_eq!(rt.run_once(), (LoadResult::Ready(1), Poll::Ready(2)));
+    }
+
+    #[test]
+    fn streams_restart_with_new_captures_and_stop_when_dead() {
+        use crate::testing::TestRuntime;
+        use futures::channel::mpsc;
+
+        let (first_send, first_recv) = mpsc::unbounded();
+        let (second_send, second_recv) = mpsc::unbounded();
+        let recvs = RefCell::new(vec![second_recv, first_recv]);
+
+        let mut rt = TestRuntime::new();
+        let root = |n: u8| {
+            load_stream_fold(&n, |_| recvs.borrow_mut().pop().unwrap(), || 0, |sum, x: u32| sum + x)
+        };
+
+        assert_eq!(rt.run_once(|| root(1)), 0);
+        assert!(rt.loads()[0].is_pending(), "no items have been folded");
+        first_send.unbounded_send(1).unwrap();
+        first_send.unbounded_send(2).unwrap();
+        rt.run_until_stalled();
+        assert_eq!(rt.run_once(|| root(1)), 3);
+        assert!(rt.loads()[0].is_ready());
+
+        assert_eq!(rt.run_once(|| root(2)), 0, "new capture resets the fold");
+        rt.run_until_stalled();
+        assert!(first_send.unbounded_send(3).is_err(), "old stream was dropped");
+
+        second_send.unbounded_send(5).unwrap();
+        rt.run_until_stalled();
+        assert_eq!(rt.run_once(|| root(2)), 5);
+
+        rt.run_once(|| ());
+        rt.run_until_stalled();
+        assert!(second_send.unbounded_send(6).is_err(), "stream dropped when callsite is dead");
+    }
+
+    #[test]
+    fn watches_see_published_commits_until_the_var_drops() {
+        use futures::{executor::block_on, StreamExt};
+
+        let mut rt = RunLoop::new(|| state(|| 0u8));
+        let (_, key) = rt.run_once();
+        let mut bounded = key.watch(WatchBuffer::Bounded(2));
+
+        batch(|| {
+            key.set(1);
+            key.set(2);
+        });
+        key.set(3);
+        key.set(4);
+        rt.run_once();
+
+        let buffered: Vec<u8> = block_on(bounded.by_ref().take(2).map(|c| *c).collect());
+        assert_eq!(buffered, vec![2, 3], "batches publish once, full buffers drop commits");
+
+        let late = key.watch(WatchBuffer::Latest);
+        drop(key);
+        let (rt, _) = rt.unloop();
+        drop(rt);
+        assert!(block_on(bounded.next()).is_none(), "the stream ends with the var");
+        assert!(block_on(late.collect::<Vec<_>>()).is_empty());
+    }
+
+    #[test]
+    fn reducers_wake_once_per_revision() {
+        let mut rt = crate::testing::TestRuntime::new();
+        let root =
+            || reducer(Vec::new, |prev: &Vec<u8>, n| prev.iter().copied().chain(Some(n)).collect());
+
+        let (_, dispatch) = rt.run_once(root);
+        dispatch.dispatch(1);
+        dispatch.dispatch(2);
+        assert_eq!(rt.take_wakes(), 1);
+
+        let (items, _) = rt.run_once(root);
+        assert_eq!(*items, vec![1, 2]);
+        batch(|| {
+            dispatch.dispatch(3);
+            assert_eq!(rt.take_wakes(), 0, "staged until the batch closes");
+        });
+        dispatch.dispatch(4);
+        assert_eq!(rt.take_wakes(), 1);
+        assert_eq!(*rt.run_once(root).0, vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn middleware_can_dispatch_to_its_own_reducer() {
+        struct FollowUp(Rc<RefCell<Option<Dispatch<u8>>>>);
+        impl Middleware<Vec<u8>, u8> for FollowUp {
+            fn after(&self, state: &Vec<u8>) {
+                if state.last() == Some(&1) {
+                    let dispatch = self.0.borrow_mut().clone().unwrap();
+                    dispatch.dispatch(10);
+                    dispatch.dispatch(11);
+                }
+            }
+        }
+
+        let mut rt = crate::testing::TestRuntime::new();
+        let own_dispatch = Rc::new(RefCell::new(None));
+        let root = || {
+            let push = |prev: &Vec<u8>, n| prev.iter().copied().chain(Some(n)).collect();
+            reducer_with_middleware(Vec::new, push, FollowUp(own_dispatch.clone()))
+        };
+
+        let (_, dispatch) = rt.run_once(root);
+        own_dispatch.replace(Some(dispatch.clone()));
+        dispatch.dispatch(1);
+        dispatch.dispatch(2);
+        assert_eq!(*rt.run_once(root).0, vec![1, 10, 11, 2], "reduced in dispatch order");
+        own_dispatch.replace(None);
+    }
+
+    #[test]
+    fn memoized_subtrees_rerun_only_when_dirty() {
+        let runs = RefCell::new(Vec::new());
+        let log = |name: &'static str| runs.borrow_mut().push(name);
+        let mut rt = crate::runtime::Runtime::new();
+        let root = |capture: u8| {
+            memo(&capture, || {
+                log("outer");
+                let left = memo(&(), || {
+                    log("left");
+                    cache(&(), |_| ());
+                    state(|| 0u8).1
+                });
+                let right = memo(&(), || {
+                    log("right");
+                    state(|| 0u8).1
+                });
+                (left, right)
+            })
+        };
+
+        let (left, _) = rt.run_once(|| root(0));
+        assert_eq!(runs.replace(vec![]), vec!["outer", "left", "right"]);
+
+        for _ in 0..3 {
+            rt.run_once(|| root(0));
+        }
+        assert!(runs.borrow().is_empty());
+        let entries = rt.inspect().entries.len();
+        assert_eq!(rt.inspect().state.len(), 2, "skipped subtrees keep their state");
+
+        left.set(1);
+        let (left, _) = rt.run_once(|| root(0));
+        assert_eq!(*left, 1);
+        assert_eq!(runs.replace(vec![]), vec!["outer", "left"], "enclosing subtrees re-run");
+        assert_eq!(rt.inspect().entries.len(), entries);
+
+        rt.run_once(|| root(1));
+        assert_eq!(runs.replace(vec![]), vec!["outer"], "captures re-run only their subtree");
+
+        rt.run_once(|| ());
+        assert_eq!(rt.inspect().entries.len(), 0, "unused subtrees are collected");
+    }
+
+    #[test]
+    fn lru_retention_keeps_recently_hidden_state() {
+        let mut rt = crate::runtime::Runtime::new();
+        rt.set_default_retention(runtime::Retention::Lru);
+        rt.set_lru_capacity(1);
+        let tab = |name: &'static str| {
+            topo::call_in_slot(name, || {
+                let (count, key) = state(|| 0u8);
+                (*count, key)
+            })
+        };
+
+        let (_, first) = rt.run_once(|| tab("first"));
+        first.set(1);
+        rt.run_once(|| tab("second"));
+        assert_eq!(rt.run_once(|| tab("first")).0, 1, "the hidden tab kept its state");
+
+        rt.run_once(|| tab("second"));
+        rt.run_once(|| tab("third"));
+        assert_eq!(rt.run_once(|| tab("first")).0, 0, "evicted for a more recent tab");
+    }
 }
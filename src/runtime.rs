@@ -1,25 +1,63 @@
 //! [`Runtime`]s are the primary integration point between moxie and
 //! embedding environments.
 
+mod batch;
 mod context;
+mod effects;
+mod inspect;
+mod memo;
+#[cfg(feature = "persist")]
+mod persist;
+mod priority;
+mod profile;
+mod record;
 mod runloop;
+mod send;
+mod shared;
+mod timer;
 mod var;
+mod watch;
 
 use dyn_cache::local::SharedLocalCache;
+use effects::Effects;
 use futures::{
     future::LocalFutureObj,
-    task::{noop_waker, LocalSpawn, SpawnError},
+    task::{noop_waker, waker, ArcWake, LocalSpawn, SpawnError},
 };
 use illicit::AsContext;
+use inspect::{Generations, Registry};
+use parking_lot::Mutex;
+use priority::Spawners;
+use profile::Profiler;
+use record::Recorder;
+use shared::Subscriptions;
 use std::{
+    cell::RefCell,
     fmt::{Debug, Formatter, Result as FmtResult},
     rc::Rc,
+    sync::Arc,
     task::Waker,
 };
+use timer::TimerHandle;
 
+pub use dyn_cache::Retention;
+
+pub(crate) use batch::Batch;
 pub(crate) use context::Context;
-pub use runloop::RunLoop;
+pub(crate) use effects::Phase;
+pub use inspect::{CacheEntry, Inspection, StateVar, Task};
+#[cfg(feature = "persist")]
+pub use persist::Snapshot;
+pub use priority::Priority;
+pub use profile::{CallsiteProfile, InitTiming, Profile, RevisionProfile};
+pub use record::{RecordedCommit, Recording, TimeTravel};
+pub use runloop::{BlockingOptions, LoopPolicy, RunLoop, Shutdown};
+pub(crate) use send::SendContext;
+pub use send::SendRuntime;
+pub use shared::SharedStore;
+pub use timer::Timer;
 pub(crate) use var::Var;
+pub(crate) use watch::Channel;
 
 /// Revisions measure moxie's notion of time passing. Each `Runtime` increments
 /// its Revision on every iteration. `crate::Commit`s to state variables are
@@ -31,7 +69,13 @@ impl Revision {
     /// Returns the current revision. Will return `Revision(0)` if called
     /// outside of a Runtime's execution.
     pub fn current() -> Self {
-        if let Ok(r) = illicit::get::<Context>() { r.revision() } else { Revision::default() }
+        if let Ok(r) = illicit::get::<Context>() {
+            r.revision()
+        } else if let Ok(r) = illicit::get::<SendContext>() {
+            r.revision()
+        } else {
+            Revision::default()
+        }
     }
 }
 
@@ -97,11 +141,50 @@ impl std::fmt::Debug for Revision {
 /// This behavior also provides deterministic drop timing for values cached by
 /// the runtime.
 ///
+/// Values can be kept for longer after they go unused with a [`Retention`]
+/// policy, either for a single callsite with [`crate::cache_with_retention`] or
+/// for every value the runtime caches with [`Runtime::set_default_retention`].
+///
 /// ## Tasks
 ///
 /// Each runtime expects to be able to spawn futures as async tasks, provided
 /// with [`Runtime::set_task_executor`]. By default a no-op spawner is provided.
 ///
+/// Tasks can be given a [`Priority`] with [`crate::load_with_priority`], and
+/// each priority can be given its own executor with
+/// [`Runtime::set_priority_executor`]. Idle tasks are not spawned until their
+/// revision has completed.
+///
+/// ## Effects
+///
+/// Side effects which shouldn't run while the root closure is still executing
+/// can be scheduled with [`crate::effect`] and [`crate::layout_effect`]. The
+/// runtime runs them at the end of [`Runtime::run_once`], and calls their
+/// cleanups when they're re-run or when their callsite is dropped from the
+/// cache.
+///
+/// ## Inspection
+///
+/// [`Runtime::inspect`] returns a snapshot of the runtime's cache, state
+/// variables, and tasks, along with the callsites which created them.
+///
+/// ## Profiling
+///
+/// [`Runtime::set_profiling`] records how often each callsite's cached values
+/// are reused or recomputed, which can be exported with
+/// [`Profile::write_chrome_trace`].
+///
+/// ## Time Travel
+///
+/// [`Runtime::set_recording`] records each commit applied to the runtime's
+/// state variables, which [`TimeTravel`] can replay to step backward and
+/// forward through revisions.
+///
+/// ## Threads
+///
+/// A `Runtime` stores values which are not thread-safe and can't be sent to
+/// other threads. See [`SendRuntime`] for a runtime which can be.
+///
 /// # Minimal Example
 ///
 /// This example has no side effects in its root function, and doesn't have any
@@ -121,8 +204,20 @@ impl std::fmt::Debug for Revision {
 pub struct Runtime {
     revision: Revision,
     cache: SharedLocalCache,
-    spawner: Spawner,
+    spawners: Spawners,
+    hold_idle: bool,
+    state_changes: Arc<StateChangeWaker>,
     wk: Waker,
+    effects: Effects,
+    registry: Registry,
+    generations: Generations,
+    profiler: Profiler,
+    recorder: Recorder,
+    subscriptions: Subscriptions,
+    load_log: Option<LoadLog>,
+    timer: Option<TimerHandle>,
+    #[cfg(feature = "persist")]
+    persisted: persist::Persisted,
 }
 
 impl Default for Runtime {
@@ -135,11 +230,25 @@ impl Runtime {
     /// Construct a new [`Runtime`] with blank storage and no external waker or
     /// task executor.
     pub fn new() -> Self {
+        let state_changes = Arc::new(StateChangeWaker(Mutex::new(noop_waker())));
+        let wk = waker(state_changes.clone());
         Self {
-            spawner: Spawner(Rc::new(JunkSpawner)),
+            spawners: Spawners::default(),
+            hold_idle: false,
             revision: Revision(0),
             cache: SharedLocalCache::default(),
-            wk: noop_waker(),
+            subscriptions: Subscriptions::new(wk.clone()),
+            wk,
+            state_changes,
+            effects: Effects::default(),
+            registry: Registry::default(),
+            generations: Generations::default(),
+            profiler: Profiler::default(),
+            recorder: Recorder::default(),
+            load_log: None,
+            timer: None,
+            #[cfg(feature = "persist")]
+            persisted: persist::Persisted::default(),
         }
     }
 
@@ -152,26 +261,279 @@ impl Runtime {
     /// Runs the root closure once with access to the runtime context,
     /// increments the runtime's `Revision`, and drops any cached values
     /// which were not marked alive.
+    ///
+    /// Layout effects scheduled by the root closure run after it returns and
+    /// before cached values are dropped. Other effects run last.
     pub fn run_once<Out>(&mut self, op: impl FnOnce() -> Out) -> Out {
         self.revision.0 += 1;
+        self.recorder.ran(self.revision);
+        let profiling = self.profiler.is_enabled();
+        let span = if profiling {
+            tracing::info_span!("revision", revision = self.revision.0)
+        } else {
+            tracing::Span::none()
+        };
+        let _entered = span.enter();
+        self.profiler.start_revision(self.revision);
 
         let ret = self.context_handle().offer(|| topo::call(op));
 
+        self.effects.run(Phase::Layout);
+        self.generations.collect(self.cache.generation(), self.revision);
+        let before_gc = if profiling { self.num_cache_entries() } else { 0 };
         self.cache.gc();
+        let evicted = if profiling {
+            let evicted = before_gc - self.num_cache_entries();
+            tracing::info!(evicted, "collected cache");
+            evicted
+        } else {
+            0
+        };
+        self.effects.run(Phase::Passive);
+        self.finish_revision();
+
+        self.profiler.finish_revision(evicted);
         ret
     }
 
+    fn num_cache_entries(&self) -> usize {
+        let mut count = 0;
+        self.cache.inspect(|_| count += 1);
+        count
+    }
+
+    fn finish_revision(&self) {
+        if self.hold_idle {
+            // loads collected this revision won't want their idle tasks later
+            self.spawners.drop_cancelled_idle();
+        } else {
+            self.spawners.spawn_idle();
+        }
+    }
+
+    /// Spawns any idle tasks requested by previous revisions which are being
+    /// held back by a [`RunLoop`]'s [`LoopPolicy`].
+    pub(crate) fn spawn_idle(&self) {
+        self.spawners.spawn_idle();
+    }
+
+    /// Sets whether idle tasks are held back after each revision until
+    /// [`Runtime::spawn_idle`] is called.
+    pub(crate) fn set_hold_idle(&mut self, hold_idle: bool) {
+        self.hold_idle = hold_idle;
+    }
+
+    /// Enables or disables profiling of subsequent revisions. While enabled,
+    /// the runtime records cache hits, misses, and init closure timings for
+    /// each callsite, along with the number of entries evicted at the end of
+    /// each revision. Retrieve the measurements with
+    /// [`Runtime::take_profile`].
+    ///
+    /// Profiled revisions and init closures are also run within `tracing`
+    /// spans named `revision` and `cache_init`.
+    ///
+    /// Disabling profiling discards any measurements which haven't been taken.
+    pub fn set_profiling(&mut self, enabled: bool) {
+        self.profiler.set_enabled(enabled);
+    }
+
+    /// Returns the measurements recorded since profiling was enabled or since
+    /// the last call to this method.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use moxie::{once, runtime::Runtime};
+    ///
+    /// let mut rt = Runtime::new();
+    /// rt.set_profiling(true);
+    /// let root = || once(|| ());
+    /// rt.run_once(root);
+    /// rt.run_once(root);
+    ///
+    /// let profile = rt.take_profile();
+    /// assert_eq!(profile.revisions[0].misses(), 1);
+    /// assert_eq!(profile.revisions[1].hits(), 1);
+    ///
+    /// let mut trace = Vec::new();
+    /// profile.write_chrome_trace(&mut trace).unwrap();
+    /// ```
+    pub fn take_profile(&mut self) -> Profile {
+        self.profiler.take()
+    }
+
+    /// Enables or disables recording of the commits applied to state variables
+    /// in subsequent revisions. Retrieve the recording with
+    /// [`Runtime::take_recording`] and replay it with [`TimeTravel`].
+    ///
+    /// Each commit is recorded with the revision which applied it, including
+    /// commits which were replaced by another before they could be applied.
+    /// Disabling recording discards any commits which haven't been taken.
+    pub fn set_recording(&mut self, enabled: bool) {
+        self.recorder.set_enabled(enabled);
+    }
+
+    /// Renders recorded commits to state variables of type `State` with its
+    /// `Debug` implementation, available as [`RecordedCommit::rendering`].
+    pub fn record_debug<State: Debug + 'static>(&mut self) {
+        self.recorder.render_with::<State>();
+    }
+
+    /// Returns the commits recorded since recording was enabled, and stops
+    /// recording.
+    pub fn take_recording(&mut self) -> Recording {
+        self.recorder.take()
+    }
+
+    /// Replay `commit` to the live state variable it was made to, without
+    /// waking the runtime. Returns false if there's no such variable.
+    pub(crate) fn replay(&self, commit: &RecordedCommit) -> bool {
+        self.registry.replay(commit)
+    }
+
+    /// Returns a snapshot of the runtime's cached values, state variables, and
+    /// in-flight tasks, for debugging.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use moxie::{runtime::Runtime, state};
+    ///
+    /// let mut rt = Runtime::new();
+    /// let (_, _key) = rt.run_once(|| state(|| 0u64));
+    ///
+    /// let report = rt.inspect();
+    /// assert_eq!(report.state.len(), 1);
+    /// assert_eq!(report.state[0].state_type, "u64");
+    /// assert_eq!(report.state[0].callsite.file(), file!());
+    /// ```
+    pub fn inspect(&self) -> Inspection {
+        let mut entries = Vec::new();
+        self.cache.inspect(|entry| entries.push(CacheEntry::new(entry, &self.generations)));
+        Inspection {
+            revision: self.revision,
+            entries,
+            state: self.registry.state(),
+            tasks: self.registry.tasks(),
+        }
+    }
+
+    /// Serializes the latest value of each live state variable created with
+    /// [`crate::persisted_state`], keyed by name. Values passed to
+    /// [`Runtime::hydrate`] which haven't been claimed by a state variable
+    /// are included as well.
+    ///
+    /// # Errors
+    ///
+    /// If a state variable's value fails to serialize.
+    #[cfg(feature = "persist")]
+    pub fn snapshot(&self) -> Result<Snapshot, serde_json::Error> {
+        self.persisted.snapshot()
+    }
+
+    /// Provides initial values for state variables created with
+    /// [`crate::persisted_state`]. Each value is claimed by the first state
+    /// variable created with its name, which starts from the value instead of
+    /// calling its init closure. Values which fail to deserialize are
+    /// discarded.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use moxie::{persisted_state, runtime::Runtime};
+    ///
+    /// let root = || persisted_state("clicks", || 0u32);
+    ///
+    /// let mut rt = Runtime::new();
+    /// let (_, clicks) = rt.run_once(root);
+    /// clicks.set(3);
+    /// let saved = serde_json::to_string(&rt.snapshot().unwrap()).unwrap();
+    ///
+    /// let mut restarted = Runtime::new();
+    /// restarted.hydrate(serde_json::from_str(&saved).unwrap());
+    /// let (clicks, _) = restarted.run_once(root);
+    /// assert_eq!(*clicks, 3);
+    /// ```
+    #[cfg(feature = "persist")]
+    pub fn hydrate(&mut self, snapshot: Snapshot) {
+        self.persisted.hydrate(snapshot);
+    }
+
     /// Sets the [`std::task::Waker`] which will be called when state variables
-    /// receive commits. By default the runtime no-ops on a state change,
-    /// which is probably the desired behavior if the embedding system will
-    /// call `Runtime::run_once` on a regular interval regardless.
+    /// receive commits, including variables created before this call. By
+    /// default the runtime no-ops on a state change, which is probably the
+    /// desired behavior if the embedding system will call `Runtime::run_once`
+    /// on a regular interval regardless.
     pub fn set_state_change_waker(&mut self, wk: Waker) {
-        self.wk = wk;
+        *self.state_changes.0.lock() = wk;
+    }
+
+    /// Sets the [`Retention`] policy for cached values and state variables
+    /// which aren't cached with one of their own. Only affects values cached
+    /// after this call.
+    pub fn set_default_retention(&mut self, retention: Retention) {
+        self.cache.set_default_retention(retention);
+    }
+
+    /// Sets the maximum number of unused values to keep with
+    /// [`Retention::Lru`], dropping the least recently used at the end of each
+    /// revision. Unlimited by default.
+    pub fn set_lru_capacity(&mut self, capacity: usize) {
+        self.cache.set_lru_capacity(capacity);
     }
 
     /// Sets the executor that will be used to spawn normal priority tasks.
     pub fn set_task_executor(&mut self, sp: impl LocalSpawn + 'static) {
-        self.spawner = Spawner(Rc::new(sp));
+        self.set_priority_executor(Priority::Normal, sp);
+    }
+
+    /// Sets the executor that will be used to spawn tasks of the given
+    /// `priority`. High priority and idle tasks are spawned with the normal
+    /// priority executor unless they're given their own.
+    pub fn set_priority_executor(&mut self, priority: Priority, sp: impl LocalSpawn + 'static) {
+        self.spawners.set(priority, sp);
+    }
+
+    /// Sets the source of time used for timeouts and by
+    /// [`crate::suspense_after`]. Runtimes have no timer by default, see
+    /// [`crate::LoadResult::NoTimer`] and [`crate::suspense_after`] for what
+    /// happens if one is needed before it's set.
+    pub fn set_timer(&mut self, timer: impl Timer + 'static) {
+        self.timer = Some(TimerHandle(Rc::new(timer)));
+    }
+
+    /// Sets the log which will record the result of each load made by the
+    /// runtime.
+    pub(crate) fn set_load_log(&mut self, log: LoadLog) {
+        self.load_log = Some(log);
+    }
+}
+
+/// Records whether each load during a revision was ready. See
+/// [`crate::testing::TestRuntime::loads`].
+#[derive(Clone, Debug, Default)]
+pub(crate) struct LoadLog(Rc<RefCell<Vec<crate::testing::LoadCall>>>);
+
+impl LoadLog {
+    fn record(&self, id: topo::CallId, is_ready: bool) {
+        self.0.borrow_mut().push(crate::testing::LoadCall { id, is_ready });
+    }
+
+    /// Returns the loads recorded since the last call to this method.
+    pub fn take(&self) -> Vec<crate::testing::LoadCall> {
+        std::mem::take(&mut *self.0.borrow_mut())
+    }
+}
+
+/// The waker given to state variables, which forwards their commits to the
+/// runtime's latest state change waker.
+struct StateChangeWaker(Mutex<Waker>);
+
+impl ArcWake for StateChangeWaker {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        // don't hold the lock while waking in case the waker is replaced
+        let wk = arc_self.0.lock().clone();
+        wk.wake();
     }
 }
 
@@ -0,0 +1,90 @@
+use super::Var;
+use illicit::AsContext;
+use parking_lot::Mutex;
+use std::{
+    cell::RefCell,
+    fmt::{Debug, Formatter, Result as FmtResult},
+    rc::Rc,
+    sync::Arc,
+    task::Waker,
+};
+
+/// A set of state variables with commits staged during a call to
+/// [`crate::batch`]. Offered via [`illicit`] while the batch is open.
+#[derive(Clone, Default)]
+pub(crate) struct Batch {
+    staged: Rc<RefCell<Vec<Arc<dyn Staged>>>>,
+}
+
+impl Batch {
+    /// Runs `op` with a batch open, publishing any commits it stages once it
+    /// returns. If `op` panics, the staged commits are discarded instead. Joins
+    /// the current batch if one is already open.
+    pub fn run<R>(op: impl FnOnce() -> R) -> R {
+        if illicit::get::<Batch>().is_ok() {
+            return op();
+        }
+
+        let batch = Batch::default();
+        let _finish = scopeguard::guard(batch.clone(), |batch| {
+            if std::thread::panicking() { batch.discard() } else { batch.publish() }
+        });
+        batch.offer(op)
+    }
+
+    /// Record that `var` has a staged commit which must be published when the
+    /// batch closes.
+    pub fn stage<State: 'static>(&self, var: Arc<Mutex<Var<State>>>) {
+        let var: Arc<dyn Staged> = var;
+        let mut staged = self.staged.borrow_mut();
+        if !staged.iter().any(|s| addr(s) == addr(&var)) {
+            staged.push(var);
+        }
+    }
+
+    /// Publish all of the staged commits and wake each distinct waker once.
+    fn publish(self) {
+        let mut to_wake: Vec<Waker> = Vec::new();
+        for var in self.staged.borrow_mut().drain(..) {
+            if let Some(waker) = var.publish() {
+                if !to_wake.iter().any(|w| w.will_wake(&waker)) {
+                    to_wake.push(waker);
+                }
+            }
+        }
+        to_wake.into_iter().for_each(Waker::wake);
+    }
+
+    fn discard(self) {
+        self.staged.borrow_mut().drain(..).for_each(|var| var.discard());
+    }
+}
+
+impl Debug for Batch {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("Batch").field("num_staged", &self.staged.borrow().len()).finish()
+    }
+}
+
+fn addr(var: &Arc<dyn Staged>) -> usize {
+    Arc::as_ptr(var) as *const () as usize
+}
+
+/// A state variable which may have a staged commit, erased over its type.
+trait Staged {
+    /// Make the staged commit pending, returning the waker to notify.
+    fn publish(&self) -> Option<Waker>;
+
+    /// Drop the staged commit.
+    fn discard(&self);
+}
+
+impl<State> Staged for Mutex<Var<State>> {
+    fn publish(&self) -> Option<Waker> {
+        self.lock().publish_staged()
+    }
+
+    fn discard(&self) {
+        self.lock().discard_staged();
+    }
+}
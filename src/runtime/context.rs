@@ -1,11 +1,34 @@
-use super::{Revision, Spawner, Var};
-use crate::{Commit, Key};
-use dyn_cache::local::SharedLocalCache;
-use futures::future::abortable;
+use super::{
+    effects::{Cleanup, Effects, Phase},
+    inspect::{Registry, Task},
+    memo::{Frame, Subtree},
+    priority::Spawners,
+    profile::Profiler,
+    record::Recorder,
+    shared::Subscriptions,
+    timer::{Timer, TimerHandle},
+    Batch, LoadLog, Priority, Revision, SharedStore, Var,
+};
+use crate::{Commit, Dispatch, Key, LoadOptions, LoadResult, Middleware, Panicked, Retry};
+use dyn_cache::{local::SharedLocalCache, Retention};
+use futures::{
+    future::{abortable, select, AbortHandle, Either},
+    stream::{Stream, StreamExt},
+};
+use illicit::AsContext;
+use parking_lot::Mutex;
+use scopeguard::ScopeGuard;
 use std::{
+    any::type_name,
     borrow::Borrow,
+    cell::{Cell, RefCell},
+    collections::VecDeque,
     future::Future,
+    panic::{catch_unwind, AssertUnwindSafe},
+    rc::Rc,
+    sync::Arc,
     task::{Poll, Waker},
+    time::Duration,
 };
 
 /// A handle to the current [`Runtime`] which is offered via [`illicit`]
@@ -15,8 +38,17 @@ use std::{
 pub(crate) struct Context {
     revision: Revision,
     pub cache: SharedLocalCache,
-    spawner: Spawner,
+    spawners: Spawners,
     waker: Waker,
+    effects: Effects,
+    registry: Registry,
+    profiler: Profiler,
+    recorder: Recorder,
+    subscriptions: Subscriptions,
+    load_log: Option<LoadLog>,
+    timer: Option<TimerHandle>,
+    #[cfg(feature = "persist")]
+    persisted: super::persist::Persisted,
 }
 
 impl Context {
@@ -25,6 +57,65 @@ impl Context {
         self.revision
     }
 
+    /// Returns the runtime's timer, if one has been set.
+    fn timer(&self) -> Option<&dyn Timer> {
+        self.timer.as_ref().map(|timer| &*timer.0)
+    }
+
+    /// Cache the return of `init` at `id`, recording a hit or miss if the
+    /// runtime is profiling.
+    pub fn cache_with<Arg, Input, Output, Ret>(
+        &self,
+        id: &topo::CallId,
+        arg: &Arg,
+        init: impl FnOnce(&Input) -> Output,
+        with: impl FnOnce(&Output) -> Ret,
+    ) -> Ret
+    where
+        Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+        Input: Borrow<Arg> + 'static,
+        Output: 'static,
+        Ret: 'static,
+    {
+        self.cache_with_retention(id, arg, None, init, with)
+    }
+
+    /// Like [`Context::cache_with`], keeping the cached value according to
+    /// `retention` if provided instead of the runtime's default policy.
+    pub fn cache_with_retention<Arg, Input, Output, Ret>(
+        &self,
+        id: &topo::CallId,
+        arg: &Arg,
+        retention: Option<Retention>,
+        init: impl FnOnce(&Input) -> Output,
+        with: impl FnOnce(&Output) -> Ret,
+    ) -> Ret
+    where
+        Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+        Input: Borrow<Arg> + 'static,
+        Output: 'static,
+        Ret: 'static,
+    {
+        let profiling = self.profiler.is_enabled();
+        let mut missed = false;
+        let init = |input: &Input| {
+            if !profiling {
+                return init(input);
+            }
+            missed = true;
+            self.profiler.miss(id, || init(input))
+        };
+
+        let ret = match retention {
+            Some(retention) => self.cache.cache_with_retention(id, arg, retention, init, with),
+            None => self.cache.cache_with(id, arg, init, with),
+        };
+        if profiling && !missed {
+            self.profiler.hit(id);
+        }
+        ret
+    }
+
     /// Load a [`crate::state::Var`] with the provided argument and initializer.
     /// Re-initializes the `Var` whenever `arg` changes.
     pub fn cache_state<Arg, Input, Output>(
@@ -38,12 +129,128 @@ impl Context {
         Input: Borrow<Arg> + 'static,
         Output: 'static,
     {
-        let var = self
-            .cache
-            .cache(id, arg, |arg| Var::new(topo::CallId::current(), self.waker.clone(), init(arg)));
+        let var = self.cache.cache(id, arg, |arg| {
+            let var = Var::new(topo::CallId::current(), self.waker.clone(), init(arg));
+            self.registry.register_var(Arc::downgrade(&var) as _);
+            var
+        });
+        self.root_var(var)
+    }
+
+    /// Root `var`, recording the read for any enclosing memoized subtree.
+    fn root_var<State: 'static>(&self, var: Arc<Mutex<Var<State>>>) -> (Commit<State>, Key<State>) {
+        if let Ok(frame) = illicit::get::<Frame>() {
+            frame.read(Arc::downgrade(&var) as _);
+        }
         Var::root(var)
     }
 
+    /// Returns true if the runtime is recording commits to state variables.
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_enabled()
+    }
+
+    /// Record a commit applied to a state variable during this revision.
+    pub fn record_commit<State: 'static>(&self, commit: &Commit<State>) {
+        self.recorder.record(self.revision, commit);
+    }
+
+    /// Root the state variable named `name` in `store`, subscribing the runtime
+    /// to the store's commits for as long as `id` is live.
+    pub fn shared_state<State>(
+        &self,
+        id: &topo::CallId,
+        store: &SharedStore,
+        name: &str,
+        init: impl FnOnce() -> State,
+    ) -> (Commit<State>, Key<State>)
+    where
+        State: Send + Sync + 'static,
+    {
+        self.cache.hold(id, &store.id(), |_| self.subscriptions.subscribe(store));
+        self.root_var(store.var(name, init))
+    }
+
+    /// Root a state variable at `id` which is included in the runtime's
+    /// snapshots under `name`, starting from the runtime's hydrated value for
+    /// `name` if it has one.
+    #[cfg(feature = "persist")]
+    pub fn persisted_state<Output>(
+        &self,
+        id: &topo::CallId,
+        name: &str,
+        init: impl FnOnce() -> Output,
+    ) -> (Commit<Output>, Key<Output>)
+    where
+        Output: serde::Serialize + serde::de::DeserializeOwned + 'static,
+    {
+        let var = self.cache.cache(id, name, |name: &String| {
+            let initial = self.persisted.take(name).unwrap_or_else(init);
+            let var = Var::new(topo::CallId::current(), self.waker.clone(), initial);
+            self.registry.register_var(Arc::downgrade(&var) as _);
+            self.persisted.register(name.clone(), Arc::downgrade(&var) as _);
+            var
+        });
+        self.root_var(var)
+    }
+
+    /// Root a state variable at `id` which is updated by reducing the actions
+    /// sent to the returned [`Dispatch`]. See [`crate::reducer`].
+    pub fn reducer<State, Action>(
+        &self,
+        id: &topo::CallId,
+        init: impl FnOnce() -> State,
+        reduce: impl Fn(&State, Action) -> State + 'static,
+        middleware: impl Middleware<State, Action> + 'static,
+    ) -> (Commit<State>, Dispatch<Action>)
+    where
+        State: 'static,
+        Action: 'static,
+    {
+        let (commit, key) = self.cache_state(id, &(), |()| init());
+        let queued = RefCell::new(VecDeque::new());
+        let dispatching = Cell::new(false);
+        let init_dispatch = |&(): &()| Dispatch {
+            id: *id,
+            send: Rc::new(move |action| {
+                queued.borrow_mut().push_back(action);
+                if dispatching.replace(true) {
+                    // dispatched from middleware or reduce, the outer dispatch will reduce it
+                    return;
+                }
+                let _dispatching = scopeguard::guard((), |()| {
+                    queued.borrow_mut().clear();
+                    dispatching.set(false);
+                });
+
+                loop {
+                    let action = match queued.borrow_mut().pop_front() {
+                        Some(action) => action,
+                        None => break,
+                    };
+                    // the var isn't locked while calling out so they can use it too
+                    let latest = key.var.lock().latest_commit();
+                    let action = match middleware.before(&latest, action) {
+                        Some(action) => action,
+                        None => continue,
+                    };
+                    let next = reduce(&latest, action);
+                    middleware.after(&next);
+
+                    let mut var = key.var.lock();
+                    if let Ok(batch) = illicit::get::<Batch>() {
+                        var.stage_commit(next);
+                        batch.stage(key.var.clone());
+                    } else {
+                        var.coalesce_commit(next);
+                    }
+                }
+            }),
+        };
+        let dispatch = self.cache_with(id, &(), init_dispatch, Clone::clone);
+        (commit, dispatch)
+    }
+
     /// Load a value from the future returned by `init` whenever `capture`
     /// changes, returning the result of calling `with` with the loaded
     /// value. Cancels the running future if there's no longer interest
@@ -57,6 +264,7 @@ impl Context {
     pub fn load_with<Arg, Input, Fut, Output, Ret>(
         &self,
         id: &topo::CallId,
+        priority: Priority,
         arg: &Arg,
         init: impl FnOnce(&Input) -> Fut,
         with: impl FnOnce(&Output) -> Ret,
@@ -74,35 +282,373 @@ impl Context {
             // before we spawn the new task we need to mark it pending
             set_result.force(Poll::Pending);
 
-            let (fut, aborter) = abortable(init(arg));
-            let task = async move {
-                if let Ok(to_store) = fut.await {
-                    set_result.update(|_| Some(Poll::Ready(to_store)));
-                }
-            };
-            self.spawner
-                .0
-                .spawn_local_obj(Box::pin(task).into())
-                .expect("that set_task_executor has been called");
-            scopeguard::guard(aborter, |a| a.abort())
+            let fut = init(arg);
+            self.spawn_task::<Output>(id, priority, async move {
+                let to_store = fut.await;
+                set_result.update(|_| Some(Poll::Ready(to_store)));
+            })
         });
 
         set_result2.refresh();
+        self.record_load(id, set_result2.is_ready());
 
         match &*set_result2 {
             Poll::Ready(ref stored) => Poll::Ready(with(stored)),
             Poll::Pending => Poll::Pending,
         }
     }
+
+    /// Load a result from the future returned by `init` whenever `capture`
+    /// changes or the returned [`Retry`] handle is used. See
+    /// [`crate::load_result_with_options`].
+    pub fn load_result<Arg, Input, Fut, Output, Error>(
+        &self,
+        id: &topo::CallId,
+        arg: &Arg,
+        options: &LoadOptions,
+        init: impl FnOnce(&Input) -> Fut,
+    ) -> (Commit<LoadResult<Output, Error>>, Retry)
+    where
+        Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+        Input: Borrow<Arg> + 'static,
+        Fut: Future<Output = Result<Output, Error>> + 'static,
+        Output: 'static,
+        Error: 'static,
+    {
+        let (_, set_result): (_, Key<LoadResult<Output, Error>>) =
+            self.cache_state(id, &(), |()| LoadResult::Loading);
+        let (attempt, attempts): (Commit<u64>, _) = self.cache_state(id, &(), |()| 0u64);
+        let running: Rc<RefCell<Running>> = self.cache.cache(id, &(), |()| Rc::default());
+
+        let mut init = Some(init);
+        let mut start = |input: &Input| {
+            let init = init.take().expect("loads only start once per revision");
+            let timeout = match (options.timeout, self.timer()) {
+                (Some(timeout), Some(timer)) => Some(timer.sleep(timeout)),
+                (Some(_), None) => {
+                    set_result.force(LoadResult::NoTimer);
+                    *running.borrow_mut() = Running { attempt: *attempt, _task: None };
+                    return;
+                }
+                (None, _) => None,
+            };
+            if !(options.keep_previous && set_result.is_ready()) {
+                set_result.force(LoadResult::Loading);
+            }
+
+            let fut = init(input);
+            let set_result = set_result.clone();
+            let task = self.spawn_task::<Output>(id, options.priority, async move {
+                let result = match timeout {
+                    Some(timeout) => match select(Box::pin(fut), timeout).await {
+                        Either::Left((result, _)) => result.into(),
+                        Either::Right(((), _)) => LoadResult::TimedOut,
+                    },
+                    None => fut.await.into(),
+                };
+                set_result.update(|_| Some(result));
+            });
+            *running.borrow_mut() = Running { attempt: *attempt, _task: Some(task) };
+        };
+
+        let mut started = false;
+        self.cache.hold(id, arg, |input| {
+            started = true;
+            start(input);
+        });
+        if !started && RefCell::borrow(&running).attempt != *attempt {
+            start(&arg.to_owned());
+        }
+
+        let mut result = set_result.clone();
+        result.refresh();
+        self.record_load(id, !result.is_loading());
+        (result.commit_at_root, Retry { attempts })
+    }
+
+    /// Consume the stream returned by `init` whenever `capture` changes,
+    /// committing the result of `reduce` with each item to a state variable
+    /// which starts at `initial()`, and returning its latest value. Cancels the
+    /// stream if there's no longer interest in it, indicated by a revision in
+    /// which this was not called with the given `id`.
+    ///
+    /// The load is recorded as ready once the stream has produced an item.
+    pub fn load_stream<Arg, Input, S, State>(
+        &self,
+        id: &topo::CallId,
+        priority: Priority,
+        arg: &Arg,
+        init: impl FnOnce(&Input) -> S,
+        initial: impl FnOnce() -> State,
+        mut reduce: impl FnMut(&State, S::Item) -> State + 'static,
+    ) -> State
+    where
+        Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+        Input: Borrow<Arg> + 'static,
+        S: Stream + 'static,
+        State: Clone + 'static,
+    {
+        let (_, set_state): (_, Key<Option<Streamed<State>>>) =
+            self.cache_state(id, &(), |()| None);
+        let mut state = set_state.clone();
+        self.cache.hold(id, arg, |arg| {
+            // reset the state before any items from the new stream arrive
+            set_state.force(Some(Streamed { state: initial(), received: false }));
+
+            let mut stream = Box::pin(init(arg));
+            self.spawn_task::<S::Item>(id, priority, async move {
+                while let Some(item) = stream.next().await {
+                    set_state.update(|prev| {
+                        let prev = prev.as_ref().expect("stream state is initialized");
+                        Some(Some(Streamed { state: reduce(&prev.state, item), received: true }))
+                    });
+                }
+            })
+        });
+
+        state.refresh();
+        let streamed = state.as_ref().expect("stream state is initialized");
+        self.record_load(id, streamed.received);
+        streamed.state.clone()
+    }
+
+    /// Spawn `task` for a load at `id`, returning a guard which cancels it when
+    /// dropped.
+    ///
+    /// # Panics
+    ///
+    /// If the [`super::Runtime`] from which `self` was created did not have
+    /// a valid call to `set_task_executor`.
+    fn spawn_task<Output>(
+        &self,
+        id: &topo::CallId,
+        priority: Priority,
+        task: impl Future<Output = ()> + 'static,
+    ) -> TaskGuard {
+        let (task, aborter) = abortable(task);
+        let record = self.registry.register_task(Task {
+            callsite: id.location(),
+            output_type: type_name::<Output>(),
+            spawned_at: self.revision,
+        });
+        let task = async move {
+            // the record is dropped along with the task
+            let _record = record;
+            task.await.ok();
+        };
+        let aborter = Rc::new(aborter);
+        self.spawners
+            .spawn(priority, Box::pin(task).into(), Rc::downgrade(&aborter))
+            .expect("that set_task_executor has been called");
+        scopeguard::guard(aborter, abort)
+    }
+
+    /// Record whether a load at `id` was ready for any load log or suspense
+    /// boundary.
+    fn record_load(&self, id: &topo::CallId, is_ready: bool) {
+        if let Some(log) = &self.load_log {
+            log.record(*id, is_ready);
+        }
+        if !is_ready {
+            if let Ok(boundary) = illicit::get::<Boundary>() {
+                boundary.pending.set(true);
+            }
+            if let Ok(frame) = illicit::get::<Frame>() {
+                frame.pending_load();
+            }
+        }
+    }
+
+    /// Run `body` and cache its output at `id`, returning the cached output
+    /// until `capture` changes or one of the state variables read by `body`
+    /// receives a commit. See [`crate::memo`].
+    pub fn memo<Arg, Input, Output>(
+        &self,
+        id: &topo::CallId,
+        capture: &Arg,
+        body: impl FnOnce() -> Output,
+    ) -> Output
+    where
+        Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+        Input: Borrow<Arg> + 'static,
+        Output: Clone + 'static,
+    {
+        let revision = self.revision.0;
+        let subtree = self.cache.cache(id, capture, |_| Rc::new(Subtree::new(revision)));
+        subtree.invalidate_if_stale(revision);
+
+        let mut ran = false;
+        // cache entries used by the body depend on this one, so they stay live
+        // as long as this is called, whether or not the body runs
+        let output = self.cache_with(
+            id,
+            &subtree.epoch(),
+            |_| {
+                ran = true;
+                let frame = Frame::default();
+                let output = frame.clone().offer(body);
+                subtree.finish(frame);
+                Memoized(output)
+            },
+            |memoized: &Memoized<Output>| memoized.0.clone(),
+        );
+
+        if subtree.report() && !ran {
+            if let Ok(boundary) = illicit::get::<Boundary>() {
+                boundary.pending.set(true);
+            }
+        }
+        output
+    }
+
+    /// Run `children` within a suspense boundary, returning the result of
+    /// `fallback` instead if any of the loads made by `children` have been
+    /// pending for at least `delay`. If `children` are pending but `delay`
+    /// hasn't elapsed, the runtime is woken once it has.
+    ///
+    /// # Panics
+    ///
+    /// If `delay` isn't zero, `children` are pending, and the runtime doesn't
+    /// have a timer.
+    pub fn suspense<Ret>(
+        &self,
+        id: &topo::CallId,
+        delay: Duration,
+        children: impl FnOnce() -> Ret,
+        fallback: impl FnOnce() -> Ret,
+    ) -> Ret {
+        let boundary = Boundary::default();
+        let (ret, boundary) = boundary.offer(|| {
+            let ret = children();
+            (ret, illicit::expect::<Boundary>().pending.get())
+        });
+
+        // use a distinct type from the rest of the cache's per-callsite storage
+        let pending_since: Rc<PendingSince> = self.cache.cache(id, &(), |()| Rc::default());
+        if !boundary {
+            pending_since.0.set(None);
+            return ret;
+        }
+
+        if delay == Duration::default() {
+            return fallback();
+        }
+
+        let timer = match self.timer() {
+            Some(timer) => timer,
+            None => {
+                tracing::warn!("suspense_after needs a timer, showing its fallback without delay");
+                return fallback();
+            }
+        };
+        let now = timer.now();
+        let since = pending_since.0.get().unwrap_or(now);
+        pending_since.0.set(Some(since));
+
+        let deadline = since + delay;
+        if now >= deadline {
+            return fallback();
+        }
+
+        self.cache.hold(id, &deadline, |&deadline| {
+            let sleep = timer.sleep(deadline - now);
+            let waker = self.waker.clone();
+            // the fallback should be shown as soon as the delay elapses
+            self.spawn_task::<()>(id, Priority::High, async move {
+                sleep.await;
+                waker.wake();
+            })
+        });
+        ret
+    }
+
+    /// Run `child`, catching any panic. If `child` panics, any values it stored
+    /// in the cache and any effects it scheduled are discarded.
+    pub fn catch_panic<Ret>(&self, child: impl FnOnce() -> Ret) -> Result<Ret, Panicked> {
+        let cache_checkpoint = self.cache.checkpoint();
+        let effects_checkpoint = self.effects.checkpoint();
+        catch_unwind(AssertUnwindSafe(child)).map_err(|payload| {
+            self.effects.rollback(effects_checkpoint);
+            self.cache.rollback(cache_checkpoint);
+            Panicked::new(payload)
+        })
+    }
+
+    /// Schedule `effect` to run in `phase` of this revision if `arg` has
+    /// changed since it last ran. The cleanup returned by the effect is called
+    /// before it runs again or when there's no longer interest in it,
+    /// indicated by a revision in which this was not called with the given
+    /// `id`.
+    pub fn effect<Arg, Input, Clean>(
+        &self,
+        id: &topo::CallId,
+        arg: &Arg,
+        phase: Phase,
+        effect: impl FnOnce(&Input) -> Clean + 'static,
+    ) where
+        Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+        Input: Borrow<Arg> + 'static,
+        Clean: FnOnce() + 'static,
+    {
+        let cleanup: Rc<Cleanup> = self.cache.cache(id, &(), |()| Rc::new(Cleanup::default()));
+        self.cache.hold(id, arg, |input| {
+            let input: Input = input.borrow().to_owned();
+            self.effects.enqueue(phase, move || cleanup.run_effect(|| effect(&input)));
+        });
+    }
+}
+
+/// A running task which is cancelled when dropped.
+type TaskGuard = ScopeGuard<Rc<AbortHandle>, fn(Rc<AbortHandle>)>;
+
+fn abort(handle: Rc<AbortHandle>) {
+    handle.abort();
+}
+
+/// The task started by [`Context::load_result`] for its most recent attempt.
+#[derive(Default)]
+struct Running {
+    attempt: u64,
+    _task: Option<TaskGuard>,
+}
+
+/// Records whether any loads within a suspense boundary were pending. Offered
+/// via [`illicit`] by [`Context::suspense`].
+#[derive(Debug, Default)]
+struct Boundary {
+    pending: Cell<bool>,
 }
 
+/// The output of a memoized subtree.
+struct Memoized<Output>(Output);
+
+/// The state of a stream consumed by [`Context::load_stream`].
+struct Streamed<State> {
+    state: State,
+    /// Whether the stream has produced any items.
+    received: bool,
+}
+
+/// When a suspense boundary's children first became pending.
+#[derive(Debug, Default)]
+struct PendingSince(Cell<Option<Duration>>);
+
 impl super::Runtime {
     pub(crate) fn context_handle(&self) -> Context {
         Context {
             revision: self.revision,
-            spawner: self.spawner.clone(),
+            spawners: self.spawners.clone(),
             cache: self.cache.clone(),
             waker: self.wk.clone(),
+            effects: self.effects.clone(),
+            registry: self.registry.clone(),
+            profiler: self.profiler.clone(),
+            recorder: self.recorder.clone(),
+            subscriptions: self.subscriptions.clone(),
+            load_log: self.load_log.clone(),
+            timer: self.timer.clone(),
+            #[cfg(feature = "persist")]
+            persisted: self.persisted.clone(),
         }
     }
 }
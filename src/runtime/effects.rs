@@ -0,0 +1,110 @@
+use std::{
+    cell::RefCell,
+    fmt::{Debug, Formatter, Result as FmtResult},
+    mem::take,
+    rc::Rc,
+};
+
+/// The phase of a revision in which an effect runs.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Phase {
+    /// Runs immediately after the root function returns, before the cache is
+    /// collected.
+    Layout,
+    /// Runs after all layout effects and after the cache is collected.
+    Passive,
+}
+
+/// Effects which have been scheduled during a revision and will run once the
+/// root function has returned.
+#[derive(Clone, Default)]
+pub(crate) struct Effects {
+    inner: Rc<RefCell<Queued>>,
+}
+
+#[derive(Default)]
+struct Queued {
+    layout: Vec<Box<dyn FnOnce()>>,
+    passive: Vec<Box<dyn FnOnce()>>,
+}
+
+impl Effects {
+    /// Schedule `effect` to run in `phase` of the current revision.
+    pub fn enqueue(&self, phase: Phase, effect: impl FnOnce() + 'static) {
+        let mut queued = self.inner.borrow_mut();
+        match phase {
+            Phase::Layout => queued.layout.push(Box::new(effect)),
+            Phase::Passive => queued.passive.push(Box::new(effect)),
+        }
+    }
+
+    /// Returns the number of effects scheduled so far for each phase, to be
+    /// passed to [`Effects::rollback`].
+    pub fn checkpoint(&self) -> (usize, usize) {
+        let queued = self.inner.borrow();
+        (queued.layout.len(), queued.passive.len())
+    }
+
+    /// Drop any effects scheduled since `checkpoint` was returned by
+    /// [`Effects::checkpoint`].
+    pub fn rollback(&self, (layout, passive): (usize, usize)) {
+        let mut queued = self.inner.borrow_mut();
+        queued.layout.truncate(layout);
+        queued.passive.truncate(passive);
+    }
+
+    /// Run all scheduled effects for `phase` in the order they were scheduled.
+    pub fn run(&self, phase: Phase) {
+        let to_run = {
+            let mut queued = self.inner.borrow_mut();
+            match phase {
+                Phase::Layout => take(&mut queued.layout),
+                Phase::Passive => take(&mut queued.passive),
+            }
+        };
+        to_run.into_iter().for_each(|effect| effect());
+    }
+}
+
+impl Debug for Effects {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        let queued = self.inner.borrow();
+        f.debug_struct("Effects")
+            .field("layout", &queued.layout.len())
+            .field("passive", &queued.passive.len())
+            .finish()
+    }
+}
+
+/// Holds the cleanup returned by the most recent run of an effect, calling it
+/// before the effect runs again or when the effect's callsite is dropped from
+/// the cache.
+#[derive(Default)]
+pub(crate) struct Cleanup {
+    inner: RefCell<Option<Box<dyn FnOnce()>>>,
+}
+
+impl Cleanup {
+    /// Call any previous cleanup, then run `effect` and store its cleanup.
+    pub fn run_effect<C>(&self, effect: impl FnOnce() -> C)
+    where
+        C: FnOnce() + 'static,
+    {
+        self.clean_up();
+        let cleanup = effect();
+        *self.inner.borrow_mut() = Some(Box::new(cleanup));
+    }
+
+    fn clean_up(&self) {
+        let prev = self.inner.borrow_mut().take();
+        if let Some(prev) = prev {
+            prev();
+        }
+    }
+}
+
+impl Drop for Cleanup {
+    fn drop(&mut self) {
+        self.clean_up();
+    }
+}
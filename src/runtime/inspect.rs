@@ -0,0 +1,310 @@
+use super::{RecordedCommit, Revision};
+use dyn_cache::EntryInfo;
+use std::{
+    any::Any,
+    cell::RefCell,
+    fmt::{Debug, Formatter, Result as FmtResult},
+    panic::Location,
+    rc::{Rc, Weak},
+    sync::Weak as SyncWeak,
+};
+
+/// A snapshot of the contents of a [`super::Runtime`], returned by
+/// [`super::Runtime::inspect`].
+#[derive(Debug)]
+pub struct Inspection {
+    /// The runtime's revision when the snapshot was taken.
+    pub revision: Revision,
+    /// Every value in the runtime's cache, including those backing state
+    /// variables and loads.
+    pub entries: Vec<CacheEntry>,
+    /// Every state variable created by the runtime which hasn't been dropped.
+    pub state: Vec<StateVar>,
+    /// Every task spawned by the runtime which hasn't completed or been
+    /// cancelled.
+    pub tasks: Vec<Task>,
+}
+
+impl Inspection {
+    /// Returns the number of cache entries for each distinct output type,
+    /// sorted by type name.
+    pub fn entries_by_type(&self) -> Vec<(&'static str, usize)> {
+        let mut counts: Vec<(&'static str, usize)> = Vec::new();
+        for entry in &self.entries {
+            match counts.iter_mut().find(|(ty, _)| *ty == entry.output_type) {
+                Some((_, count)) => *count += 1,
+                None => counts.push((entry.output_type, 1)),
+            }
+        }
+        counts.sort();
+        counts
+    }
+}
+
+/// A value stored in a runtime's cache.
+#[derive(Debug)]
+pub struct CacheEntry {
+    /// The source location of the [`topo::CallId`] under which the entry is
+    /// cached, if it is cached under one.
+    pub callsite: Option<&'static Location<'static>>,
+    /// The type name of the entry's input.
+    pub input_type: &'static str,
+    /// The type name of the entry's output.
+    pub output_type: &'static str,
+    /// The most recent revision in which the entry was rooted.
+    pub last_rooted: Revision,
+    /// The size in bytes of the entry's input and output, not including any
+    /// heap allocations they own.
+    pub shallow_size: usize,
+}
+
+impl CacheEntry {
+    pub(super) fn new(entry: EntryInfo<'_>, generations: &Generations) -> Self {
+        Self {
+            callsite: entry.scope.downcast_ref::<topo::CallId>().map(topo::CallId::location),
+            input_type: entry.input_type,
+            output_type: entry.output_type,
+            last_rooted: generations.revision(entry.rooted_generation),
+            shallow_size: entry.shallow_size,
+        }
+    }
+}
+
+/// Maps the generations of a runtime's cache to the revisions which collected
+/// them.
+#[derive(Debug, Default)]
+pub(crate) struct Generations {
+    /// Each generation collected by a revision other than the one following
+    /// the previous generation's, e.g. after a revision panicked before GC.
+    starts: Vec<(u64, Revision)>,
+}
+
+impl Generations {
+    /// Record that `generation` is collected at the end of `revision`.
+    pub fn collect(&mut self, generation: u64, revision: Revision) {
+        if self.revision(generation) != revision {
+            self.starts.push((generation, revision));
+        }
+    }
+
+    /// Returns the revision which collected `generation`, or the current
+    /// revision if it hasn't been collected yet.
+    pub fn revision(&self, generation: u64) -> Revision {
+        match self.starts.iter().rev().find(|(start, _)| *start <= generation) {
+            Some((start, revision)) => Revision(revision.0 + (generation - start)),
+            None => Revision(generation + 1),
+        }
+    }
+}
+
+/// A state variable created with [`crate::state`] or
+/// [`crate::cache_state`].
+#[derive(Debug)]
+pub struct StateVar {
+    /// The source location at which the state variable was created.
+    pub callsite: &'static Location<'static>,
+    /// The type name of the state variable's contents.
+    pub state_type: &'static str,
+    /// The most recent revision in which the state variable was rooted.
+    pub last_rooted: Revision,
+    /// Whether the state variable has a commit which will be applied the next
+    /// time it is rooted.
+    pub has_pending: bool,
+    /// The size in bytes of the state variable's contents, not including any
+    /// heap allocations they own.
+    pub shallow_size: usize,
+}
+
+/// A task spawned by [`crate::load_with`] or one of its variants.
+#[derive(Clone, Debug)]
+pub struct Task {
+    /// The source location of the load which spawned the task.
+    pub callsite: &'static Location<'static>,
+    /// The type name of the task's output.
+    pub output_type: &'static str,
+    /// The revision during which the task was spawned.
+    pub spawned_at: Revision,
+}
+
+/// A state variable erased over its type.
+pub(crate) trait InspectVar {
+    fn describe(&self) -> StateVar;
+
+    fn id(&self) -> topo::CallId;
+
+    /// Make `commit` pending without waking the runtime, returning false if it
+    /// isn't a commit of this variable's type.
+    fn replay(&self, commit: &dyn Any) -> bool;
+}
+
+/// Tracks the state variables and tasks created by a runtime without keeping
+/// them alive.
+#[derive(Clone, Default)]
+pub(crate) struct Registry {
+    inner: Rc<RefCell<Registered>>,
+}
+
+#[derive(Default)]
+struct Registered {
+    vars: Vec<SyncWeak<dyn InspectVar>>,
+    tasks: Vec<Weak<Task>>,
+}
+
+impl Registry {
+    pub fn register_var(&self, var: SyncWeak<dyn InspectVar>) {
+        push_pruned(&mut self.inner.borrow_mut().vars, var, |v| v.strong_count() > 0);
+    }
+
+    /// Register a task, returning a record which must be kept alive for as long
+    /// as the task is running.
+    pub fn register_task(&self, task: Task) -> Rc<Task> {
+        let task = Rc::new(task);
+        push_pruned(&mut self.inner.borrow_mut().tasks, Rc::downgrade(&task), |t| {
+            t.strong_count() > 0
+        });
+        task
+    }
+
+    pub fn state(&self) -> Vec<StateVar> {
+        let inner = self.inner.borrow();
+        inner.vars.iter().filter_map(SyncWeak::upgrade).map(|var| var.describe()).collect()
+    }
+
+    /// Replay `commit` to the most recently registered live state variable
+    /// with its id and type, returning false if there isn't one.
+    pub fn replay(&self, commit: &RecordedCommit) -> bool {
+        let inner = self.inner.borrow();
+        inner
+            .vars
+            .iter()
+            .rev()
+            .filter_map(SyncWeak::upgrade)
+            .filter(|var| var.id() == commit.id)
+            .any(|var| var.replay(commit.erased()))
+    }
+
+    pub fn tasks(&self) -> Vec<Task> {
+        let inner = self.inner.borrow();
+        inner.tasks.iter().filter_map(Weak::upgrade).map(|task| (*task).clone()).collect()
+    }
+}
+
+/// Push `item`, first dropping any dead items if the vec would need to grow.
+fn push_pruned<T>(items: &mut Vec<T>, item: T, is_alive: impl FnMut(&T) -> bool) {
+    if items.len() == items.capacity() {
+        items.retain(is_alive);
+    }
+    items.push(item);
+}
+
+impl Debug for Registry {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        let inner = self.inner.borrow();
+        f.debug_struct("Registry")
+            .field("vars", &inner.vars.len())
+            .field("tasks", &inner.tasks.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cache, load_once,
+        runtime::{Retention, Runtime},
+        state,
+    };
+    use futures::future::pending;
+    use std::task::Poll;
+
+    #[test]
+    fn cached_values_are_reported_with_callsites() {
+        let mut rt = Runtime::new();
+        let root = || {
+            cache(&1u8, |&n| u16::from(n));
+        };
+        rt.run_once(root);
+        rt.run_once(root);
+
+        let report = rt.inspect();
+        assert_eq!(report.revision, Revision(2));
+        assert_eq!(report.entries.len(), 1);
+
+        let entry = &report.entries[0];
+        assert_eq!(entry.callsite.unwrap().file(), file!());
+        assert_eq!(entry.input_type, "u8");
+        assert_eq!(entry.output_type, "u16");
+        assert_eq!(entry.last_rooted, Revision(2));
+        assert_eq!(entry.shallow_size, 3);
+        assert_eq!(report.entries_by_type(), vec![("u16", 1)]);
+
+        rt.run_once(|| ());
+        assert_eq!(rt.inspect().entries.len(), 0, "unused entries are collected");
+    }
+
+    #[test]
+    fn entries_report_the_revision_they_were_rooted_in() {
+        let mut rt = Runtime::new();
+        rt.set_default_retention(Retention::Revisions(2));
+        let root = || cache(&(), |_| cache(&1u8, |&n| n));
+        rt.run_once(root);
+        rt.run_once(root);
+        rt.run_once(|| ());
+
+        let mut rooted: Vec<_> =
+            rt.inspect().entries.iter().map(|e| (e.output_type, e.last_rooted)).collect();
+        rooted.sort();
+        assert_eq!(rooted, vec![("u8", Revision(1)), ("u8", Revision(2))]);
+    }
+
+    #[test]
+    fn revisions_which_panic_before_gc_are_skipped() {
+        let mut generations = Generations::default();
+        generations.collect(0, Revision(1));
+        generations.collect(1, Revision(3));
+        generations.collect(2, Revision(4));
+        assert_eq!(generations.revision(0), Revision(1));
+        assert_eq!(generations.revision(1), Revision(3));
+        assert_eq!(generations.revision(3), Revision(5));
+    }
+
+    #[test]
+    fn state_vars_are_reported_until_dropped() {
+        let mut rt = Runtime::new();
+        let (_, key) = rt.run_once(|| state(|| 0u32));
+        key.set(1);
+
+        let report = rt.inspect();
+        assert_eq!(report.state.len(), 1);
+        assert_eq!(report.state[0].callsite.file(), file!());
+        assert_eq!(report.state[0].state_type, "u32");
+        assert_eq!(report.state[0].last_rooted, Revision(1));
+        assert!(report.state[0].has_pending);
+
+        rt.run_once(|| ());
+        assert_eq!(rt.inspect().state.len(), 1, "the key keeps the var alive");
+        drop(key);
+        assert_eq!(rt.inspect().state.len(), 0);
+    }
+
+    #[test]
+    fn tasks_are_reported_until_cancelled() {
+        let mut pool = futures::executor::LocalPool::new();
+        let mut rt = Runtime::new();
+        rt.set_task_executor(pool.spawner());
+        let root = || load_once(pending::<u8>);
+
+        assert_eq!(rt.run_once(root), Poll::Pending);
+        pool.run_until_stalled();
+        let tasks = rt.inspect().tasks;
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0].callsite.file(), file!());
+        assert_eq!(tasks[0].output_type, "u8");
+        assert_eq!(tasks[0].spawned_at, Revision(1));
+
+        rt.run_once(|| ());
+        pool.run_until_stalled();
+        assert_eq!(rt.inspect().tasks.len(), 0, "the task was cancelled");
+    }
+}
@@ -0,0 +1,117 @@
+use std::{
+    cell::{Cell, RefCell},
+    fmt::{Debug, Formatter, Result as FmtResult},
+    rc::Rc,
+    sync::Weak,
+};
+
+/// A state variable erased over its type, which counts the commits made to it.
+pub(crate) trait Versioned {
+    /// Returns the number of commits which have been enqueued or published.
+    fn version(&self) -> u64;
+}
+
+/// A state variable read by a subtree, and its version when it was read.
+#[derive(Clone)]
+struct Read {
+    var: Weak<dyn Versioned>,
+    version: u64,
+}
+
+impl Read {
+    fn is_stale(&self) -> bool {
+        match self.var.upgrade() {
+            Some(var) => var.version() != self.version,
+            None => true,
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+struct Reads {
+    vars: Vec<Read>,
+    pending_loads: bool,
+}
+
+/// Records the state variables read while running a memoized subtree. Offered
+/// via [`illicit`] while the subtree runs.
+#[derive(Clone, Default)]
+pub(crate) struct Frame {
+    reads: Rc<RefCell<Reads>>,
+}
+
+impl Frame {
+    pub fn read(&self, var: Weak<dyn Versioned>) {
+        if let Some(current) = var.upgrade() {
+            let version = current.version();
+            self.reads.borrow_mut().vars.push(Read { var, version });
+        }
+    }
+
+    pub fn pending_load(&self) {
+        self.reads.borrow_mut().pending_loads = true;
+    }
+
+    fn extend(&self, other: &Reads) {
+        let mut reads = self.reads.borrow_mut();
+        reads.vars.extend(other.vars.iter().cloned());
+        reads.pending_loads |= other.pending_loads;
+    }
+}
+
+impl Debug for Frame {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("Frame").field("reads", &self.reads.borrow().vars.len()).finish()
+    }
+}
+
+/// The reads made by the most recent run of a memoized subtree.
+#[derive(Default)]
+pub(crate) struct Subtree {
+    epoch: Cell<u64>,
+    reads: RefCell<Reads>,
+}
+
+impl Subtree {
+    pub fn new(epoch: u64) -> Self {
+        Self { epoch: Cell::new(epoch), reads: Default::default() }
+    }
+
+    /// Identifies the subtree's current output. Changes when the subtree is
+    /// invalidated.
+    pub fn epoch(&self) -> u64 {
+        self.epoch.get()
+    }
+
+    /// Sets a new epoch if any of the state variables read by the subtree have
+    /// received commits since.
+    pub fn invalidate_if_stale(&self, epoch: u64) {
+        if self.reads.borrow().vars.iter().any(Read::is_stale) {
+            self.epoch.set(epoch);
+        }
+    }
+
+    /// Store the reads recorded by `frame` during a run of the subtree.
+    pub fn finish(&self, frame: Frame) {
+        *self.reads.borrow_mut() = frame.reads.replace(Reads::default());
+    }
+
+    /// Add the subtree's reads to the enclosing subtree's frame, if any.
+    /// Returns whether the subtree had any pending loads.
+    pub fn report(&self) -> bool {
+        let reads = self.reads.borrow();
+        if let Ok(parent) = illicit::get::<Frame>() {
+            parent.extend(&reads);
+        }
+        reads.pending_loads
+    }
+}
+
+impl Debug for Subtree {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("Subtree")
+            .field("epoch", &self.epoch.get())
+            .field("reads", &self.reads.borrow().vars.len())
+            .finish()
+    }
+}
@@ -0,0 +1,159 @@
+use super::Var;
+use parking_lot::Mutex;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{Error, Value};
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    fmt::{Debug, Formatter, Result as FmtResult},
+    rc::Rc,
+    sync::Weak,
+};
+
+/// The values of a runtime's persisted state variables, keyed by the names
+/// they were given in [`crate::persisted_state`]. Returned by
+/// [`super::Runtime::snapshot`] and consumed by [`super::Runtime::hydrate`].
+///
+/// A snapshot can be written and read with any serde format.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(transparent)]
+pub struct Snapshot {
+    values: BTreeMap<String, Value>,
+}
+
+impl Snapshot {
+    /// Returns the number of state variables in the snapshot.
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Returns true if the snapshot has no state variables.
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Returns the names of the state variables in the snapshot, sorted.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.values.keys().map(String::as_str)
+    }
+}
+
+/// A state variable erased over its type.
+pub(crate) trait PersistVar {
+    fn to_value(&self) -> Result<Value, Error>;
+}
+
+impl<State: Serialize> PersistVar for Mutex<Var<State>> {
+    fn to_value(&self) -> Result<Value, Error> {
+        serde_json::to_value(self.lock().latest())
+    }
+}
+
+/// Tracks a runtime's persisted state variables by name without keeping them
+/// alive, along with any hydrated values which haven't been claimed yet.
+#[derive(Clone, Default)]
+pub(crate) struct Persisted {
+    inner: Rc<RefCell<Inner>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    hydrated: BTreeMap<String, Value>,
+    vars: BTreeMap<String, Weak<dyn PersistVar>>,
+}
+
+impl Persisted {
+    pub fn hydrate(&self, snapshot: Snapshot) {
+        self.inner.borrow_mut().hydrated.extend(snapshot.values);
+    }
+
+    /// Claim the hydrated value for `name`, if there is one which can be
+    /// deserialized as a `State`.
+    pub fn take<State: DeserializeOwned>(&self, name: &str) -> Option<State> {
+        let value = self.inner.borrow_mut().hydrated.remove(name)?;
+        match serde_json::from_value(value) {
+            Ok(state) => Some(state),
+            Err(error) => {
+                tracing::warn!(name, %error, "discarding persisted state which failed to load");
+                None
+            }
+        }
+    }
+
+    pub fn register(&self, name: String, var: Weak<dyn PersistVar>) {
+        let mut inner = self.inner.borrow_mut();
+        inner.vars.retain(|_, v| v.strong_count() > 0);
+        inner.vars.insert(name, var);
+    }
+
+    /// Serializes the latest value of each live state variable. Hydrated
+    /// values which haven't been claimed are carried over.
+    pub fn snapshot(&self) -> Result<Snapshot, Error> {
+        let inner = self.inner.borrow();
+        let mut values = inner.hydrated.clone();
+        for (name, var) in &inner.vars {
+            if let Some(var) = var.upgrade() {
+                values.insert(name.clone(), var.to_value()?);
+            }
+        }
+        Ok(Snapshot { values })
+    }
+}
+
+impl Debug for Persisted {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        let inner = self.inner.borrow();
+        f.debug_struct("Persisted")
+            .field("hydrated", &inner.hydrated.len())
+            .field("vars", &inner.vars.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{persisted_state, runtime::Runtime, state};
+
+    #[test]
+    fn later_runtimes_start_from_a_snapshot() {
+        let root = || {
+            let (count, count_key) = persisted_state("count", || 0u32);
+            let (name, _) = persisted_state("name", || String::from("anon"));
+            let (_, scratch_key) = state(|| 0u8);
+            (*count, (*name).clone(), count_key, scratch_key)
+        };
+
+        let mut rt = Runtime::new();
+        let (count, name, count_key, scratch_key) = rt.run_once(root);
+        assert_eq!((count, name.as_str()), (0, "anon"));
+        count_key.set(5);
+        scratch_key.set(9);
+
+        let snapshot = rt.snapshot().unwrap();
+        assert_eq!(snapshot.names().collect::<Vec<_>>(), vec!["count", "name"]);
+        let serialized = serde_json::to_string(&snapshot).unwrap();
+        assert_eq!(serialized, r#"{"count":5,"name":"anon"}"#);
+
+        let mut rt = Runtime::new();
+        rt.hydrate(serde_json::from_str(&serialized).unwrap());
+        let (count, name, _, _) = rt.run_once(root);
+        assert_eq!((count, name.as_str()), (5, "anon"));
+        assert_eq!(rt.snapshot().unwrap(), snapshot);
+    }
+
+    #[test]
+    fn unclaimed_and_invalid_values() {
+        let mut snapshot = Snapshot::default();
+        snapshot.values.insert("count".into(), "not a number".into());
+        snapshot.values.insert("elsewhere".into(), 1.into());
+
+        let mut rt = Runtime::new();
+        rt.hydrate(snapshot);
+        let (count, _) = rt.run_once(|| persisted_state("count", || 3u32));
+        assert_eq!(*count, 3, "values which fail to load are replaced by init");
+
+        let names = rt.snapshot().unwrap().names().map(String::from).collect::<Vec<_>>();
+        assert_eq!(names, vec!["count", "elsewhere"], "unclaimed values are kept");
+    }
+}
@@ -0,0 +1,122 @@
+use super::{JunkSpawner, Spawner};
+use futures::{
+    future::{AbortHandle, LocalFutureObj},
+    task::{LocalSpawn, SpawnError},
+};
+use std::{
+    cell::RefCell,
+    fmt::{Debug, Formatter, Result as FmtResult},
+    rc::{Rc, Weak},
+};
+
+/// The urgency of a task spawned by a [`super::Runtime`]. Each priority can
+/// be given its own executor with [`super::Runtime::set_priority_executor`].
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Priority {
+    /// Work which should run before anything else, like responding to input.
+    /// Spawned with the normal executor if no high priority executor is set.
+    High,
+    /// Most work. Spawned with the executor passed to
+    /// [`super::Runtime::set_task_executor`].
+    Normal,
+    /// Work which can wait until the runtime has nothing else to do.
+    /// Not spawned until the revision which requested it has completed, and
+    /// spawned with the normal executor if no idle executor is set.
+    Idle,
+}
+
+/// The executors for each [`Priority`], along with any idle tasks waiting for
+/// their revision to complete.
+#[derive(Clone)]
+pub(crate) struct Spawners {
+    high: Option<Spawner>,
+    normal: Spawner,
+    idle: Option<Spawner>,
+    waiting: Rc<RefCell<Vec<Waiting>>>,
+}
+
+/// An idle task waiting to be spawned, and the handle which cancels it.
+struct Waiting {
+    task: LocalFutureObj<'static, ()>,
+    aborter: Weak<AbortHandle>,
+}
+
+impl Waiting {
+    /// Returns false if the task was cancelled before it could be spawned.
+    fn is_wanted(&self) -> bool {
+        self.aborter.strong_count() > 0
+    }
+}
+
+impl Default for Spawners {
+    fn default() -> Self {
+        Self {
+            high: None,
+            normal: Spawner(Rc::new(JunkSpawner)),
+            idle: None,
+            waiting: Default::default(),
+        }
+    }
+}
+
+impl Spawners {
+    pub fn set(&mut self, priority: Priority, spawner: impl LocalSpawn + 'static) {
+        let spawner = Spawner(Rc::new(spawner));
+        match priority {
+            Priority::High => self.high = Some(spawner),
+            Priority::Normal => self.normal = spawner,
+            Priority::Idle => self.idle = Some(spawner),
+        }
+    }
+
+    /// Spawn `task` with the executor for `priority`. Idle tasks wait for a
+    /// call to [`Spawners::spawn_idle`], and are never spawned if `aborter` is
+    /// dropped first.
+    pub fn spawn(
+        &self,
+        priority: Priority,
+        task: LocalFutureObj<'static, ()>,
+        aborter: Weak<AbortHandle>,
+    ) -> Result<(), SpawnError> {
+        match priority {
+            Priority::High => self.high.as_ref().unwrap_or(&self.normal).0.spawn_local_obj(task),
+            Priority::Normal => self.normal.0.spawn_local_obj(task),
+            Priority::Idle => {
+                self.idle.as_ref().unwrap_or(&self.normal).0.status_local()?;
+                self.waiting.borrow_mut().push(Waiting { task, aborter });
+                Ok(())
+            }
+        }
+    }
+
+    /// Drop any waiting idle tasks which were cancelled before being spawned.
+    pub fn drop_cancelled_idle(&self) {
+        self.waiting.borrow_mut().retain(Waiting::is_wanted);
+    }
+
+    /// Spawn any idle tasks which are waiting and haven't been cancelled.
+    pub fn spawn_idle(&self) {
+        let spawner = self.idle.as_ref().unwrap_or(&self.normal);
+        for waiting in std::mem::take(&mut *self.waiting.borrow_mut()) {
+            if waiting.is_wanted() {
+                (spawner.0.spawn_local_obj(waiting.task))
+                    .expect("that the idle executor is still running");
+            }
+        }
+    }
+
+    pub fn num_idle_waiting(&self) -> usize {
+        self.waiting.borrow().len()
+    }
+}
+
+impl Debug for Spawners {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("Spawners")
+            .field("high", &self.high)
+            .field("normal", &self.normal)
+            .field("idle", &self.idle)
+            .field("waiting", &self.num_idle_waiting())
+            .finish()
+    }
+}
@@ -0,0 +1,319 @@
+use super::Revision;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt::{Debug, Formatter, Result as FmtResult},
+    io::{Result as IoResult, Write},
+    panic::Location,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+use topo::CallId;
+
+/// Measurements of the revisions run while profiling was enabled with
+/// [`super::Runtime::set_profiling`]. Returned by
+/// [`super::Runtime::take_profile`].
+#[derive(Debug, Default)]
+pub struct Profile {
+    /// Each profiled revision, in the order they ran.
+    pub revisions: Vec<RevisionProfile>,
+}
+
+impl Profile {
+    /// Writes the profile as a [Chrome trace-event] JSON file which can be
+    /// loaded by `chrome://tracing` or [Perfetto]. Each revision and each call
+    /// to an init closure is written as a complete event, and each revision's
+    /// hit, miss, and eviction counts are written as counter events.
+    ///
+    /// [Chrome trace-event]: https://docs.google.com/document/d/1CvAClvFfyA5R-PhYUmn5OOQtYMH4h6I0nSsKchNAySU
+    /// [Perfetto]: https://ui.perfetto.dev
+    pub fn write_chrome_trace(&self, mut out: impl Write) -> IoResult<()> {
+        let mut first = true;
+        let mut event = |out: &mut dyn Write, body: std::fmt::Arguments| {
+            let sep = if first { "" } else { "," };
+            first = false;
+            write!(out, "{}\n{{{},\"pid\":1,\"tid\":1}}", sep, body)
+        };
+
+        write!(out, "{{\"traceEvents\":[")?;
+        for rev in &self.revisions {
+            let ts = micros(rev.start);
+            event(
+                &mut out,
+                format_args!(
+                    "\"name\":\"{:?}\",\"cat\":\"revision\",\"ph\":\"X\",\"ts\":{},\"dur\":{}",
+                    rev.revision,
+                    ts,
+                    micros(rev.duration)
+                ),
+            )?;
+            for init in &rev.inits {
+                event(
+                    &mut out,
+                    format_args!(
+                        "\"name\":\"{}\",\"cat\":\"init\",\"ph\":\"X\",\"ts\":{},\"dur\":{}",
+                        escape(&init.callsite.to_string()),
+                        micros(init.start),
+                        micros(init.duration)
+                    ),
+                )?;
+            }
+            let counts = format!(
+                "{{\"hits\":{},\"misses\":{},\"evicted\":{}}}",
+                rev.hits(),
+                rev.misses(),
+                rev.evicted
+            );
+            event(
+                &mut out,
+                format_args!("\"name\":\"cache\",\"ph\":\"C\",\"ts\":{},\"args\":{}", ts, counts),
+            )?;
+        }
+        write!(out, "\n]}}")
+    }
+}
+
+/// Measurements of a single revision.
+#[derive(Debug)]
+pub struct RevisionProfile {
+    /// The revision measured.
+    pub revision: Revision,
+    /// When the revision started, relative to when profiling was enabled.
+    pub start: Duration,
+    /// How long the revision took, including effects and GC.
+    pub duration: Duration,
+    /// Cache usage during the revision for each callsite, sorted by location.
+    pub callsites: Vec<CallsiteProfile>,
+    /// Each call to an init closure during the revision, in the order they
+    /// started.
+    pub inits: Vec<InitTiming>,
+    /// The number of cache entries dropped by GC at the end of the revision.
+    pub evicted: usize,
+}
+
+impl RevisionProfile {
+    /// The total number of cache hits during the revision.
+    pub fn hits(&self) -> u64 {
+        self.callsites.iter().map(|c| c.hits).sum()
+    }
+
+    /// The total number of cache misses during the revision.
+    pub fn misses(&self) -> u64 {
+        self.callsites.iter().map(|c| c.misses).sum()
+    }
+}
+
+/// Cache usage by all of the [`topo::CallId`]s sharing a source location.
+#[derive(Clone, Debug)]
+pub struct CallsiteProfile {
+    /// The source location of the cache call.
+    pub callsite: &'static Location<'static>,
+    /// The number of calls which returned a cached value.
+    pub hits: u64,
+    /// The number of calls which ran their init closure.
+    pub misses: u64,
+    /// The total time spent in init closures, including any nested calls.
+    pub init_time: Duration,
+}
+
+/// A single call to a cache init closure.
+#[derive(Clone, Debug)]
+pub struct InitTiming {
+    /// The source location of the cache call.
+    pub callsite: &'static Location<'static>,
+    /// When the init closure started, relative to when profiling was enabled.
+    pub start: Duration,
+    /// How long the init closure took, including any nested calls.
+    pub duration: Duration,
+}
+
+/// Records a [`Profile`] while enabled, otherwise does nothing.
+#[derive(Clone, Default)]
+pub(crate) struct Profiler {
+    inner: Rc<RefCell<Option<Recorder>>>,
+}
+
+struct Recorder {
+    epoch: Instant,
+    revisions: Vec<RevisionProfile>,
+    current: Option<Current>,
+}
+
+struct Current {
+    revision: Revision,
+    start: Instant,
+    callsites: HashMap<*const Location<'static>, CallsiteProfile>,
+    inits: Vec<InitTiming>,
+}
+
+impl Current {
+    fn callsite(&mut self, callsite: &'static Location<'static>) -> &mut CallsiteProfile {
+        self.callsites.entry(callsite as *const _).or_insert_with(|| CallsiteProfile {
+            callsite,
+            hits: 0,
+            misses: 0,
+            init_time: Duration::default(),
+        })
+    }
+}
+
+impl Profiler {
+    pub fn set_enabled(&self, enabled: bool) {
+        let mut inner = self.inner.borrow_mut();
+        match (enabled, inner.is_some()) {
+            (true, false) => {
+                *inner = Some(Recorder { epoch: Instant::now(), revisions: vec![], current: None })
+            }
+            (false, true) => *inner = None,
+            _ => (),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.inner.borrow().is_some()
+    }
+
+    pub fn start_revision(&self, revision: Revision) {
+        if let Some(recorder) = &mut *self.inner.borrow_mut() {
+            recorder.current = Some(Current {
+                revision,
+                start: Instant::now(),
+                callsites: HashMap::new(),
+                inits: vec![],
+            });
+        }
+    }
+
+    pub fn finish_revision(&self, evicted: usize) {
+        if let Some(recorder) = &mut *self.inner.borrow_mut() {
+            if let Some(current) = recorder.current.take() {
+                let mut callsites: Vec<_> = current.callsites.values().cloned().collect();
+                callsites
+                    .sort_by_key(|c| (c.callsite.file(), c.callsite.line(), c.callsite.column()));
+                recorder.revisions.push(RevisionProfile {
+                    revision: current.revision,
+                    start: current.start - recorder.epoch,
+                    duration: current.start.elapsed(),
+                    callsites,
+                    inits: current.inits,
+                    evicted,
+                });
+            }
+        }
+    }
+
+    /// Record a cache hit at `id`.
+    pub fn hit(&self, id: &CallId) {
+        if let Some(Recorder { current: Some(current), .. }) = &mut *self.inner.borrow_mut() {
+            current.callsite(id.location()).hits += 1;
+        }
+    }
+
+    /// Run `init` as a cache miss at `id`, timing it if profiling.
+    pub fn miss<R>(&self, id: &CallId, init: impl FnOnce() -> R) -> R {
+        if !self.is_enabled() {
+            return init();
+        }
+
+        let callsite = id.location();
+        let span = tracing::info_span!("cache_init", callsite = %callsite);
+        let start = Instant::now();
+        let ret = span.in_scope(init);
+        let duration = start.elapsed();
+
+        if let Some(Recorder { epoch, current: Some(current), .. }) = &mut *self.inner.borrow_mut()
+        {
+            let stats = current.callsite(callsite);
+            stats.misses += 1;
+            stats.init_time += duration;
+            current.inits.push(InitTiming { callsite, start: start - *epoch, duration });
+        }
+        ret
+    }
+
+    pub fn take(&self) -> Profile {
+        let revisions = match &mut *self.inner.borrow_mut() {
+            Some(recorder) => std::mem::take(&mut recorder.revisions),
+            None => vec![],
+        };
+        Profile { revisions }
+    }
+}
+
+impl Debug for Profiler {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("Profiler").field("enabled", &self.is_enabled()).finish()
+    }
+}
+
+fn micros(duration: Duration) -> u128 {
+    duration.as_micros()
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cache, once, runtime::Runtime};
+
+    #[test]
+    fn counts_hits_misses_and_evictions() {
+        let mut rt = Runtime::new();
+        rt.set_profiling(true);
+        let root = |n: u8| {
+            for i in 0..n {
+                topo::call(|| cache(&i, |&i| i));
+            }
+            once(|| ());
+        };
+
+        rt.run_once(|| root(3));
+        rt.run_once(|| root(2));
+
+        let profile = rt.take_profile();
+        assert_eq!(profile.revisions.len(), 2);
+
+        let (first, second) = (&profile.revisions[0], &profile.revisions[1]);
+        assert_eq!(first.revision, Revision(1));
+        assert_eq!((first.hits(), first.misses(), first.evicted), (0, 4, 0));
+        assert_eq!(first.inits.len(), 4);
+        assert_eq!((second.hits(), second.misses(), second.evicted), (3, 0, 1));
+        assert!(second.inits.is_empty());
+
+        let loop_callsite = &second.callsites[0];
+        assert_eq!(loop_callsite.callsite.file(), file!());
+        assert_eq!(loop_callsite.hits, 2, "callsites are shared by CallIds in a loop");
+
+        assert!(rt.take_profile().revisions.is_empty(), "profiles are drained");
+    }
+
+    #[test]
+    fn nothing_recorded_when_disabled() {
+        let mut rt = Runtime::new();
+        rt.run_once(|| cache(&(), |_| ()));
+        rt.set_profiling(true);
+        rt.set_profiling(false);
+        rt.run_once(|| cache(&(), |_| ()));
+        assert!(rt.take_profile().revisions.is_empty());
+    }
+
+    #[test]
+    fn chrome_trace_has_an_event_per_revision_and_init() {
+        let mut rt = Runtime::new();
+        rt.set_profiling(true);
+        rt.run_once(|| cache(&(), |_| ()));
+
+        let mut out = Vec::new();
+        rt.take_profile().write_chrome_trace(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert!(out.starts_with("{\"traceEvents\":["));
+        assert!(out.ends_with("]}"));
+        assert_eq!(out.matches("\"ph\":\"X\"").count(), 2);
+        assert_eq!(out.matches("\"cat\":\"init\"").count(), 1);
+        assert!(out.contains("\"args\":{\"hits\":0,\"misses\":1,\"evicted\":0}"));
+    }
+}
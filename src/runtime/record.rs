@@ -0,0 +1,346 @@
+use super::{Revision, Runtime};
+use crate::Commit;
+use std::{
+    any::{type_name, Any, TypeId},
+    cell::RefCell,
+    collections::HashMap,
+    fmt::{Debug, Formatter, Result as FmtResult},
+    rc::Rc,
+};
+use topo::CallId;
+
+/// The commits applied to a runtime's state variables while recording was
+/// enabled with [`Runtime::set_recording`]. Returned by
+/// [`Runtime::take_recording`] and replayed by [`TimeTravel`].
+#[derive(Clone, Debug, Default)]
+pub struct Recording {
+    /// Each recorded commit, in the order they were applied.
+    pub commits: Vec<RecordedCommit>,
+    /// The last revision which ran while recording.
+    pub end: Revision,
+}
+
+impl Recording {
+    /// Returns the commits which were applied during `revision`.
+    pub fn at(&self, revision: Revision) -> impl Iterator<Item = &RecordedCommit> {
+        self.commits.iter().filter(move |c| c.revision == revision)
+    }
+}
+
+/// A single commit to a state variable.
+#[derive(Clone)]
+pub struct RecordedCommit {
+    /// The [`topo::CallId`] of the state variable.
+    pub id: CallId,
+    /// The revision during which the commit was applied.
+    pub revision: Revision,
+    /// The type name of the state variable's contents.
+    pub state_type: &'static str,
+    /// The `Debug` rendering of the committed value, if its type was
+    /// registered with [`Runtime::record_debug`].
+    pub rendering: Option<String>,
+    commit: Rc<dyn Any>,
+}
+
+impl RecordedCommit {
+    /// Returns the committed value if it is a `State`.
+    pub fn value<State: 'static>(&self) -> Option<&State> {
+        self.commit.downcast_ref::<Commit<State>>().map(|c| &**c)
+    }
+
+    /// Returns the committed value as a type-erased `Commit<State>`.
+    pub(crate) fn erased(&self) -> &dyn Any {
+        &*self.commit
+    }
+}
+
+impl Debug for RecordedCommit {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("RecordedCommit")
+            .field("id", &self.id)
+            .field("revision", &self.revision)
+            .field("state_type", &self.state_type)
+            .field("rendering", &self.rendering)
+            .finish()
+    }
+}
+
+/// Records a [`Recording`] while enabled, otherwise does nothing.
+#[derive(Clone, Default)]
+pub(crate) struct Recorder {
+    recording: Rc<RefCell<Option<Recording>>>,
+    renderers: Rc<RefCell<HashMap<TypeId, Render>>>,
+}
+
+/// Renders a type-erased `Commit<State>` with `State`'s `Debug` impl.
+type Render = fn(&dyn Any) -> String;
+
+impl Recorder {
+    pub fn set_enabled(&self, enabled: bool) {
+        let mut recording = self.recording.borrow_mut();
+        match (enabled, recording.is_some()) {
+            (true, false) => *recording = Some(Recording::default()),
+            (false, true) => *recording = None,
+            _ => (),
+        }
+    }
+
+    pub fn render_with<State: Debug + 'static>(&self) {
+        fn render<State: Debug + 'static>(commit: &dyn Any) -> String {
+            format!("{:?}", commit.downcast_ref::<Commit<State>>().unwrap())
+        }
+        self.renderers.borrow_mut().insert(TypeId::of::<State>(), render::<State>);
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.recording.borrow().is_some()
+    }
+
+    pub fn ran(&self, revision: Revision) {
+        if let Some(recording) = &mut *self.recording.borrow_mut() {
+            recording.end = revision;
+        }
+    }
+
+    pub fn record<State: 'static>(&self, revision: Revision, commit: &Commit<State>) {
+        if let Some(recording) = &mut *self.recording.borrow_mut() {
+            let render = self.renderers.borrow().get(&TypeId::of::<State>()).copied();
+            recording.commits.push(RecordedCommit {
+                id: commit.id,
+                revision,
+                state_type: type_name::<State>(),
+                rendering: render.map(|render| render(commit)),
+                commit: Rc::new(commit.clone()),
+            });
+        }
+    }
+
+    pub fn take(&self) -> Recording {
+        self.recording.borrow_mut().take().unwrap_or_default()
+    }
+}
+
+impl Debug for Recorder {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        let recording = self.recording.borrow();
+        f.debug_struct("Recorder")
+            .field("commits", &recording.as_ref().map(|r| r.commits.len()))
+            .finish()
+    }
+}
+
+/// Steps backward and forward through the revisions of a [`Recording`] by
+/// replaying its commits into fresh runtimes.
+///
+/// Before running each revision, the commits recorded for that revision are
+/// applied to the live state variables with matching [`topo::CallId`]s and
+/// types. Moving to an earlier revision starts over with a new runtime.
+///
+/// Because `CallId`s are only stable within a process, recordings can only be
+/// replayed by the process which made them. The recording should be started
+/// before the runtime's first revision, and the root function should behave
+/// the same given the same state.
+///
+/// # Example
+///
+/// ```
+/// use moxie::{
+///     runtime::{Revision, Runtime, TimeTravel},
+///     state,
+/// };
+///
+/// let root = || state(|| 0u32);
+/// let mut rt = Runtime::new();
+/// rt.set_recording(true);
+/// for _ in 0..3 {
+///     let (count, key) = rt.run_once(root);
+///     key.set(*count + 1);
+/// }
+///
+/// let mut replay = TimeTravel::new(rt.take_recording(), || *root().0);
+/// assert_eq!(replay.seek(Revision(3)), Some(2));
+/// assert_eq!(replay.step_back(), Some(1));
+/// assert_eq!(replay.step_forward(), Some(2));
+/// assert_eq!(replay.step_forward(), None, "the recording ended");
+/// ```
+pub struct TimeTravel<Root> {
+    recording: Recording,
+    make_runtime: Box<dyn FnMut() -> Runtime>,
+    root: Root,
+    rt: Runtime,
+}
+
+impl<Out, Root> TimeTravel<Root>
+where
+    Root: FnMut() -> Out,
+{
+    /// Replay `recording` by running `root` in new runtimes.
+    pub fn new(recording: Recording, root: Root) -> Self {
+        Self::with_runtime(recording, Runtime::new, root)
+    }
+
+    /// Replay `recording` by running `root` in runtimes created by
+    /// `make_runtime`, for roots which need task executors or timers.
+    pub fn with_runtime(
+        recording: Recording,
+        mut make_runtime: impl FnMut() -> Runtime + 'static,
+        root: Root,
+    ) -> Self {
+        let rt = make_runtime();
+        Self { recording, make_runtime: Box::new(make_runtime), root, rt }
+    }
+
+    /// The revision most recently replayed, or `Revision(0)` before the first.
+    pub fn revision(&self) -> Revision {
+        self.rt.revision()
+    }
+
+    /// Replay the revision after the current one, returning the root's output.
+    /// Returns `None` if the recording has ended.
+    pub fn step_forward(&mut self) -> Option<Out> {
+        self.seek(Revision(self.revision().0 + 1))
+    }
+
+    /// Replay the revision before the current one, returning the root's
+    /// output. Returns `None` if the current revision is the first.
+    pub fn step_back(&mut self) -> Option<Out> {
+        match self.revision().0 {
+            0 | 1 => None,
+            current => self.seek(Revision(current - 1)),
+        }
+    }
+
+    /// Replay up to and including `target`, returning the root's output from
+    /// that revision. Returns `None` if `target` is outside of the recording.
+    pub fn seek(&mut self, target: Revision) -> Option<Out> {
+        if target == Revision(0) || target > self.recording.end {
+            return None;
+        }
+        if target <= self.rt.revision() {
+            self.rt = (self.make_runtime)();
+        }
+
+        let mut out = None;
+        while self.rt.revision() < target {
+            let next = Revision(self.rt.revision().0 + 1);
+            for commit in self.recording.at(next) {
+                if !self.rt.replay(commit) {
+                    tracing::debug!(?commit, "no state variable to replay commit to");
+                }
+            }
+            out = Some(self.rt.run_once(&mut self.root));
+        }
+        out
+    }
+}
+
+impl<Root> Debug for TimeTravel<Root> {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("TimeTravel")
+            .field("revision", &self.rt.revision())
+            .field("end", &self.recording.end)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{batch, derived, state, Key};
+
+    #[test]
+    fn records_applied_commits_with_renderings() {
+        let mut rt = Runtime::new();
+        rt.set_recording(true);
+        rt.record_debug::<String>();
+        let root = || (state(|| 0u8), state(|| String::from("a")));
+
+        let ((_, num), (_, text)) = rt.run_once(root);
+        batch(|| {
+            num.set(1);
+            text.set(String::from("b"));
+        });
+        rt.run_once(root);
+        num.set(2);
+        num.set(3);
+        rt.run_once(root);
+
+        let recording = rt.take_recording();
+        assert_eq!(recording.end, Revision(3));
+        let summary: Vec<_> = recording
+            .commits
+            .iter()
+            .map(|c| (c.revision.0, c.value::<u8>().copied(), c.rendering.as_deref()))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (2, Some(1), None),
+                (2, None, Some("\"b\"")),
+                (3, Some(2), None),
+                (3, Some(3), None)
+            ],
+            "replaced commits are recorded, and only registered types are rendered"
+        );
+        assert_eq!(recording.commits[0].id, num.id());
+        assert_eq!(recording.commits[0].state_type, "u8");
+
+        assert!(rt.take_recording().commits.is_empty(), "taking stops recording");
+    }
+
+    /// Doubles a state variable with `derived`, which reads it after `state`
+    /// has applied its commits.
+    fn derive_doubled() -> (u8, Key<u8>) {
+        let (_, key) = state(|| 0u8);
+        (*derived(&key, |n| **n * 2), key)
+    }
+
+    #[test]
+    fn records_commits_read_by_derived_values() {
+        let mut rt = Runtime::new();
+        rt.set_recording(true);
+        let (_, key) = rt.run_once(derive_doubled);
+        key.set(1);
+        key.set(2);
+        assert_eq!(rt.run_once(derive_doubled).0, 4);
+
+        let recording = rt.take_recording();
+        let values: Vec<_> = recording.at(Revision(2)).map(|c| c.value::<u8>().copied()).collect();
+        assert_eq!(values, vec![Some(1), Some(2)], "both commits before the revision");
+
+        let mut replay = TimeTravel::new(recording, derive_doubled);
+        assert_eq!(replay.seek(Revision(2)).map(|(doubled, _)| doubled), Some(4));
+    }
+
+    #[test]
+    fn replays_into_fresh_runtimes() {
+        let root = || {
+            let (count, key) = state(|| 0u32);
+            if *count % 2 == 0 {
+                key.set(*count + 1);
+            }
+            (*count, key)
+        };
+
+        let mut rt = Runtime::new();
+        rt.set_recording(true);
+        for _ in 0..4 {
+            rt.run_once(root);
+        }
+        let (_, key) = rt.run_once(root);
+        key.set(10);
+        rt.run_once(root);
+
+        let mut replay = TimeTravel::new(rt.take_recording(), || root().0);
+        assert_eq!(replay.revision(), Revision(0));
+        assert_eq!(replay.step_back(), None);
+        let forward: Vec<_> = std::iter::from_fn(|| replay.step_forward()).collect();
+        assert_eq!(forward, vec![0, 1, 1, 1, 1, 10]);
+        assert_eq!(replay.revision(), Revision(6));
+
+        assert_eq!(replay.step_back(), Some(1));
+        assert_eq!(replay.seek(Revision(2)), Some(1));
+        assert_eq!(replay.seek(Revision(7)), None);
+        assert_eq!(replay.revision(), Revision(2));
+    }
+}
@@ -1,10 +1,18 @@
-use super::{Revision, Runtime};
+mod blocking;
+
+pub use blocking::{BlockingOptions, Shutdown};
+
+use super::{Priority, Revision, Runtime, Timer};
 use futures::{
     stream::{Stream, StreamExt},
-    task::LocalSpawn,
+    task::{waker, ArcWake, AtomicWaker, LocalSpawn},
 };
 use std::{
     pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
     task::{Context as FutContext, Poll, Waker},
 };
 
@@ -16,6 +24,55 @@ use std::{
 pub struct RunLoop<Root> {
     inner: Runtime,
     root: Root,
+    policy: LoopPolicy,
+    wakes: Arc<LoopWaker>,
+    signal: Arc<blocking::Signal>,
+}
+
+/// Configures how a [`RunLoop`] schedules revisions when it's polled as a
+/// [`futures::Stream`]. Set with [`RunLoop::set_policy`].
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct LoopPolicy {
+    coalesce_wakes: bool,
+    defer_idle: bool,
+}
+
+impl LoopPolicy {
+    /// Returns the default policy, which runs a revision every time the loop
+    /// is polled and spawns idle tasks as soon as their revision completes.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wait for a state change before running each revision after the first,
+    /// running a single revision for any number of state changes.
+    pub fn coalesce_wakes(mut self) -> Self {
+        self.coalesce_wakes = true;
+        self
+    }
+
+    /// Hold idle tasks until the loop is waiting for a state change, rather
+    /// than spawning them as soon as their revision completes. Implies
+    /// [`LoopPolicy::coalesce_wakes`].
+    pub fn defer_idle(mut self) -> Self {
+        self.coalesce_wakes = true;
+        self.defer_idle = true;
+        self
+    }
+}
+
+/// The state change waker for a [`RunLoop`] which coalesces wakes.
+#[derive(Default)]
+struct LoopWaker {
+    woken: AtomicBool,
+    task: AtomicWaker,
+}
+
+impl ArcWake for LoopWaker {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.woken.store(true, Ordering::Release);
+        arc_self.task.wake();
+    }
 }
 
 impl super::Runtime {
@@ -25,7 +82,13 @@ impl super::Runtime {
     where
         Root: FnMut() -> Out,
     {
-        RunLoop { inner: self, root }
+        RunLoop {
+            inner: self,
+            root,
+            policy: LoopPolicy::default(),
+            wakes: Default::default(),
+            signal: Default::default(),
+        }
     }
 }
 
@@ -35,7 +98,7 @@ where
 {
     /// Creates a new `Runtime` attached to the provided root function.
     pub fn new(root: Root) -> RunLoop<Root> {
-        RunLoop { root, inner: Runtime::new() }
+        Runtime::new().looped(root)
     }
 
     /// Returns the runtime's current Revision.
@@ -54,6 +117,59 @@ where
         self.inner.set_task_executor(sp);
     }
 
+    /// Sets the executor that will be used to spawn tasks of the given
+    /// `priority`.
+    pub fn set_priority_executor(&mut self, priority: Priority, sp: impl LocalSpawn + 'static) {
+        self.inner.set_priority_executor(priority, sp);
+    }
+
+    /// Sets the source of time used for timeouts and delayed suspense
+    /// fallbacks.
+    pub fn set_timer(&mut self, timer: impl Timer + 'static) {
+        self.inner.set_timer(timer);
+    }
+
+    /// Sets the policy used to schedule revisions when this loop is polled as
+    /// a [`futures::Stream`].
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use futures::{executor::LocalPool, future::ready, task::LocalSpawnExt, StreamExt};
+    /// use moxie::{
+    ///     runtime::{LoopPolicy, RunLoop},
+    ///     state,
+    /// };
+    /// use std::{cell::Cell, rc::Rc};
+    ///
+    /// let revisions = Rc::new(Cell::new(0));
+    /// let revisions2 = revisions.clone();
+    /// let mut rt = RunLoop::new(move || {
+    ///     revisions2.set(revisions2.get() + 1);
+    ///     state(|| 0u8).1
+    /// });
+    /// rt.set_policy(LoopPolicy::new().coalesce_wakes());
+    ///
+    /// let mut exec = LocalPool::new();
+    /// let (sender, mut keys) = futures::channel::mpsc::unbounded();
+    /// exec.spawner()
+    ///     .spawn_local(rt.for_each(move |(_, key)| ready(sender.unbounded_send(key).unwrap())))
+    ///     .unwrap();
+    ///
+    /// let key = exec.run_until(keys.next()).unwrap();
+    /// exec.run_until_stalled();
+    /// assert_eq!(revisions.get(), 1, "the loop waits for a state change");
+    ///
+    /// key.set(1);
+    /// key.set(2);
+    /// exec.run_until_stalled();
+    /// assert_eq!(revisions.get(), 2, "both commits are handled by one revision");
+    /// ```
+    pub fn set_policy(&mut self, policy: LoopPolicy) {
+        self.inner.set_hold_idle(policy.defer_idle);
+        self.policy = policy;
+    }
+
     /// Run the root function once within this runtime's context, returning the
     /// result.
     pub fn run_once(&mut self) -> Out {
@@ -81,12 +197,89 @@ where
 {
     type Item = (Revision, Out);
 
-    /// This `Stream` implementation runs a single revision for each call to
-    /// `poll_next`, always returning `Poll::Ready(Some(...))`.
+    /// By default this `Stream` implementation runs a single revision for each
+    /// call to `poll_next`, always returning `Poll::Ready(Some(...))`. If the
+    /// loop's [`LoopPolicy`] coalesces wakes, revisions after the first are
+    /// only run once a state change has been reported, returning
+    /// `Poll::Pending` otherwise.
     fn poll_next(self: Pin<&mut Self>, cx: &mut FutContext<'_>) -> Poll<Option<Self::Item>> {
         let this = self.get_mut();
-        this.inner.set_state_change_waker(cx.waker().clone());
+        if !this.policy.coalesce_wakes {
+            this.inner.set_state_change_waker(cx.waker().clone());
+            let out = this.run_once();
+            return Poll::Ready(Some((this.inner.revision, out)));
+        }
+
+        this.wakes.task.register(cx.waker());
+        this.inner.set_state_change_waker(waker(this.wakes.clone()));
+        let woken = this.wakes.woken.swap(false, Ordering::AcqRel);
+        if this.inner.revision() > Revision(0) && !woken {
+            // the loop is idle until the next state change
+            this.inner.spawn_idle();
+            return Poll::Pending;
+        }
+
         let out = this.run_once();
         Poll::Ready(Some((this.inner.revision, out)))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{load_once_with_priority, load_with_priority};
+    use futures::{executor::LocalPool, task::LocalSpawnExt};
+    use std::{cell::Cell, rc::Rc};
+
+    #[test]
+    fn idle_tasks_wait_for_the_loop_to_be_idle() {
+        let revisions = Rc::new(Cell::new(0));
+        let idle_started_after = Rc::new(Cell::new(None));
+        let (revisions2, idle_started_after2) = (revisions.clone(), idle_started_after.clone());
+        let mut rt = RunLoop::new(move || {
+            revisions2.set(revisions2.get() + 1);
+            let (revisions, started_after) = (revisions2.clone(), idle_started_after2.clone());
+            load_once_with_priority(
+                Priority::Idle,
+                move || async move { started_after.set(Some(revisions.get())) },
+                |_| (),
+            )
+        });
+        rt.set_policy(LoopPolicy::new().defer_idle());
+
+        let mut exec = LocalPool::new();
+        rt.set_task_executor(exec.spawner());
+        let last = Rc::new(Cell::new(Poll::Pending));
+        let last2 = last.clone();
+        exec.spawner()
+            .spawn_local(rt.for_each(move |(_, out)| {
+                last2.set(out);
+                futures::future::ready(())
+            }))
+            .unwrap();
+        exec.run_until_stalled();
+
+        // the first revision's load wakes the loop, so the second runs right away
+        assert_eq!(idle_started_after.get(), Some(2), "idle work waits for the loop to settle");
+        assert_eq!(revisions.get(), 3, "the idle task's result wakes the loop");
+        assert_eq!(last.get(), Poll::Ready(()));
+    }
+
+    #[test]
+    fn held_idle_tasks_are_dropped_with_their_loads() {
+        let input = Rc::new(Cell::new(0));
+        let input2 = input.clone();
+        let mut rt = RunLoop::new(move || {
+            load_with_priority(Priority::Idle, &input2.get(), |_| async {}, |_| ())
+        });
+        rt.set_policy(LoopPolicy::new().defer_idle());
+        let exec = LocalPool::new();
+        rt.set_task_executor(exec.spawner());
+
+        for i in 0..10 {
+            input.set(i);
+            assert_eq!(rt.run_once(), Poll::Pending);
+        }
+        assert_eq!(rt.inner.spawners.num_idle_waiting(), 1, "only the latest load is held");
+    }
+}
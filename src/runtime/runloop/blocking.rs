@@ -0,0 +1,296 @@
+use super::RunLoop;
+use futures::task::{waker, ArcWake};
+use parking_lot::{Condvar, Mutex};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+/// Configures [`RunLoop::run_blocking`].
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct BlockingOptions {
+    min_interval: Option<Duration>,
+    coalesce: Option<Duration>,
+}
+
+impl BlockingOptions {
+    /// Returns the default options, which run a revision as soon as the
+    /// runtime is woken.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wait until at least `1 / fps` seconds have passed since the start of the
+    /// previous revision before starting another.
+    pub fn max_fps(mut self, fps: u32) -> Self {
+        self.min_interval = Some(Duration::from_secs(1) / fps.max(1));
+        self
+    }
+
+    /// After being woken, wait for `window` before running a revision so that
+    /// a burst of state changes is handled by a single revision.
+    pub fn coalesce(mut self, window: Duration) -> Self {
+        self.coalesce = Some(window);
+        self
+    }
+}
+
+/// Stops a call to [`RunLoop::run_blocking`] once its current revision has
+/// completed. Returned by [`RunLoop::shutdown_handle`], and can be sent to and
+/// used from other threads.
+#[derive(Clone, Debug)]
+pub struct Shutdown {
+    signal: Arc<Signal>,
+}
+
+impl Shutdown {
+    /// Requests that the loop return. If the loop isn't running, the next call
+    /// to `run_blocking` returns without running a revision.
+    pub fn shutdown(&self) {
+        self.signal.state.lock().shutdown = true;
+        self.signal.changed.notify_all();
+    }
+}
+
+/// Wakes a thread blocked in [`RunLoop::run_blocking`].
+#[derive(Debug, Default)]
+pub(super) struct Signal {
+    state: Mutex<SignalState>,
+    changed: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct SignalState {
+    woken: bool,
+    shutdown: bool,
+}
+
+impl ArcWake for Signal {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.state.lock().woken = true;
+        arc_self.changed.notify_all();
+    }
+}
+
+impl Signal {
+    /// Block until woken, returning false if shutdown was requested instead.
+    fn wait_for_wake(&self) -> bool {
+        let mut state = self.state.lock();
+        while !state.woken && !state.shutdown {
+            self.changed.wait(&mut state);
+        }
+        !state.shutdown
+    }
+
+    /// Block until `deadline`, returning false if shutdown was requested first.
+    fn wait_until(&self, deadline: Instant) -> bool {
+        let mut state = self.state.lock();
+        while !state.shutdown && !self.changed.wait_until(&mut state, deadline).timed_out() {}
+        !state.shutdown
+    }
+
+    /// Clear any wake before starting a revision, returning false if shutdown
+    /// was requested.
+    fn start_revision(&self) -> bool {
+        let mut state = self.state.lock();
+        state.woken = false;
+        !state.shutdown
+    }
+
+    fn finish(&self) {
+        self.state.lock().shutdown = false;
+    }
+}
+
+impl<Root, Out> RunLoop<Root>
+where
+    Root: FnMut() -> Out + Unpin,
+{
+    /// Returns a handle which stops [`RunLoop::run_blocking`].
+    pub fn shutdown_handle(&self) -> Shutdown {
+        Shutdown { signal: self.signal.clone() }
+    }
+
+    /// Run revisions on the current thread until shutdown is requested with a
+    /// handle from [`RunLoop::shutdown_handle`], parking the thread until the
+    /// runtime is woken between revisions. Returns the output of the last
+    /// revision, or `None` if shutdown was requested before the first.
+    ///
+    /// Replaces the runtime's state change waker, so the loop can be run this
+    /// way after being polled as a `Stream`. Any tasks spawned by the root
+    /// function need an executor which runs on another thread.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use moxie::{
+    ///     runtime::{BlockingOptions, RunLoop},
+    ///     state,
+    /// };
+    /// use std::{sync::mpsc, thread};
+    ///
+    /// let (send_key, keys) = mpsc::channel();
+    /// let mut rt = RunLoop::new(move || {
+    ///     let (count, key) = state(|| 0u32);
+    ///     send_key.send(key).ok();
+    ///     *count
+    /// });
+    ///
+    /// let shutdown = rt.shutdown_handle();
+    /// let writer = thread::spawn(move || {
+    ///     for i in 1..=3 {
+    ///         keys.recv().unwrap().set(i);
+    ///     }
+    ///     keys.recv().unwrap(); // wait for the last commit to be applied
+    ///     shutdown.shutdown();
+    /// });
+    ///
+    /// let last = rt.run_blocking(&BlockingOptions::new().max_fps(120)).unwrap();
+    /// writer.join().unwrap();
+    /// assert_eq!(last, 3);
+    /// ```
+    pub fn run_blocking(&mut self, options: &BlockingOptions) -> Option<Out> {
+        self.inner.set_state_change_waker(waker(self.signal.clone()));
+        let mut output = None;
+        let mut last_start: Option<Instant> = None;
+
+        loop {
+            if let (Some(interval), Some(start)) = (options.min_interval, last_start) {
+                if !self.signal.wait_until(start + interval) {
+                    break;
+                }
+            }
+            if !self.signal.start_revision() {
+                break;
+            }
+            last_start = Some(Instant::now());
+            output = Some(self.run_once());
+
+            if self.policy.defer_idle {
+                self.inner.spawn_idle();
+            }
+            if !self.signal.wait_for_wake() {
+                break;
+            }
+            if let Some(window) = options.coalesce {
+                if !self.signal.wait_until(Instant::now() + window) {
+                    break;
+                }
+            }
+        }
+
+        self.signal.finish();
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{state, Key};
+    use std::{
+        sync::mpsc::{channel, Receiver},
+        thread,
+    };
+
+    type Counted = RunLoop<Box<dyn FnMut() -> (u32, usize)>>;
+
+    /// Returns a loop which sends its state's value and key to the receiver
+    /// each revision, returning its value and the number of revisions run so
+    /// far.
+    fn counted() -> (Counted, Receiver<(u32, Key<u32>)>) {
+        let (send_key, keys) = channel();
+        let mut revisions = 0usize;
+        let rt = RunLoop::new(Box::new(move || {
+            revisions += 1;
+            let (value, key) = state(|| 0u32);
+            send_key.send((*value, key)).ok();
+            (*value, revisions)
+        }) as Box<dyn FnMut() -> _>);
+        (rt, keys)
+    }
+
+    /// Waits for a revision which has applied `value`.
+    fn applied(keys: &Receiver<(u32, Key<u32>)>, value: u32) {
+        while keys.recv().unwrap().0 != value {}
+    }
+
+    #[test]
+    fn bursts_are_coalesced() {
+        let (mut rt, keys) = counted();
+        let shutdown = rt.shutdown_handle();
+        let writer = thread::spawn(move || {
+            let (_, key) = keys.recv().unwrap();
+            for burst in 0..3 {
+                for i in 0..5 {
+                    key.set(burst * 5 + i + 1);
+                }
+                applied(&keys, burst * 5 + 5);
+            }
+            shutdown.shutdown();
+        });
+
+        let options = BlockingOptions::new().coalesce(Duration::from_millis(50));
+        let (last, revisions) = rt.run_blocking(&options).unwrap();
+        writer.join().unwrap();
+
+        assert_eq!(last, 15, "the final burst was applied");
+        // each burst takes at least one revision, and the window outlasts them
+        assert!((4..16).contains(&revisions), "{} revisions for 15 commits", revisions);
+    }
+
+    #[test]
+    fn revisions_are_throttled() {
+        let (mut rt, keys) = counted();
+        let shutdown = rt.shutdown_handle();
+        let writer = thread::spawn(move || {
+            let (_, key) = keys.recv().unwrap();
+            for i in 1..=100 {
+                key.set(i);
+                thread::sleep(Duration::from_millis(1));
+            }
+            applied(&keys, 100);
+            shutdown.shutdown();
+        });
+
+        let start = Instant::now();
+        let (_, revisions) = rt.run_blocking(&BlockingOptions::new().max_fps(20)).unwrap();
+        writer.join().unwrap();
+        let elapsed = start.elapsed();
+
+        let allowed = 1 + elapsed.as_millis() as usize / 50;
+        assert!(revisions <= allowed, "ran {} revisions in {:?}", revisions, elapsed);
+        assert!(revisions > 1, "revisions still ran while throttled");
+    }
+
+    #[test]
+    fn vars_created_before_blocking_wake_the_loop() {
+        let (mut rt, keys) = counted();
+        assert_eq!(rt.run_once(), (0, 1));
+        let (_, key) = keys.recv().unwrap();
+
+        let shutdown = rt.shutdown_handle();
+        let writer = thread::spawn(move || {
+            key.set(1);
+            applied(&keys, 1);
+            shutdown.shutdown();
+        });
+        assert_eq!(rt.run_blocking(&BlockingOptions::new()).unwrap().0, 1);
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn shutdown_before_running_returns_immediately() {
+        let mut rt = RunLoop::new(|| ());
+        rt.shutdown_handle().shutdown();
+        assert_eq!(rt.run_blocking(&BlockingOptions::new()), None);
+
+        let shutdown = rt.shutdown_handle();
+        let stopper = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            shutdown.shutdown();
+        });
+        assert_eq!(rt.run_blocking(&BlockingOptions::new()), Some(()), "shutdown is reset");
+        stopper.join().unwrap();
+    }
+}
@@ -0,0 +1,286 @@
+use super::{Revision, Var};
+use crate::{Commit, Key};
+use dyn_cache::sync::SharedSendCache;
+use futures::{
+    future::{abortable, FutureObj},
+    task::{noop_waker, Spawn, SpawnError},
+};
+use illicit::AsContext;
+use std::{
+    borrow::Borrow,
+    fmt::{Debug, Formatter, Result as FmtResult},
+    future::Future,
+    sync::Arc,
+    task::{Poll, Waker},
+};
+
+/// A [`SendRuntime`] is the thread-safe equivalent of a [`super::Runtime`].
+/// It stores its cached values and state variables in a
+/// [`dyn_cache::sync::SharedSendCache`] and spawns loaders onto a
+/// [`futures::task::Spawn`] executor, so the runtime and anything it hands out
+/// can be moved between threads.
+///
+/// Code run by a `SendRuntime` must use the functions in [`crate::sync`]
+/// rather than those at the crate root, which expect a local [`super::Runtime`]
+/// and will panic if called without one.
+///
+/// # Example
+///
+/// ```
+/// use moxie::{runtime::SendRuntime, sync::state};
+///
+/// let root = || state(|| 0u64);
+/// let mut rt = SendRuntime::new();
+/// let (commit, key) = rt.run_once(root);
+/// assert_eq!(*commit, 0);
+///
+/// // runtimes, keys, and commits can all be sent to other threads
+/// let mut rt = std::thread::spawn(move || {
+///     key.set(1);
+///     rt
+/// })
+/// .join()
+/// .unwrap();
+///
+/// let (commit, _) = rt.run_once(root);
+/// assert_eq!(*commit, 1);
+/// ```
+pub struct SendRuntime {
+    revision: Revision,
+    cache: SharedSendCache,
+    spawner: SendSpawner,
+    wk: Waker,
+}
+
+impl Default for SendRuntime {
+    fn default() -> SendRuntime {
+        SendRuntime::new()
+    }
+}
+
+impl SendRuntime {
+    /// Construct a new [`SendRuntime`] with blank storage and no external waker
+    /// or task executor.
+    pub fn new() -> Self {
+        Self {
+            spawner: SendSpawner(Arc::new(JunkSpawner)),
+            revision: Revision(0),
+            cache: SharedSendCache::default(),
+            wk: noop_waker(),
+        }
+    }
+
+    /// The current revision of the runtime, or how many times `run_once` has
+    /// been invoked.
+    pub fn revision(&self) -> Revision {
+        self.revision
+    }
+
+    /// Runs the root closure once with access to the runtime context,
+    /// increments the runtime's `Revision`, and drops any cached values
+    /// which were not marked alive.
+    pub fn run_once<Out>(&mut self, op: impl FnOnce() -> Out) -> Out {
+        self.revision.0 += 1;
+
+        let ret = self.context_handle().offer(|| topo::call(op));
+
+        self.cache.gc();
+        ret
+    }
+
+    /// Sets the [`std::task::Waker`] which will be called when state variables
+    /// receive commits. See [`super::Runtime::set_state_change_waker`].
+    pub fn set_state_change_waker(&mut self, wk: Waker) {
+        self.wk = wk;
+    }
+
+    /// Sets the executor that will be used to spawn normal priority tasks.
+    pub fn set_task_executor(&mut self, sp: impl Spawn + Send + Sync + 'static) {
+        self.spawner = SendSpawner(Arc::new(sp));
+    }
+
+    fn context_handle(&self) -> SendContext {
+        SendContext {
+            revision: self.revision,
+            spawner: self.spawner.clone(),
+            cache: self.cache.clone(),
+            waker: self.wk.clone(),
+        }
+    }
+}
+
+/// A handle to the current [`SendRuntime`] which is offered via [`illicit`]
+/// contexts. The thread-safe equivalent of [`super::Context`].
+#[derive(Debug)]
+pub(crate) struct SendContext {
+    revision: Revision,
+    pub cache: SharedSendCache,
+    spawner: SendSpawner,
+    waker: Waker,
+}
+
+impl SendContext {
+    /// Returns the revision for which this context was created.
+    pub fn revision(&self) -> Revision {
+        self.revision
+    }
+
+    /// Load a [`Var`] with the provided argument and initializer.
+    /// Re-initializes the `Var` whenever `arg` changes.
+    pub fn cache_state<Arg, Input, Output>(
+        &self,
+        id: &topo::CallId,
+        arg: &Arg,
+        init: impl FnOnce(&Input) -> Output,
+    ) -> (Commit<Output>, Key<Output>)
+    where
+        Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+        Input: Borrow<Arg> + Send + 'static,
+        Output: Send + Sync + 'static,
+    {
+        let var = self
+            .cache
+            .cache(id, arg, |arg| Var::new(topo::CallId::current(), self.waker.clone(), init(arg)));
+        Var::root(var)
+    }
+
+    /// Load a value from the future returned by `init` whenever `capture`
+    /// changes, returning the result of calling `with` with the loaded
+    /// value. See [`super::Context::load_with`].
+    ///
+    /// # Panics
+    ///
+    /// If the [`SendRuntime`] from which `self` was created did not have
+    /// a valid call to `set_task_executor`.
+    pub fn load_with<Arg, Input, Fut, Output, Ret>(
+        &self,
+        id: &topo::CallId,
+        arg: &Arg,
+        init: impl FnOnce(&Input) -> Fut,
+        with: impl FnOnce(&Output) -> Ret,
+    ) -> Poll<Ret>
+    where
+        Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+        Input: Borrow<Arg> + Send + 'static,
+        Fut: Future<Output = Output> + Send + 'static,
+        Output: Send + Sync + 'static,
+        Ret: Send + 'static,
+    {
+        let (_, set_result): (_, Key<Poll<Output>>) = self.cache_state(id, &(), |()| Poll::Pending);
+        let mut set_result2 = set_result.clone();
+        self.cache.hold(id, arg, |arg| {
+            // before we spawn the new task we need to mark it pending
+            set_result.force(Poll::Pending);
+
+            let (fut, aborter) = abortable(init(arg));
+            let task = async move {
+                if let Ok(to_store) = fut.await {
+                    set_result.update(|_| Some(Poll::Ready(to_store)));
+                }
+            };
+            self.spawner
+                .0
+                .spawn_obj(FutureObj::new(Box::new(task)))
+                .expect("that set_task_executor has been called");
+            scopeguard::guard(aborter, |a| a.abort())
+        });
+
+        set_result2.refresh();
+
+        match &*set_result2 {
+            Poll::Ready(ref stored) => Poll::Ready(with(stored)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct SendSpawner(pub Arc<dyn Spawn + Send + Sync>);
+
+impl Debug for SendSpawner {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.write_fmt(format_args!("{:p}", &self.0))
+    }
+}
+
+struct JunkSpawner;
+impl Spawn for JunkSpawner {
+    fn spawn_obj(&self, _: FutureObj<'static, ()>) -> Result<(), SpawnError> {
+        Err(SpawnError::shutdown())
+    }
+
+    fn status(&self) -> Result<(), SpawnError> {
+        Err(SpawnError::shutdown())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::{load_once, once};
+    use futures::future::FutureExt;
+
+    fn assert_send<T: Send>(_: &T) {}
+
+    #[test]
+    fn runtime_is_send() {
+        let rt = SendRuntime::new();
+        assert_send(&rt);
+        let mut rt = std::thread::spawn(move || rt).join().unwrap();
+        assert_eq!(rt.run_once(Revision::current), Revision(1));
+    }
+
+    #[test]
+    fn cached_values_cross_threads() {
+        let root = || once(|| Arc::new(0u8));
+        let mut rt = SendRuntime::new();
+        let first = rt.run_once(root);
+
+        let (mut rt, second) = std::thread::spawn(move || {
+            let second = rt.run_once(root);
+            (rt, second)
+        })
+        .join()
+        .unwrap();
+        assert!(Arc::ptr_eq(&first, &second), "same callsite, same cached value");
+
+        let third = rt.run_once(root);
+        assert!(Arc::ptr_eq(&first, &third));
+    }
+
+    /// Runs each spawned task to completion on its own thread.
+    struct ThreadSpawner;
+    impl Spawn for ThreadSpawner {
+        fn spawn_obj(&self, task: FutureObj<'static, ()>) -> Result<(), SpawnError> {
+            std::thread::spawn(move || futures::executor::block_on(task));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn loads_on_other_threads() {
+        let (send, recv) = futures::channel::oneshot::channel();
+        let recv = Arc::new(parking_lot::Mutex::new(Some(recv)));
+
+        let mut rt = SendRuntime::new();
+        rt.set_task_executor(ThreadSpawner);
+
+        let mut root = move || {
+            let recv = recv.lock().take();
+            load_once(move || recv.unwrap().map(Result::unwrap))
+        };
+
+        assert_eq!(rt.run_once(&mut root), Poll::Pending);
+        send.send(5u8).unwrap();
+
+        let mut loaded = Poll::Pending;
+        for _ in 0..1_000 {
+            loaded = rt.run_once(&mut root);
+            if loaded.is_ready() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        assert_eq!(loaded, Poll::Ready(5));
+    }
+}
@@ -0,0 +1,262 @@
+use super::Var;
+use futures::task::{waker, ArcWake};
+use parking_lot::Mutex;
+use std::{
+    any::{type_name, Any},
+    cell::RefCell,
+    collections::HashMap,
+    fmt::{Debug, Formatter, Result as FmtResult},
+    rc::{self, Rc},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Weak,
+    },
+    task::Waker,
+};
+
+/// State variables which can be rooted by any number of [`super::Runtime`]s
+/// with [`crate::shared_state`]. A commit to a shared state variable wakes
+/// every runtime which roots a state variable from the store, until the
+/// runtime goes a revision without rooting any.
+///
+/// Cloning a store returns another handle to the same state variables, and
+/// handles can be sent to other threads. Shared state variables live as long
+/// as the store, rather than being dropped when a runtime stops rooting them.
+#[derive(Clone, Default)]
+pub struct SharedStore {
+    inner: Arc<StoreInner>,
+}
+
+struct StoreInner {
+    id: u64,
+    vars: Mutex<HashMap<String, Arc<dyn Any + Send + Sync>>>,
+    subscribers: Arc<Subscribers>,
+}
+
+impl Default for StoreInner {
+    fn default() -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            vars: Default::default(),
+            subscribers: Default::default(),
+        }
+    }
+}
+
+impl SharedStore {
+    /// Returns an empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a value unique to this store and its clones.
+    pub(crate) fn id(&self) -> u64 {
+        self.inner.id
+    }
+
+    /// Returns the number of state variables in the store.
+    pub fn len(&self) -> usize {
+        self.inner.vars.lock().len()
+    }
+
+    /// Returns true if the store has no state variables.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of runtimes which will be woken by commits to the
+    /// store's state variables.
+    pub fn num_subscribers(&self) -> usize {
+        self.inner.subscribers.wakers.lock().len()
+    }
+
+    /// Returns the state variable for `name`, creating it with `init` if it
+    /// doesn't exist yet. Its `CallId` depends only on the store and `name`,
+    /// not on the runtime which created it.
+    pub(crate) fn var<State>(
+        &self,
+        name: &str,
+        init: impl FnOnce() -> State,
+    ) -> Arc<Mutex<Var<State>>>
+    where
+        State: Send + Sync + 'static,
+    {
+        let mut vars = self.inner.vars.lock();
+        let var = vars
+            .entry(name.to_owned())
+            .or_insert_with(|| {
+                let wake_subscribers = waker(self.inner.subscribers.clone());
+                let id = topo::root(|| {
+                    topo::call_in_slot(&(self.inner.id, name.to_owned()), topo::CallId::current)
+                });
+                Var::new(id, wake_subscribers, init())
+            })
+            .clone();
+        var.downcast().unwrap_or_else(|_| {
+            panic!(
+                "shared state `{}` was first rooted with a type other than `{}`",
+                name,
+                type_name::<State>()
+            )
+        })
+    }
+}
+
+impl Debug for SharedStore {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("SharedStore")
+            .field("vars", &self.len())
+            .field("subscribers", &self.num_subscribers())
+            .finish()
+    }
+}
+
+/// The state change wakers of the runtimes subscribed to a [`SharedStore`],
+/// keyed by [`Subscriptions`] id. Used as the waker for each of the store's
+/// state variables.
+#[derive(Default)]
+struct Subscribers {
+    wakers: Mutex<HashMap<u64, Waker>>,
+}
+
+impl ArcWake for Subscribers {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        let wakers: Vec<_> = arc_self.wakers.lock().values().cloned().collect();
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+/// The [`SharedStore`]s a runtime is subscribed to.
+#[derive(Clone, Debug)]
+pub(crate) struct Subscriptions {
+    inner: Rc<RefCell<SubscriptionsInner>>,
+}
+
+struct SubscriptionsInner {
+    id: u64,
+    waker: Waker,
+    stores: Vec<rc::Weak<Subscription>>,
+}
+
+impl Subscriptions {
+    pub fn new(waker: Waker) -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        Self { inner: Rc::new(RefCell::new(SubscriptionsInner { id, waker, stores: Vec::new() })) }
+    }
+
+    /// Wake the runtime for commits to `store`'s state variables until the
+    /// returned subscription and any others to `store` are dropped.
+    pub fn subscribe(&self, store: &SharedStore) -> Rc<Subscription> {
+        let mut inner = self.inner.borrow_mut();
+        inner.stores.retain(|s| s.strong_count() > 0);
+        let existing = inner.stores.iter().filter_map(rc::Weak::upgrade).find(|subscription| {
+            subscription.subscribers.as_ptr() == Arc::as_ptr(&store.inner.subscribers)
+        });
+        if let Some(subscription) = existing {
+            return subscription;
+        }
+
+        let subscribers = &store.inner.subscribers;
+        subscribers.wakers.lock().insert(inner.id, inner.waker.clone());
+        let subscription =
+            Rc::new(Subscription { id: inner.id, subscribers: Arc::downgrade(subscribers) });
+        inner.stores.push(Rc::downgrade(&subscription));
+        subscription
+    }
+}
+
+/// Wakes a runtime for commits to a store's state variables until dropped.
+/// Cached by each callsite which roots a state variable from the store.
+pub(crate) struct Subscription {
+    id: u64,
+    subscribers: Weak<Subscribers>,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        if let Some(subscribers) = self.subscribers.upgrade() {
+            subscribers.wakers.lock().remove(&self.id);
+        }
+    }
+}
+
+impl Debug for SubscriptionsInner {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("Subscriptions")
+            .field("id", &self.id)
+            .field("stores", &self.stores.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{runtime::Runtime, shared_state, testing::TestRuntime};
+
+    #[test]
+    fn commits_wake_every_subscribed_runtime() {
+        let store = SharedStore::new();
+        let (mut first, mut second) = (TestRuntime::new(), TestRuntime::new());
+        let root = {
+            let store = store.clone();
+            move || shared_state(&store, "count", || 0u32)
+        };
+
+        let (count, key) = first.run_once(root.clone());
+        assert_eq!(*count, 0);
+        let (count, _) = second.run_once(root.clone());
+        assert_eq!(*count, 0);
+        assert_eq!((store.len(), store.num_subscribers()), (1, 2));
+        first.take_wakes();
+        second.take_wakes();
+
+        key.set(1);
+        assert_eq!(first.take_wakes(), 1);
+        assert_eq!(second.take_wakes(), 1);
+        assert_eq!(*second.run_once(root.clone()).0, 1);
+        assert_eq!(*first.run_once(root.clone()).0, 1, "either runtime can apply a commit");
+
+        drop(second);
+        assert_eq!(store.num_subscribers(), 1, "dropped runtimes unsubscribe");
+        let mut third = Runtime::new();
+        third.run_once(root);
+        assert_eq!(store.num_subscribers(), 2);
+    }
+
+    #[test]
+    fn runtimes_unsubscribe_once_they_stop_rooting() {
+        let store = SharedStore::new();
+        let mut rt = TestRuntime::new();
+        let root = |rooted: bool, memoized: bool| {
+            if rooted {
+                crate::memo(&memoized, || shared_state(&store, "count", || 0u32));
+            }
+        };
+
+        rt.run_once(|| root(true, false));
+        rt.run_once(|| root(true, true));
+        rt.run_once(|| root(true, true));
+        assert_eq!(store.num_subscribers(), 1, "reused subtrees keep their subscriptions");
+
+        rt.run_once(|| root(false, true));
+        assert_eq!(store.num_subscribers(), 0);
+        rt.take_wakes();
+        let (_, key) = TestRuntime::new().run_once(|| shared_state(&store, "count", || 0u32));
+        key.set(1);
+        assert_eq!(rt.take_wakes(), 0, "unsubscribed runtimes aren't woken");
+    }
+
+    #[test]
+    fn shared_vars_are_identified_by_store() {
+        let mut rt = TestRuntime::new();
+        let (first, second) = (SharedStore::new(), SharedStore::new());
+        let root = |store: &SharedStore| shared_state(store, "count", || 0u32).1.id();
+        assert_ne!(rt.run_once(|| root(&first)), rt.run_once(|| root(&second)));
+        assert_eq!(rt.run_once(|| root(&first)), TestRuntime::new().run_once(|| root(&first)));
+    }
+}
@@ -0,0 +1,31 @@
+use futures::future::LocalBoxFuture;
+use std::{
+    fmt::{Debug, Formatter, Result as FmtResult},
+    rc::Rc,
+    time::Duration,
+};
+
+/// A source of time for a runtime, used for timeouts and delayed suspense
+/// fallbacks. Set with [`super::Runtime::set_timer`].
+///
+/// Runtimes don't have a timer unless one is set, because there's no portable
+/// way to sleep: `std::time::Instant` isn't available on
+/// `wasm32-unknown-unknown`, and embedders usually have their own event loop
+/// timers to integrate with.
+pub trait Timer {
+    /// Returns the time elapsed since an arbitrary fixed point.
+    fn now(&self) -> Duration;
+
+    /// Returns a future which completes once `duration` has elapsed. Dropping
+    /// the future should cancel the sleep.
+    fn sleep(&self, duration: Duration) -> LocalBoxFuture<'static, ()>;
+}
+
+#[derive(Clone)]
+pub(crate) struct TimerHandle(pub Rc<dyn Timer>);
+
+impl Debug for TimerHandle {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.write_fmt(format_args!("{:p}", &self.0))
+    }
+}
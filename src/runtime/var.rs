@@ -1,6 +1,17 @@
+use super::{
+    inspect::{InspectVar, StateVar},
+    memo::Versioned,
+    watch::{Channel, Watchers},
+    Context, Revision,
+};
 use crate::{Commit, Key};
 use parking_lot::Mutex;
-use std::{sync::Arc, task::Waker};
+use std::{
+    any::{type_name, Any},
+    mem::size_of,
+    sync::Arc,
+    task::Waker,
+};
 
 /// The underlying container of state variables. Vends copies of the latest
 /// [`Commit`] for [`Key`]s.
@@ -8,39 +19,177 @@ pub(crate) struct Var<State> {
     current: Commit<State>,
     id: topo::CallId,
     pending: Option<Commit<State>>,
+    staged: Option<Commit<State>>,
     waker: Waker,
+    rooted_at: Revision,
+    watchers: Watchers<State>,
+    /// The number of commits which have been enqueued or published.
+    version: u64,
+    /// Whether the runtime which last rooted this variable is recording.
+    recording: bool,
+    /// The commits enqueued or published since this variable was last rooted,
+    /// kept while `recording`.
+    unapplied: Vec<Commit<State>>,
 }
 
 impl<State> Var<State> {
     pub fn new(id: topo::CallId, waker: Waker, inner: State) -> Arc<Mutex<Self>> {
         let current = Commit { id, inner: Arc::new(inner) };
-        Arc::new(Mutex::new(Var { id, current, waker, pending: None }))
+        Arc::new(Mutex::new(Var {
+            id,
+            current,
+            waker,
+            pending: None,
+            staged: None,
+            rooted_at: Revision::current(),
+            watchers: Watchers::default(),
+            version: 0,
+            recording: false,
+            unapplied: Vec::new(),
+        }))
     }
 
     /// Attach this `Var` to its callsite, performing any pending commit and
-    /// returning the resulting latest commit.
-    pub fn root(var: Arc<Mutex<Self>>) -> (Commit<State>, Key<State>) {
+    /// returning the resulting latest commit. If the current runtime is
+    /// recording, each commit made since the variable was last rooted is
+    /// recorded.
+    pub fn root(var: Arc<Mutex<Self>>) -> (Commit<State>, Key<State>)
+    where
+        State: 'static,
+    {
+        let recorder = illicit::get::<Context>().ok().filter(|rt| rt.is_recording());
         let (id, commit_at_root) = {
             let mut var = var.lock();
+            let unapplied = std::mem::take(&mut var.unapplied);
             if let Some(pending) = var.pending.take() {
+                if let Some(rt) = &recorder {
+                    // the pending commit is the only one known if recording just started
+                    if unapplied.is_empty() {
+                        rt.record_commit(&pending);
+                    }
+                    unapplied.iter().for_each(|commit| rt.record_commit(commit));
+                }
                 var.current = pending;
             }
+            var.recording = recorder.is_some();
+            var.rooted_at = var.rooted_at.max(Revision::current());
             (var.id, var.current.clone())
         };
 
         (commit_at_root.clone(), Key { id, commit_at_root, var })
     }
 
-    /// Returns a reference to the latest value, pending or committed.
+    /// Returns a reference to the latest value, staged, pending or committed.
     pub fn latest(&self) -> &State {
-        &self.pending.as_ref().unwrap_or(&self.current)
+        self.staged.as_ref().or(self.pending.as_ref()).unwrap_or(&self.current)
+    }
+
+    /// Returns the current commit, without applying a pending one.
+    pub fn current_commit(&self) -> Commit<State> {
+        self.current.clone()
+    }
+
+    /// Returns the latest commit, staged, pending or current.
+    pub fn latest_commit(&self) -> Commit<State> {
+        self.staged.as_ref().or(self.pending.as_ref()).unwrap_or(&self.current).clone()
     }
 
     /// Initiate a commit to the state variable. The commit will actually
     /// complete asynchronously when the state variable is next rooted in a
     /// topological function, flushing the pending commit.
     pub fn enqueue_commit(&mut self, state: State) {
-        self.pending = Some(Commit { inner: Arc::new(state), id: self.id });
+        let commit = Commit { inner: Arc::new(state), id: self.id };
+        self.sent(&commit);
+        self.pending = Some(commit);
+        self.version += 1;
         self.waker.wake_by_ref();
     }
+
+    /// Like [`Var::enqueue_commit`], but only wakes the runtime if there isn't
+    /// already a pending commit which it has been woken for.
+    pub fn coalesce_commit(&mut self, state: State) {
+        let commit = Commit { inner: Arc::new(state), id: self.id };
+        self.sent(&commit);
+        self.version += 1;
+        if self.pending.replace(commit).is_none() {
+            self.waker.wake_by_ref();
+        }
+    }
+
+    /// Stage a commit to the state variable as part of a [`super::Batch`]. The
+    /// commit is not visible to revisions and the runtime is not woken until
+    /// the batch publishes it with [`Var::publish_staged`].
+    pub fn stage_commit(&mut self, state: State) {
+        self.staged = Some(Commit { inner: Arc::new(state), id: self.id });
+    }
+
+    /// Move any staged commit to be pending, returning the waker which should
+    /// be notified of it.
+    pub fn publish_staged(&mut self) -> Option<Waker> {
+        let staged = self.staged.take()?;
+        self.sent(&staged);
+        self.pending = Some(staged);
+        self.version += 1;
+        Some(self.waker.clone())
+    }
+
+    /// Notify watchers of a commit which is about to become pending, keeping
+    /// it to be recorded if the variable is being recorded.
+    fn sent(&mut self, commit: &Commit<State>) {
+        self.watchers.send(commit);
+        if self.recording {
+            self.unapplied.push(commit.clone());
+        }
+    }
+
+    /// Drop any staged commit without publishing it.
+    pub fn discard_staged(&mut self) {
+        self.staged = None;
+    }
+
+    /// Send each subsequent commit to `channel` until the variable is dropped.
+    pub fn watch(&mut self, channel: Arc<Channel<State>>) {
+        self.watchers.add(channel);
+    }
+}
+
+impl<State> Drop for Var<State> {
+    fn drop(&mut self) {
+        self.watchers.close();
+    }
+}
+
+impl<State: 'static> InspectVar for Mutex<Var<State>> {
+    fn describe(&self) -> StateVar {
+        let var = self.lock();
+        StateVar {
+            callsite: var.id.location(),
+            state_type: type_name::<State>(),
+            last_rooted: var.rooted_at,
+            has_pending: var.pending.is_some() || var.staged.is_some(),
+            shallow_size: size_of::<State>(),
+        }
+    }
+
+    fn id(&self) -> topo::CallId {
+        self.lock().id
+    }
+
+    fn replay(&self, commit: &dyn Any) -> bool {
+        match commit.downcast_ref::<Commit<State>>() {
+            Some(commit) => {
+                let mut var = self.lock();
+                var.pending = Some(commit.clone());
+                var.version += 1;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl<State> Versioned for Mutex<Var<State>> {
+    fn version(&self) -> u64 {
+        self.lock().version
+    }
 }
@@ -0,0 +1,92 @@
+use crate::{Commit, WatchBuffer};
+use futures::task::AtomicWaker;
+use parking_lot::Mutex;
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    task::{Context as FutContext, Poll},
+};
+
+/// Buffers the commits to a state variable for a single [`crate::Watch`].
+pub(crate) struct Channel<State> {
+    buffer: Mutex<VecDeque<Commit<State>>>,
+    mode: WatchBuffer,
+    closed: AtomicBool,
+    waker: AtomicWaker,
+}
+
+impl<State> Channel<State> {
+    pub fn new(mode: WatchBuffer) -> Arc<Self> {
+        Arc::new(Self {
+            buffer: Mutex::new(VecDeque::new()),
+            mode,
+            closed: AtomicBool::new(false),
+            waker: AtomicWaker::new(),
+        })
+    }
+
+    fn send(&self, commit: &Commit<State>) {
+        {
+            let mut buffer = self.buffer.lock();
+            match self.mode {
+                WatchBuffer::Latest => buffer.clear(),
+                WatchBuffer::Bounded(capacity) if buffer.len() >= capacity.max(1) => {
+                    tracing::debug!(capacity, "dropping commit for lagging watcher");
+                    return;
+                }
+                WatchBuffer::Bounded(_) => (),
+            }
+            buffer.push_back(commit.clone());
+        }
+        self.waker.wake();
+    }
+
+    fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+        self.waker.wake();
+    }
+
+    pub fn poll_next(&self, cx: &mut FutContext<'_>) -> Poll<Option<Commit<State>>> {
+        self.waker.register(cx.waker());
+        if let Some(commit) = self.buffer.lock().pop_front() {
+            Poll::Ready(Some(commit))
+        } else if self.closed.load(Ordering::Acquire) {
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// The channels watching a state variable.
+pub(crate) struct Watchers<State> {
+    channels: Vec<Arc<Channel<State>>>,
+}
+
+impl<State> Default for Watchers<State> {
+    fn default() -> Self {
+        Self { channels: Vec::new() }
+    }
+}
+
+impl<State> Watchers<State> {
+    pub fn add(&mut self, channel: Arc<Channel<State>>) {
+        self.channels.push(channel);
+    }
+
+    /// Send `commit` to each channel, dropping any which are no longer being
+    /// watched.
+    pub fn send(&mut self, commit: &Commit<State>) {
+        self.channels.retain(|channel| Arc::strong_count(channel) > 1);
+        for channel in &self.channels {
+            channel.send(commit);
+        }
+    }
+
+    pub fn close(&mut self) {
+        self.channels.drain(..).for_each(|channel| channel.close());
+    }
+}
@@ -0,0 +1,201 @@
+//! Thread-safe versions of the topologically nested functions at the crate
+//! root, for use within a [`crate::runtime::SendRuntime`].
+//!
+//! Each function here behaves like its namesake at the crate root but requires
+//! cached values, state, and loaded futures to be `Send` (and `Sync` where
+//! they're shared through a [`Commit`] or [`Key`]).
+//!
+//! # Example
+//!
+//! ```
+//! use moxie::{runtime::SendRuntime, sync::cache};
+//! use std::sync::atomic::{AtomicU64, Ordering};
+//!
+//! let epoch = AtomicU64::new(0);
+//! let num_created = AtomicU64::new(0);
+//!
+//! let mut rt = SendRuntime::new();
+//! let mut root = || {
+//!     cache(&epoch.load(Ordering::Relaxed), |_| num_created.fetch_add(1, Ordering::Relaxed));
+//! };
+//!
+//! rt.run_once(&mut root);
+//! rt.run_once(&mut root);
+//! assert_eq!(num_created.load(Ordering::Relaxed), 1);
+//!
+//! epoch.store(1, Ordering::Relaxed);
+//! rt.run_once(&mut root);
+//! assert_eq!(num_created.load(Ordering::Relaxed), 2);
+//! ```
+
+use crate::{runtime::SendContext, Commit, Key, Observed, Sources};
+use std::{borrow::Borrow, future::Future, sync::Arc, task::Poll};
+use topo::CallId;
+
+/// Cache the return of the `init` function. See [`crate::cache_with`].
+#[topo::nested]
+#[illicit::from_env(rt: &SendContext)]
+pub fn cache_with<Arg, Input, Output, Ret>(
+    arg: &Arg,
+    init: impl FnOnce(&Input) -> Output,
+    with: impl FnOnce(&Output) -> Ret,
+) -> Ret
+where
+    Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+    Input: Borrow<Arg> + Send + 'static,
+    Output: Send + 'static,
+    Ret: Send + 'static,
+{
+    rt.cache.cache_with(&CallId::current(), arg, init, with)
+}
+
+/// Caches `init` once in the current [`topo::CallId`]. Runs `with` on every
+/// [`crate::runtime::Revision`]. See [`crate::once_with`].
+#[topo::nested]
+#[illicit::from_env(rt: &SendContext)]
+pub fn once_with<Output, Ret>(
+    init: impl FnOnce() -> Output,
+    with: impl FnOnce(&Output) -> Ret,
+) -> Ret
+where
+    Output: Send + 'static,
+    Ret: Send + 'static,
+{
+    rt.cache.cache_with(&CallId::current(), &(), |&()| init(), with)
+}
+
+/// Memoizes `init` at this callsite, cloning a cached `Output` if it exists and
+/// `Input` is the same as when the stored value was created. See
+/// [`crate::cache`].
+#[topo::nested]
+#[illicit::from_env(rt: &SendContext)]
+pub fn cache<Arg, Input, Output>(arg: &Arg, init: impl FnOnce(&Input) -> Output) -> Output
+where
+    Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+    Input: Borrow<Arg> + Send + 'static,
+    Output: Clone + Send + 'static,
+{
+    rt.cache.cache(&CallId::current(), arg, init)
+}
+
+/// Runs `init` once per [`topo::CallId`]. See [`crate::once`].
+#[topo::nested]
+#[illicit::from_env(rt: &SendContext)]
+pub fn once<Output>(init: impl FnOnce() -> Output) -> Output
+where
+    Output: Clone + Send + 'static,
+{
+    rt.cache.cache(&CallId::current(), &(), |()| init())
+}
+
+/// Root a state variable at this callsite, returning a [`Key`] to the state
+/// variable. See [`crate::state`].
+#[topo::nested]
+#[illicit::from_env(rt: &SendContext)]
+pub fn state<Output>(init: impl FnOnce() -> Output) -> (Commit<Output>, Key<Output>)
+where
+    Output: Send + Sync + 'static,
+{
+    rt.cache_state(&CallId::current(), &(), |_| init())
+}
+
+/// Root a state variable at this callsite, returning a [`Key`] to the state
+/// variable. Re-initializes the state variable if the capture `arg` changes.
+/// See [`crate::cache_state`].
+#[topo::nested]
+#[illicit::from_env(rt: &SendContext)]
+pub fn cache_state<Arg, Input, Output>(
+    arg: &Arg,
+    init: impl FnOnce(&Input) -> Output,
+) -> (Commit<Output>, Key<Output>)
+where
+    Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+    Input: Borrow<Arg> + Send + 'static,
+    Output: Send + Sync + 'static,
+{
+    rt.cache_state(&CallId::current(), arg, init)
+}
+
+/// Derive a value from the latest commits to one or more state variables,
+/// recomputing it only when one of them has received a new commit. See
+/// [`crate::derived`].
+#[topo::nested]
+#[illicit::from_env(rt: &SendContext)]
+pub fn derived<S, Output>(sources: &S, derive: impl FnOnce(&S::Commits) -> Output) -> Commit<Output>
+where
+    S: Sources + 'static,
+    S::Commits: Send,
+    Output: Send + Sync + 'static,
+{
+    let id = CallId::current();
+    let observed = Observed::<S>(sources.latest());
+    rt.cache.cache(&id, &observed, |observed| Commit { id, inner: Arc::new(derive(&observed.0)) })
+}
+
+/// Load a value from the future returned by `init` whenever `capture` changes,
+/// returning the result of calling `with` with the loaded value. Cancels the
+/// running future after any revision during which this call was not made. See
+/// [`crate::load_with`].
+#[topo::nested]
+#[illicit::from_env(rt: &SendContext)]
+pub fn load_with<Arg, Input, Fut, Output, Ret>(
+    arg: &Arg,
+    init: impl FnOnce(&Input) -> Fut,
+    with: impl FnOnce(&Output) -> Ret,
+) -> Poll<Ret>
+where
+    Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+    Input: Borrow<Arg> + Send + 'static,
+    Fut: Future<Output = Output> + Send + 'static,
+    Output: Send + Sync + 'static,
+    Ret: Send + 'static,
+{
+    rt.load_with(&CallId::current(), arg, init, with)
+}
+
+/// Calls [`load_with`] but never re-initializes the loading future. See
+/// [`crate::load_once_with`].
+#[topo::nested]
+#[illicit::from_env(rt: &SendContext)]
+pub fn load_once_with<Fut, Output, Ret>(
+    init: impl FnOnce() -> Fut,
+    with: impl FnOnce(&Output) -> Ret,
+) -> Poll<Ret>
+where
+    Fut: Future<Output = Output> + Send + 'static,
+    Output: Send + Sync + 'static,
+    Ret: Send + 'static,
+{
+    rt.load_with(&CallId::current(), &(), |()| init(), with)
+}
+
+/// Calls [`load_with`], never re-initializes the loading future, and clones the
+/// returned value on each revision once the future has completed and returned.
+/// See [`crate::load_once`].
+#[topo::nested]
+#[illicit::from_env(rt: &SendContext)]
+pub fn load_once<Fut, Output>(init: impl FnOnce() -> Fut) -> Poll<Output>
+where
+    Fut: Future<Output = Output> + Send + 'static,
+    Output: Clone + Send + Sync + 'static,
+{
+    rt.load_with(&CallId::current(), &(), |()| init(), Clone::clone)
+}
+
+/// Load a value from a future, cloning it on subsequent revisions after it is
+/// first returned. Re-initializes the loading future if the capture argument
+/// changes from previous revisions. See [`crate::load`].
+#[topo::nested]
+#[illicit::from_env(rt: &SendContext)]
+pub fn load<Arg, Input, Fut, Output>(
+    capture: &Arg,
+    init: impl FnOnce(&Input) -> Fut,
+) -> Poll<Output>
+where
+    Arg: PartialEq<Input> + ToOwned<Owned = Input> + ?Sized,
+    Input: Borrow<Arg> + Send + 'static,
+    Fut: Future<Output = Output> + Send + 'static,
+    Output: Clone + Send + Sync + 'static,
+{
+    rt.load_with(&CallId::current(), capture, init, Clone::clone)
+}
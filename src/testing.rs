@@ -1,9 +1,24 @@
 //! Utilities for testing moxie-based programs.
 
-use futures::task::ArcWake;
-use std::sync::{
-    atomic::{AtomicBool, AtomicU64, Ordering},
-    Arc,
+use crate::runtime::{LoadLog, Revision, Runtime, Timer};
+use futures::{
+    executor::LocalPool,
+    future::{FutureExt, LocalBoxFuture},
+    task::{waker, ArcWake},
+};
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    future::Future,
+    panic::Location,
+    pin::Pin,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll, Waker},
+    time::Duration,
 };
 
 /// A value which keeps track of how many times it's been cloned. Useful for
@@ -46,3 +61,331 @@ impl ArcWake for BoolWaker {
         arc_self.0.store(true, Ordering::Relaxed);
     }
 }
+
+/// A [`Runtime`] bundled with a single-threaded executor for its loads, a
+/// manually-advanced [`Clock`] which is also used as its [`Timer`], and a
+/// record of its state change wakeups.
+/// Useful for stepping through revisions deterministically.
+///
+/// # Example
+///
+/// ```
+/// use moxie::{load_once, testing::TestRuntime};
+/// use std::{task::Poll, time::Duration};
+///
+/// let mut rt = TestRuntime::new();
+/// let clock = rt.clock();
+/// let root = || {
+///     load_once(|| {
+///         let sleep = clock.sleep(Duration::from_secs(1));
+///         async move {
+///             sleep.await;
+///             "done"
+///         }
+///     })
+/// };
+///
+/// assert_eq!(rt.run_once(root), Poll::Pending);
+/// rt.take_wakes();
+/// rt.run_until_stalled();
+/// assert_eq!(rt.run_once(root), Poll::Pending, "the clock hasn't moved");
+/// assert!(rt.loads()[0].is_pending());
+///
+/// rt.advance(Duration::from_secs(1));
+/// assert_eq!(rt.take_wakes(), 1, "the loader's commit woke the runtime");
+/// assert_eq!(rt.run_once(root), Poll::Ready("done"));
+/// assert!(rt.loads()[0].is_ready());
+/// ```
+pub struct TestRuntime {
+    rt: Runtime,
+    pool: LocalPool,
+    clock: Clock,
+    wakes: Arc<CountsWakes>,
+    load_log: LoadLog,
+    loads: Vec<LoadCall>,
+}
+
+impl Default for TestRuntime {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TestRuntime {
+    /// Returns a new runtime with its clock at zero.
+    pub fn new() -> Self {
+        let pool = LocalPool::new();
+        let wakes = Arc::new(CountsWakes::default());
+        let load_log = LoadLog::default();
+
+        let clock = Clock::default();
+
+        let mut rt = Runtime::new();
+        rt.set_task_executor(pool.spawner());
+        rt.set_state_change_waker(waker(wakes.clone()));
+        rt.set_load_log(load_log.clone());
+        rt.set_timer(clock.clone());
+
+        Self { rt, pool, clock, wakes, load_log, loads: vec![] }
+    }
+
+    /// Runs a single revision. Tasks spawned during the revision don't run
+    /// until [`TestRuntime::run_until_stalled`] or [`TestRuntime::advance`]
+    /// is called.
+    pub fn run_once<Out>(&mut self, op: impl FnOnce() -> Out) -> Out {
+        let ret = self.rt.run_once(op);
+        self.loads = self.load_log.take();
+        ret
+    }
+
+    /// The current revision of the runtime.
+    pub fn revision(&self) -> Revision {
+        self.rt.revision()
+    }
+
+    /// Runs spawned tasks until none of them can make progress without the
+    /// clock advancing or some other external event.
+    pub fn run_until_stalled(&mut self) {
+        self.pool.run_until_stalled();
+    }
+
+    /// Advances the clock, waking any sleeps which have elapsed, then runs
+    /// spawned tasks until they stall.
+    pub fn advance(&mut self, by: Duration) {
+        self.clock.advance(by);
+        self.run_until_stalled();
+    }
+
+    /// Returns a handle to the runtime's clock.
+    pub fn clock(&self) -> Clock {
+        self.clock.clone()
+    }
+
+    /// Returns the number of times the runtime's state change waker has been
+    /// called since the last call to this method.
+    pub fn take_wakes(&self) -> usize {
+        self.wakes.0.swap(0, Ordering::Relaxed)
+    }
+
+    /// Returns each load made during the most recent revision, in the order
+    /// they were called.
+    pub fn loads(&self) -> &[LoadCall] {
+        &self.loads
+    }
+
+    /// Returns the wrapped runtime.
+    pub fn runtime(&mut self) -> &mut Runtime {
+        &mut self.rt
+    }
+}
+
+/// The result of a single call to [`crate::load_with`] or one of its variants
+/// during a [`TestRuntime`] revision.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct LoadCall {
+    /// The callsite of the load.
+    pub id: topo::CallId,
+    /// Whether the load returned `Poll::Ready`.
+    pub is_ready: bool,
+}
+
+impl LoadCall {
+    /// The source location of the load.
+    pub fn location(&self) -> &'static Location<'static> {
+        self.id.location()
+    }
+
+    /// Returns true if the load returned `Poll::Ready`.
+    pub fn is_ready(&self) -> bool {
+        self.is_ready
+    }
+
+    /// Returns true if the load returned `Poll::Pending`.
+    pub fn is_pending(&self) -> bool {
+        !self.is_ready
+    }
+}
+
+#[derive(Default)]
+struct CountsWakes(AtomicUsize);
+
+impl ArcWake for CountsWakes {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// A clock which only moves when advanced by its [`TestRuntime`].
+#[derive(Clone, Debug, Default)]
+pub struct Clock {
+    inner: Rc<RefCell<ClockInner>>,
+}
+
+#[derive(Debug, Default)]
+struct ClockInner {
+    now: Duration,
+    next_sleep: u64,
+    /// The deadline and latest waker of each pending [`Sleep`], by id.
+    sleeping: BTreeMap<u64, (Duration, Waker)>,
+}
+
+impl Clock {
+    /// Returns the time elapsed since the clock was created.
+    pub fn now(&self) -> Duration {
+        self.inner.borrow().now
+    }
+
+    /// Returns a future which completes once the clock has advanced by
+    /// `duration`.
+    pub fn sleep(&self, duration: Duration) -> Sleep {
+        Sleep { clock: self.clone(), deadline: self.now() + duration, id: None }
+    }
+
+    /// Returns the number of sleeps which have been polled and are waiting
+    /// for the clock to advance.
+    pub fn pending_sleeps(&self) -> usize {
+        self.inner.borrow().sleeping.len()
+    }
+
+    fn advance(&self, by: Duration) {
+        let mut elapsed = vec![];
+        {
+            let mut inner = self.inner.borrow_mut();
+            inner.now += by;
+            let now = inner.now;
+            let ids: Vec<u64> = inner
+                .sleeping
+                .iter()
+                .filter(|(_, (deadline, _))| *deadline <= now)
+                .map(|(id, _)| *id)
+                .collect();
+            for id in ids {
+                elapsed.extend(inner.sleeping.remove(&id).map(|(_, waker)| waker));
+            }
+        }
+        elapsed.into_iter().for_each(Waker::wake);
+    }
+}
+
+impl Timer for Clock {
+    fn now(&self) -> Duration {
+        Clock::now(self)
+    }
+
+    fn sleep(&self, duration: Duration) -> LocalBoxFuture<'static, ()> {
+        Clock::sleep(self, duration).boxed_local()
+    }
+}
+
+/// A future returned by [`Clock::sleep`].
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled"]
+pub struct Sleep {
+    clock: Clock,
+    deadline: Duration,
+    /// This sleep's entry in the clock, once it has been polled.
+    id: Option<u64>,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = &mut *self;
+        let mut clock = this.clock.inner.borrow_mut();
+        if clock.now >= this.deadline {
+            if let Some(id) = this.id.take() {
+                clock.sleeping.remove(&id);
+            }
+            return Poll::Ready(());
+        }
+
+        let id = *this.id.get_or_insert_with(|| {
+            clock.next_sleep += 1;
+            clock.next_sleep
+        });
+        clock.sleeping.insert(id, (this.deadline, cx.waker().clone()));
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            self.clock.inner.borrow_mut().sleeping.remove(&id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{load, state};
+
+    #[test]
+    fn loads_advance_with_the_clock() {
+        let mut rt = TestRuntime::new();
+        let clock = rt.clock();
+        let root = |delay: u64| {
+            let clock = clock.clone();
+            load(&delay, move |&delay| clock.sleep(Duration::from_millis(delay)))
+        };
+
+        assert_eq!(rt.run_once(|| root(10)), Poll::Pending);
+        assert_eq!(rt.take_wakes(), 1, "starting the load marks it pending");
+        rt.advance(Duration::from_millis(5));
+        assert_eq!(rt.run_once(|| root(10)), Poll::Pending);
+        assert_eq!(rt.take_wakes(), 0);
+
+        rt.advance(Duration::from_millis(5));
+        assert_eq!(rt.take_wakes(), 1);
+        assert_eq!(rt.run_once(|| root(10)), Poll::Ready(()));
+        assert_eq!(rt.clock().now(), Duration::from_millis(10));
+
+        assert_eq!(rt.run_once(|| root(20)), Poll::Pending, "new input restarts the load");
+        assert_eq!(rt.loads().len(), 1);
+        assert_eq!(rt.loads()[0].location().file(), file!());
+        assert_eq!(rt.revision(), Revision(4));
+    }
+
+    #[test]
+    fn state_commits_wake_the_runtime() {
+        let mut rt = TestRuntime::new();
+        let (_, key) = rt.run_once(|| state(|| 0));
+        key.set(1);
+        key.set(2);
+        assert_eq!(rt.take_wakes(), 2);
+        assert_eq!(rt.take_wakes(), 0);
+        assert!(rt.loads().is_empty());
+    }
+
+    #[test]
+    fn repolled_sleeps_are_woken_once() {
+        let clock = Clock::default();
+        let mut sleep = clock.sleep(Duration::from_millis(10));
+        let wakes = Arc::new(CountsWakes::default());
+        let waker = waker(wakes.clone());
+        let mut cx = Context::from_waker(&waker);
+
+        for _ in 0..3 {
+            assert_eq!(Pin::new(&mut sleep).poll(&mut cx), Poll::Pending);
+        }
+        assert_eq!(clock.pending_sleeps(), 1);
+
+        clock.advance(Duration::from_millis(10));
+        assert_eq!(wakes.0.load(Ordering::Relaxed), 1);
+        assert_eq!(Pin::new(&mut sleep).poll(&mut cx), Poll::Ready(()));
+    }
+
+    #[test]
+    fn dropped_sleeps_leave_the_clock() {
+        let clock = Clock::default();
+        let mut sleep = clock.sleep(Duration::from_millis(10));
+        let waker = futures::task::noop_waker();
+        let mut cx = Context::from_waker(&waker);
+        assert_eq!(Pin::new(&mut sleep).poll(&mut cx), Poll::Pending);
+
+        drop(sleep);
+        assert_eq!(clock.pending_sleeps(), 0);
+    }
+}
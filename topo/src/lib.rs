@@ -133,7 +133,12 @@
 pub use topo_macro::nested;
 
 use slot::{OpaqueSlot, Slot};
-use std::{borrow::Borrow, cell::RefCell, hash::Hash, panic::Location};
+use std::{
+    borrow::Borrow,
+    cell::RefCell,
+    hash::{Hash, Hasher},
+    panic::Location,
+};
 
 mod slot;
 
@@ -419,6 +424,17 @@ impl CallId {
         Scope::with_current(|current| current.id)
     }
 
+    /// Returns the source location of the call which created this `CallId`.
+    ///
+    /// ```
+    /// let (id, line) = topo::call(|| (topo::CallId::current(), line!()));
+    /// assert_eq!(id.location().file(), file!());
+    /// assert_eq!(id.location().line(), line);
+    /// ```
+    pub fn location(&self) -> &'static Location<'static> {
+        self.callsite.location
+    }
+
     pub(crate) fn child<Q, S>(&self, callsite: Callsite, slot: &Q) -> Self
     where
         Q: Eq + Hash + ToOwned<Owned = S> + ?Sized,
@@ -429,9 +445,9 @@ impl CallId {
 }
 
 /// A value unique to the source location where it is created.
-#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[derive(Clone, Copy, Debug)]
 struct Callsite {
-    location: usize,
+    location: &'static Location<'static>,
 }
 
 impl Callsite {
@@ -454,14 +470,31 @@ impl Callsite {
             }
         })
     }
+
+    /// The pointer value for a given location is enough to differentiate it
+    /// from all others.
+    fn addr(self) -> usize {
+        self.location as *const _ as usize
+    }
 }
 
 impl From<&'static Location<'static>> for Callsite {
     fn from(location: &'static Location<'static>) -> Self {
-        Self {
-            // the pointer value for a given location is enough to differentiate it from all others
-            location: location as *const _ as usize,
-        }
+        Self { location }
+    }
+}
+
+impl PartialEq for Callsite {
+    fn eq(&self, other: &Self) -> bool {
+        self.addr() == other.addr()
+    }
+}
+
+impl Eq for Callsite {}
+
+impl Hash for Callsite {
+    fn hash<H: Hasher>(&self, hasher: &mut H) {
+        self.addr().hash(hasher);
     }
 }
 